@@ -6,9 +6,25 @@ function! LSP#filename() abort
     if !l:filename
         let l:filename = expand('%:p')
     endif
+    if empty(l:filename) && get(g:, 'LanguageClient_untitledBuffers', 0)
+        let l:bufnr = expand('<abuf>') ? str2nr(expand('<abuf>')) : bufnr('%')
+        if empty(bufname(l:bufnr)) && empty(getbufvar(l:bufnr, '&buftype'))
+            let l:filename = 'untitled:Untitled-' . l:bufnr
+        endif
+    endif
     return l:filename
 endfunction
 
+" Number of the buffer with the given name, as returned by LSP#filename(),
+" including the untitled: names given to unnamed buffers.
+function! LSP#bufnr(filename) abort
+    let l:bufnr = matchstr(a:filename, '^untitled:Untitled-\zs\d\+$')
+    if !empty(l:bufnr)
+        return str2nr(l:bufnr)
+    endif
+    return bufnr(a:filename)
+endfunction
+
 " This function will return buffer text as required by LSP.
 "
 " The main difference with getbufline is that it checks fixendofline settings
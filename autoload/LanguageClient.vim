@@ -14,10 +14,21 @@ let s:POPUP_WINDOW_AVAILABLE = exists('*popup_atcursor')
 " timers to control throttling
 let s:timers = {}
 
+" ids of the popups opened, closed together by s:ClosePopups() unless pinned
+let s:popup_win_ids = []
+
 if !hlexists('LanguageClientCodeLens')
   hi link LanguageClientCodeLens Title
 endif
 
+if !hlexists('LanguageClientHoverRange')
+  hi link LanguageClientHoverRange Visual
+endif
+
+if !hlexists('LanguageClientLinkedEdit')
+  hi link LanguageClientLinkedEdit Visual
+endif
+
 if !hlexists('LanguageClientWarningSign')
   hi link LanguageClientWarningSign todo
 endif
@@ -51,27 +62,6 @@ function! s:Echo(message) abort
     echo a:message
 endfunction
 
-function! s:Ellipsis(message) abort
-    let l:maxlen = &columns * &cmdheight - 2
-    if &showcmd
-        let maxlen -= 11
-    endif
-    if &ruler
-        let maxlen -= 18
-    endif
-    if len(a:message) < l:maxlen
-        let l:message = a:message
-    else
-        let l:message = a:message[:l:maxlen - 3] . '...'
-    endif
-    return l:message
-endfunction
-
-" `echo` message without trigger |hit-enter|
-function! s:EchoEllipsis(message) abort
-    echo s:Ellipsis(a:message)
-endfunction
-
 " `echomsg` message without trigger |hit-enter|
 function! s:EchomsgEllipsis(message) abort
     " Credit: ALE, snippets from ale#cursor#TruncatedEcho()
@@ -141,6 +131,10 @@ function! s:hasSnippetSupport() abort
         return g:LanguageClient_hasSnippetSupport !=# 0
     endif
 
+    if exists('g:LanguageClient_snippetEngine')
+        return 1
+    endif
+
     " https://github.com/Shougo/neosnippet.vim
     if exists('g:loaded_neosnippet')
         return 1
@@ -149,6 +143,34 @@ function! s:hasSnippetSupport() abort
     return 0
 endfunction
 
+" Replace the items of the quickfix/location list with the given id, or create a
+" new list if it no longer exists. Returns the id of the populated list.
+function! s:SetListById(loclist, list, title, id) abort
+    let l:what = {'items': a:list, 'title': a:title}
+    if a:loclist
+        let l:exists = a:id != 0 && getloclist(0, {'id': a:id}).id == a:id
+        call setloclist(0, [], l:exists ? 'r' : ' ', l:exists ? extend(l:what, {'id': a:id}) : l:what)
+        return l:exists ? a:id : getloclist(0, {'id': 0}).id
+    endif
+
+    let l:exists = a:id != 0 && getqflist({'id': a:id}).id == a:id
+    call setqflist([], l:exists ? 'r' : ' ', l:exists ? extend(l:what, {'id': a:id}) : l:what)
+    return l:exists ? a:id : getqflist({'id': 0}).id
+endfunction
+
+" Expand a snippet body at the cursor position with the configured snippet engine.
+function! s:ExpandSnippet(engine, snippet) abort
+    if a:engine ==? 'UltiSnips'
+        call UltiSnips#Anon(a:snippet)
+    elseif a:engine ==? 'Vsnip'
+        call vsnip#anonymous(a:snippet)
+    elseif a:engine ==? 'Neosnippet'
+        call neosnippet#anonymous(a:snippet)
+    else
+        call s:Echoerr('Unknown snippet engine: ' . a:engine)
+    endif
+endfunction
+
 function! s:getSelectionUI() abort
 	if type(get(g:, 'LanguageClient_selectionUI', v:null)) is s:TYPE.funcref
 		return 'funcref'
@@ -157,6 +179,14 @@ function! s:getSelectionUI() abort
 	endif
 endfunction
 
+function! s:getSettingsPath() abort
+    let l:path = get(g:, 'LanguageClient_settingsPath', '.vim/settings.json')
+    if type(l:path) == v:t_dict
+        return map(copy(l:path), "map(s:ToList(v:val), 'expand(v:val)')")
+    endif
+    return map(s:ToList(l:path), 'expand(v:val)')
+endfunction
+
 function! s:useVirtualText() abort
     let l:use = s:GetVar('LanguageClient_useVirtualText')
     if l:use isnot v:null
@@ -191,7 +221,7 @@ endfunction
 
 " Clear and set virtual texts between line_start and line_end (exclusive).
 function! s:set_virtual_texts(buf_id, ns_id, line_start, line_end, virtual_texts) abort
-    " VirtualText: map with keys line, text and hl_group.
+    " VirtualText: map with keys line, text, hl_group, position and spacing.
 
     let l:prefix = s:GetVar('LanguageClient_virtualTextPrefix')
     if l:prefix is v:null
@@ -205,16 +235,31 @@ function! s:set_virtual_texts(buf_id, ns_id, line_start, line_end, virtual_texts
     call nvim_buf_clear_namespace(a:buf_id, a:ns_id, a:line_start, a:line_end)
 
     for vt in a:virtual_texts
-        call nvim_buf_set_virtual_text(a:buf_id, a:ns_id, vt['line'], [[l:prefix . vt['text'], vt['hl_group']]], {})
+        let l:chunks = [[l:prefix . vt['text'], vt['hl_group']]]
+        if get(vt, 'spacing', 0) > 0
+            call insert(l:chunks, [repeat(' ', vt['spacing'])])
+        endif
+
+        let l:position = get(vt, 'position', 'eol')
+        if l:position !=# 'eol' && exists('*nvim_buf_set_extmark')
+            call nvim_buf_set_extmark(a:buf_id, a:ns_id, vt['line'], 0, {
+                        \ 'virt_text': l:chunks,
+                        \ 'virt_text_pos': l:position,
+                        \ })
+        else
+            call nvim_buf_set_virtual_text(a:buf_id, a:ns_id, vt['line'], l:chunks, {})
+        endif
     endfor
 endfunction
 
-function! s:place_sign(id, name, file, line) abort
+function! s:place_sign(id, name, file, line, priority) abort
   if !exists('*sign_place')
+    " Sign priorities came with sign_place(), so they are left out here.
     execute 'sign place id=' . a:id . ' name=' . a:name . ' file=' . a:file . ' line=' . a:line
+    return
   endif
 
-  call sign_place(0, 'LanguageClientNeovim', a:name, a:file, { 'lnum': a:line })
+  call sign_place(0, 'LanguageClientNeovim', a:name, a:file, { 'lnum': a:line, 'priority': a:priority })
 endfunction
 
 " clears all signs on the buffer with the given name
@@ -234,7 +279,8 @@ function! s:set_signs(file, signs) abort
     let l:line = l:sign['line'] + 1
     let l:name = l:sign['name']
     let l:id = l:sign['id']
-    call s:place_sign(l:id, l:name, a:file, l:line)
+    let l:priority = l:sign['priority']
+    call s:place_sign(l:id, l:name, a:file, l:line, l:priority)
   endfor
 endfunction
 
@@ -271,6 +317,13 @@ function! s:selectionUI_funcref(source, sink) abort
     endif
 endfunction
 
+" Passes the code actions, with a preview of their edits, to the funcref in
+" g:LanguageClient_selectionUI_codeActions, along with the sink to call with
+" the index of the action picked.
+function! s:selectionUI_codeActions(actions) abort
+    call call(g:LanguageClient_selectionUI_codeActions, [a:actions, function('LanguageClient_codeActionSink')])
+endfunction
+
 function! s:FZF(source, sink) abort
     if !get(g:, 'loaded_fzf')
         call s:Echoerr('FZF not loaded!')
@@ -297,7 +350,7 @@ endfunction
 
 function! s:Edit(action, path) abort
     " If editing current file, push current location to jump list.
-    let l:bufnr = bufnr(a:path)
+    let l:bufnr = LSP#bufnr(a:path)
     if l:bufnr == bufnr('%')
         execute 'normal! m`'
         return
@@ -311,9 +364,43 @@ function! s:Edit(action, path) abort
         return
     endif
 
+    " Unnamed buffers can only be opened by number.
+    if l:bufnr != -1 && a:path =~# '^untitled:'
+        execute l:action
+        execute 'buffer' l:bufnr
+        return
+    endif
+
     execute l:action . ' ' . fnameescape(a:path)
 endfunction
 
+" Open a URL the way |gx| does, which can be configured with
+" g:netrw_browsex_viewer.
+function! s:OpenBrowser(url) abort
+    if !exists('g:loaded_netrwPlugin')
+        call s:Echowarn('Cannot open ' . a:url . ', netrw is not loaded')
+        return
+    endif
+
+    call netrw#BrowseX(a:url, 0)
+endfunction
+
+" Visually select a LSP range, whose end position is exclusive.
+function! s:SelectRange(range) abort
+    let l:start = [a:range.start.line + 1, a:range.start.character + 1]
+    let l:end = [a:range.end.line + 1, a:range.end.character]
+    if l:end[1] == 0 && l:end[0] > l:start[0]
+        " Range ends at the beginning of a line, select up to the end of the
+        " previous one instead.
+        let l:end[0] -= 1
+        let l:end[1] = len(getline(l:end[0]))
+    endif
+
+    call cursor(l:start)
+    normal! v
+    call cursor(l:end[0], max([1, l:end[1]]))
+endfunction
+
 " Batch version of `matchdelete()`.
 function! s:MatchDelete(ids) abort
     for l:id in a:ids
@@ -336,6 +423,19 @@ function! s:ApplySemanticHighlights(bufnr, ns_id, clears, highlights) abort
     endfor
 endfunction
 
+function! s:ClearSemanticHighlights(ns_id) abort
+    " TODO: implement this for vim8
+    if !has('nvim')
+      return
+    endif
+
+    for l:bufnr in range(1, bufnr('$'))
+        if bufloaded(l:bufnr)
+            call nvim_buf_clear_namespace(l:bufnr, a:ns_id, 0, -1)
+        endif
+    endfor
+endfunction
+
 " Batch version of nvim_buf_add_highlight
 function! s:AddHighlights(namespace, highlights) abort
   if has('nvim')
@@ -501,6 +601,10 @@ function! s:OpenHoverPreview(bufname, lines, filetype, ...) abort
         let l:topBottom = repeat([''], l:hoverMarginSize)
         let lines = l:topBottom + lines + l:topBottom
         let height = len(lines)
+        let l:maxHeight = s:GetVar('LanguageClient_hoverMaxHeight', v:null)
+        if l:maxHeight isnot v:null
+            let height = min([height, l:maxHeight])
+        endif
 
         " Calculate anchor
         " Prefer North, but if there is no space, fallback into South
@@ -523,8 +627,12 @@ function! s:OpenHoverPreview(bufname, lines, filetype, ...) abort
         endif
 
         let relative = 'cursor'
-        let col = get(a:000, 0, col)
-        let row = get(a:000, 1, row)
+        if get(a:000, 0, v:null) isnot v:null
+            let col = a:1
+        endif
+        if get(a:000, 1, v:null) isnot v:null
+            let row = a:2
+        endif
         if get(a:000, 0, v:null) isnot v:null && get(a:000, 1, v:null) isnot v:null
           let relative = 'win'
         endif
@@ -545,17 +653,25 @@ function! s:OpenHoverPreview(bufname, lines, filetype, ...) abort
         execute printf('setlocal winhl=%s', float_win_highlight)
     elseif display_approach ==# 'popup_win'
         let l:padding = [1, 1, 1, 1]
+        let l:options = { 'padding': l:padding }
+        let l:maxHeight = s:GetVar('LanguageClient_hoverMaxHeight', v:null)
+        if l:maxHeight isnot v:null
+            let l:options['maxheight'] = l:maxHeight
+            let l:options['scrollbar'] = 1
+        endif
         if get(a:000, 0, v:null) isnot v:null && get(a:000, 1, v:null) isnot v:null
-          let pop_win_id = popup_create(a:lines, {
+          let pop_win_id = popup_create(a:lines, extend({
                 \ 'line': get(a:000, 1) + 1,
                 \ 'col': get(a:000, 0) + 1,
-                \ 'padding': l:padding,
                 \ 'moved': 'any'
-                \ })
+                \ }, l:options))
         else
-          let pop_win_id = popup_atcursor(a:lines, { 'padding': l:padding })
+          let pop_win_id = popup_atcursor(a:lines, l:options)
         endif
+        let s:popup_win_id = pop_win_id
+        call add(s:popup_win_ids, pop_win_id)
         call setbufvar(winbufnr(pop_win_id), '&filetype', a:filetype)
+        call win_execute(pop_win_id, 'call s:HighlightCodeRegions(' . string(get(a:000, 2, [])) . ')')
         " trigger refresh on plasticboy/vim-markdown
         call win_execute(pop_win_id, 'doautocmd InsertLeave')
     elseif display_approach ==# 'preview'
@@ -573,10 +689,20 @@ function! s:OpenHoverPreview(bufname, lines, filetype, ...) abort
         endif
 
         call setline(1, lines)
+        if display_approach ==# 'float_win'
+            call s:HighlightCodeRegions(get(a:000, 2, []), l:hoverMarginSize)
+        else
+            call s:HighlightCodeRegions(get(a:000, 2, []))
+        endif
         " trigger refresh on plasticboy/vim-markdown
         doautocmd InsertLeave
         setlocal nomodified nomodifiable
 
+        let l:maxHeight = s:GetVar('LanguageClient_hoverMaxHeight', v:null)
+        if display_approach ==# 'preview' && l:maxHeight isnot v:null
+            execute 'resize' min([len(lines), l:maxHeight])
+        endif
+
         wincmd p
     endif
 
@@ -592,6 +718,146 @@ function! s:OpenHoverPreview(bufname, lines, filetype, ...) abort
     endif
 endfunction
 
+" Highlights the code regions of the current buffer with the syntax of their filetype, and hides
+" the fences around them. Lines of the regions are shifted by the top margin, if any.
+function! s:HighlightCodeRegions(regions, ...) abort
+    if empty(a:regions)
+        return
+    endif
+
+    let l:offset = get(a:000, 0, 0)
+    for l:region in a:regions
+        let l:cluster = '@LanguageClientCode_' . substitute(l:region['filetype'], '\W', '_', 'g')
+        if empty(globpath(&runtimepath, 'syntax/' . l:region['filetype'] . '.vim'))
+            continue
+        endif
+
+        let l:current_syntax = get(b:, 'current_syntax', v:null)
+        unlet! b:current_syntax
+        execute 'silent! syntax include' l:cluster 'syntax/' . l:region['filetype'] . '.vim'
+        if l:current_syntax isnot v:null
+            let b:current_syntax = l:current_syntax
+        endif
+
+        execute printf('syntax region LanguageClientCodeRegion start=/\%%%dl/ end=/\%%%dl$/ keepend contains=%s',
+                    \ l:region['start'] + l:offset, l:region['end'] + l:offset, l:cluster)
+    endfor
+
+    syntax match LanguageClientCodeFence /^\s*```.*$/ conceal
+    setlocal conceallevel=2 concealcursor=nvic
+endfunction
+
+" Highlights the range of the hover being shown, until the cursor moves or the buffer is left.
+function! s:HighlightHoverRange(highlights) abort
+    call s:SetHighlights(a:highlights, '__LCN_HOVER_RANGE__')
+    augroup LanguageClient_hoverRange
+        autocmd!
+        execute 'autocmd CursorMoved,CursorMovedI,InsertEnter <buffer> call s:ClearHoverRange(' . string(getpos('.')) . ')'
+        autocmd BufLeave <buffer> call s:ClearHoverRange(v:null)
+    augroup END
+endfunction
+
+function! s:ClearHoverRange(opened) abort
+    " CursorMoved is triggered just after the hover is shown, without the cursor moving.
+    if a:opened isnot v:null && getpos('.') == a:opened
+        return
+    endif
+    if exists('#LanguageClient_hoverRange')
+        autocmd! LanguageClient_hoverRange
+        augroup! LanguageClient_hoverRange
+        call s:ClearHighlights('__LCN_HOVER_RANGE__')
+    endif
+endfunction
+
+" Mirror the edits made in insert mode to one of the linked editing ranges, the
+" one at the cursor, to the others once insert mode is left. Ranges are within
+" a single line, with byte columns. The cursor position and the length of its
+" line are those from when insert mode was entered, as text may have been typed
+" since.
+function! s:SetupLinkedEdit(ranges, highlights, insert_enter) abort
+    call s:ClearLinkedEdit()
+    " Replies arriving after insert mode was left, or to the request of an
+    " earlier insert, are dropped.
+    if type(a:insert_enter) isnot s:TYPE.dict
+                \ || a:insert_enter.id != get(b:, 'LanguageClient_linkedEditId', 0)
+                \ || mode() !~# '^[iR]'
+        return
+    endif
+
+    let l:line = a:insert_enter.line
+    let l:col = a:insert_enter.col
+    let l:primary = v:null
+    for l:range in a:ranges
+        if l:range.start.line == l:line && l:range.start.character <= l:col
+                    \ && l:col <= l:range.end.character
+            let l:primary = l:range
+        endif
+    endfor
+    if l:primary is v:null || len(a:ranges) < 2
+        return
+    endif
+
+    let b:LanguageClient_linkedEdit = {
+                \ 'ranges': a:ranges,
+                \ 'primary': l:primary,
+                \ 'length': a:insert_enter.length,
+                \ }
+    call s:SetHighlights(a:highlights, '__LCN_LINKED_EDIT__')
+    augroup LanguageClient_linkedEdit
+        autocmd! * <buffer>
+        autocmd InsertLeave <buffer> call s:ApplyLinkedEdit()
+    augroup END
+endfunction
+
+function! s:ApplyLinkedEdit() abort
+    let l:edit = get(b:, 'LanguageClient_linkedEdit', v:null)
+    call s:ClearLinkedEdit()
+    if l:edit is v:null
+        return
+    endif
+
+    " Edits are assumed to be within the range at the cursor, so the change in
+    " length of its line is the change in length of the range.
+    let l:primary = l:edit.primary
+    let l:delta = len(getline(l:primary.start.line + 1)) - l:edit.length
+    let l:length = l:primary.end.character - l:primary.start.character + l:delta
+    if l:length < 0
+        return
+    endif
+    let l:text = strpart(getline(l:primary.start.line + 1), l:primary.start.character, l:length)
+
+    " Replace from the last range to the first, so that the columns of the
+    " ranges left to replace stay valid.
+    let l:ranges = sort(copy(l:edit.ranges), {a, b ->
+                \ a.start.line != b.start.line ? a.start.line - b.start.line
+                \ : a.start.character - b.start.character})
+    for l:range in reverse(l:ranges)
+        if l:range is l:primary
+            continue
+        endif
+
+        let l:start = l:range.start.character
+        let l:end = l:range.end.character
+        if l:range.start.line == l:primary.start.line && l:start > l:primary.start.character
+            let l:start += l:delta
+            let l:end += l:delta
+        endif
+        let l:current = getline(l:range.start.line + 1)
+        call setline(l:range.start.line + 1,
+                    \ strpart(l:current, 0, l:start) . l:text . strpart(l:current, l:end))
+    endfor
+endfunction
+
+function! s:ClearLinkedEdit() abort
+    if exists('b:LanguageClient_linkedEdit')
+        unlet b:LanguageClient_linkedEdit
+        call s:ClearHighlights('__LCN_LINKED_EDIT__')
+    endif
+    augroup LanguageClient_linkedEdit
+        autocmd! * <buffer>
+    augroup END
+endfunction
+
 function! s:MoveIntoHoverPreview(bufname) abort
     for bufnr in range(1, bufnr('$'))
         if bufname(bufnr) ==# a:bufname
@@ -837,8 +1103,12 @@ function! s:SkipSendingMessage() abort
         return v:false
     endif
 
+    if get(b:, 'LanguageClient_disabled', 0)
+        return v:true
+    endif
+
     let l:has_command = LanguageClient#HasCommand(&filetype)
-    return !l:has_command || &buftype !=# '' || &filetype ==# '' || expand('%') ==# ''
+    return !l:has_command || &buftype !=# '' || &filetype ==# '' || empty(LSP#filename())
 endfunction
 
 function! LanguageClient#HasCommand(filetype) abort
@@ -896,6 +1166,26 @@ function! LanguageClient#Notify(method, params) abort
                 \ }))
 endfunction
 
+function! LanguageClient#textDocument_linkedEditingRange(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    " Each request gets an id, so that only the reply to the latest one is used.
+    let b:LanguageClient_linkedEditId = get(b:, 'LanguageClient_linkedEditId', 0) + 1
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'position': LSP#position(),
+                \ 'insertEnter': {
+                \   'id': b:LanguageClient_linkedEditId,
+                \   'line': line('.') - 1,
+                \   'col': col('.') - 1,
+                \   'length': len(getline('.')),
+                \ },
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('textDocument/linkedEditingRange', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#textDocument_hover(...) abort
     if s:ShouldUseFloatWindow() && s:MoveIntoHoverPreview('__LCNHover__')
         return
@@ -914,6 +1204,59 @@ endfunction
 
 function! LanguageClient#closeFloatingHover() abort
     call s:CloseFloatingHover()
+    " The pinned hovers are tracked by the client, which closes them.
+    call LanguageClient#Notify('languageClient/closePinnedHovers', {})
+endfunction
+
+" Keep the last hover open as a reference instead of closing it when the
+" cursor moves, until it's closed with LanguageClient#closeFloatingHover() or
+" like any other window.
+function! LanguageClient#pinHover() abort
+    if exists('s:float_win_id') && win_id2win(s:float_win_id) != 0
+        autocmd! plugin-LC-neovim-close-hover
+        let l:winid = s:float_win_id
+        unlet s:float_win_id
+    elseif exists('s:popup_win_id') && !empty(popup_getpos(s:popup_win_id))
+        call popup_setoptions(s:popup_win_id, {'moved': [0, 0, 0], 'close': 'button'})
+        call filter(s:popup_win_ids, 'v:val != s:popup_win_id')
+        call LanguageClient#Notify('languageClient/pinHover', {'winid': s:popup_win_id})
+        unlet s:popup_win_id
+        return
+    else
+        let l:winid = 0
+        for l:winnr in range(1, winnr('$'))
+            if getwinvar(l:winnr, '&previewwindow')
+                let l:winid = win_getid(l:winnr)
+                call setwinvar(l:winnr, '&previewwindow', 0)
+            endif
+        endfor
+        if l:winid == 0
+            call s:Echowarn('No hover to pin')
+            return
+        endif
+    endif
+
+    " Hovers are shown in a buffer of a fixed name, which the next hover would
+    " reuse, so the pinned one is renamed.
+    let l:current = win_getid()
+    call win_gotoid(l:winid)
+    execute 'silent file' fnameescape(bufname('%') . '_pinned_' . l:winid)
+    call win_gotoid(l:current)
+    call LanguageClient#Notify('languageClient/pinHover', {'winid': l:winid})
+endfunction
+
+function! s:ClosePinnedHovers(winids) abort
+    for l:winid in a:winids
+        if exists('*popup_getpos') && !empty(popup_getpos(l:winid))
+            call popup_close(l:winid)
+            continue
+        endif
+
+        let l:winnr = win_id2win(l:winid)
+        if l:winnr != 0
+            execute l:winnr . 'wincmd c'
+        endif
+    endfor
 endfunction
 
 " Meta methods to go to various places.
@@ -938,6 +1281,107 @@ function! LanguageClient#textDocument_switchSourceHeader(...) abort
     return LanguageClient#Call('textDocument/switchSourceHeader', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#clangd_gotoDefinition(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'gotoCmd': v:null,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/clangdGotoDefinition', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_openCargoToml(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'gotoCmd': v:null,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('experimental/openCargoToml', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_parentModule(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'gotoCmd': v:null,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('experimental/parentModule', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_joinLines(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('experimental/joinLines', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_ssr(...) abort
+    let l:Callback = get(a:000, 2, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'query': get(a:000, 0, v:null),
+                \ }
+    call extend(l:params, get(a:000, 1, {}))
+    return LanguageClient#Call('experimental/ssr', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_reloadWorkspace(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('rust-analyzer/reloadWorkspace', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_analyzerStatus(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('rust-analyzer/analyzerStatus', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_syntaxTree(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('rust-analyzer/syntaxTree', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#rustAnalyzer_viewHir(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('rust-analyzer/viewHir', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#textDocument_definition(...) abort
     let l:Callback = get(a:000, 1, v:null)
     let l:params = {
@@ -952,20 +1396,59 @@ function! LanguageClient#textDocument_definition(...) abort
     return LanguageClient#Call('textDocument/definition', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#textDocument_declaration(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ 'gotoCmd': v:null,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('textDocument/declaration', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#textDocument_typeDefinition(...) abort
+    let l:Callback = get(a:000, 1, v:null)
     let l:params = {
-                \ 'method': 'textDocument/typeDefinition',
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ 'gotoCmd': v:null,
                 \ }
     call extend(l:params, get(a:000, 0, {}))
-    return call('LanguageClient#findLocations', [l:params] + a:000[1:])
+    return LanguageClient#Call('textDocument/typeDefinition', l:params, l:Callback)
 endfunction
 
 function! LanguageClient#textDocument_implementation(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ 'gotoCmd': v:null,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('textDocument/implementation', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#goToExpressionType(...) abort
+    let l:Callback = get(a:000, 1, v:null)
     let l:params = {
-                \ 'method': 'textDocument/implementation',
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'position': LSP#position(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ 'gotoCmd': v:null,
                 \ }
     call extend(l:params, get(a:000, 0, {}))
-    return call('LanguageClient#findLocations', [l:params] + a:000[1:])
+    return LanguageClient#Call('languageClient/gotoExpressionType', l:params, l:Callback)
 endfunction
 
 function! LanguageClient#textDocument_references(...) abort
@@ -1008,6 +1491,17 @@ function! LanguageClient#textDocument_documentSymbol(...) abort
     return LanguageClient#Call('textDocument/documentSymbol', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#documentSymbolSearch(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'query': v:null,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/documentSymbolSearch', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#workspace_symbol(...) abort
     let l:Callback = get(a:000, 2, v:null)
     let l:params = {
@@ -1020,6 +1514,16 @@ function! LanguageClient#workspace_symbol(...) abort
     return LanguageClient#Call('workspace/symbol', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#workspace_diagnostic(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('workspace/diagnostic', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#textDocument_codeLens(...) abort
     let l:Callback = get(a:000, 1, v:null)
     let l:params = {
@@ -1133,6 +1637,40 @@ function! LanguageClient#textDocument_rangeFormatting_sync(...) abort
     return l:result isnot v:null
 endfunction
 
+function! LanguageClient#textDocument_selectionRange(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'position': LSP#position(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('textDocument/selectionRange', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#selectionRange_expand(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'position': LSP#position(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/selectionRangeExpand', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#selectionRange_shrink(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'position': LSP#position(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/selectionRangeShrink', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#textDocument_didOpen(...) abort
     return LanguageClient#Notify('textDocument/didOpen', {
                 \ 'filename': LSP#filename(),
@@ -1193,6 +1731,16 @@ function! LanguageClient#startServer(...) abort
     return LanguageClient#Call('languageClient/startServer', l:params, funcref('LanguageClient#textDocument_didOpen'))
 endfunction
 
+function! LanguageClient#restartServer(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'languageId': &filetype,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/restartServer', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#registerServerCommands(cmds, ...) abort
     let l:handle = a:0 > 0 ? a:1 : v:null
     return LanguageClient#Call('languageClient/registerServerCommands', a:cmds, l:handle, v:true)
@@ -1205,6 +1753,19 @@ function! LanguageClient#setLoggingLevel(level) abort
     return LanguageClient#Call('languageClient/setLoggingLevel', l:params, v:null)
 endfunction
 
+function! LanguageClient#setTrace(trace) abort
+    " The settings are read again when a server is started, so the servers
+    " started afterwards use the new trace level too.
+    if index(['off', 'messages', 'verbose'], a:trace) >= 0
+        let g:LanguageClient_trace = a:trace
+    endif
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'trace': a:trace,
+                \ }
+    return LanguageClient#Call('languageClient/setTrace', l:params, v:null)
+endfunction
+
 function! LanguageClient#diagnosticsPrevious() abort
     let l:params = {
                 \ 'filename': LSP#filename(),
@@ -1233,9 +1794,14 @@ function! LanguageClient#registerHandlers(handlers, ...) abort
     return LanguageClient#Call('languageClient/registerHandlers', a:handlers, l:handle)
 endfunction
 
-function! s:ExecuteAutocmd(event) abort
+function! s:ExecuteAutocmd(event, ...) abort
     if exists('#User#' . a:event)
-        execute 'doautocmd <nomodeline> User ' . a:event
+        if a:0 > 0 && has('nvim-0.8')
+            " Lua callbacks get the data in the `data` field of their argument.
+            call nvim_exec_autocmds('User', {'pattern': a:event, 'modeline': v:false, 'data': a:1})
+        else
+            execute 'doautocmd <nomodeline> User ' . a:event
+        endif
     endif
 endfunction
 
@@ -1293,7 +1859,7 @@ function! LanguageClient#handleFileType() abort
 endfunction
 
 function! LanguageClient#handleTextChanged() abort
-    if &buftype !=# '' || &filetype ==# '' || expand('%') ==# ''
+    if &buftype !=# '' || &filetype ==# '' || empty(LSP#filename())
         return
     endif
 
@@ -1307,10 +1873,52 @@ function! LanguageClient#handleTextChanged() abort
     endtry
 endfunction
 
+function! LanguageClient#textDocument_willSaveWaitUntil(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('textDocument/willSaveWaitUntil', l:params, l:Callback)
+endfunction
+
+function! s:HandleBufWritePre(params, callback) abort
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': v:true,
+                \ }
+    call extend(l:params, a:params)
+    return LanguageClient#Call('languageClient/handleBufWritePre', l:params, a:callback)
+endfunction
+
+function! LanguageClient#handleBufWritePre() abort
+    " A file is created by the first write of its buffer.
+    let b:LanguageClient_newFile = filereadable(expand('<afile>:p')) ? v:false : v:true
+
+    " The edits must be made before the buffer is written, so the request is synchronous.
+    if !LanguageClient#isServerRunning()
+        return
+    endif
+
+    try
+        call LanguageClient_runSync(function('s:HandleBufWritePre'), {
+                    \ 'newFile': b:LanguageClient_newFile,
+                    \ })
+    catch
+        call s:Debug('LanguageClient caught exception: ' . string(v:exception))
+    endtry
+endfunction
+
 function! LanguageClient#handleBufWritePost() abort
     try
         call LanguageClient#Notify('languageClient/handleBufWritePost', {
                     \ 'filename': LSP#filename(),
+                    \ 'newFile': get(b:, 'LanguageClient_newFile', v:false),
                     \ })
     catch
         call s:Debug('LanguageClient caught exception: ' . string(v:exception))
@@ -1327,6 +1935,29 @@ function! LanguageClient#handleBufDelete() abort
     endtry
 endfunction
 
+function! LanguageClient#handleBufFilePre() abort
+    let b:LanguageClient_previousFilename = LSP#filename()
+endfunction
+
+" An unnamed buffer given a name, e.g. with `:w foo.ts`, is a new document for
+" the server, so the untitled one is closed before the new one is opened.
+function! LanguageClient#handleBufFilePost() abort
+    let l:previous = get(b:, 'LanguageClient_previousFilename', '')
+    unlet! b:LanguageClient_previousFilename
+    if l:previous !~# '^untitled:' || l:previous ==# LSP#filename()
+        return
+    endif
+
+    try
+        call LanguageClient#Notify('languageClient/handleBufDelete', {
+                    \ 'filename': l:previous,
+                    \ })
+    catch
+        call s:Debug('LanguageClient caught exception: ' . string(v:exception))
+    endtry
+    call LanguageClient#handleFileType()
+endfunction
+
 " TODO: Separate CursorMoved and ViewportChanged events. But after separating,
 " there will Mutex poison error.
 let s:last_cursor_line = -1
@@ -1397,6 +2028,12 @@ function! LanguageClient_FZFSinkCommand(selection) abort
                 \ })
 endfunction
 
+function! LanguageClient_codeActionSink(index) abort
+    return LanguageClient#Notify('LanguageClient_codeActionSink', {
+                \ 'index': a:index,
+                \ })
+endfunction
+
 function! LanguageClient_NCMRefresh(info, context) abort
     return LanguageClient#Call('LanguageClient_NCMRefresh', {
                 \ 'info': a:info,
@@ -1410,6 +2047,21 @@ function! LanguageClient_NCM2OnComplete(context) abort
                 \ }, v:null)
 endfunction
 
+function! LanguageClient#getDiagnostics(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/getDiagnostics', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#registrations(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = get(a:000, 0, {})
+    return LanguageClient#Call('languageClient/registrations', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#explainErrorAtPoint(...) abort
     let extra = get(a:000, 0, {})
     let silent_mode = get(extra, 'silent', v:false)
@@ -1429,6 +2081,30 @@ function! LanguageClient#explainErrorAtPoint(...) abort
     return LanguageClient#Call('languageClient/explainErrorAtPoint', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#copyDiagnosticAtPoint(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/copyDiagnosticAtPoint', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#benchmark(feature, ...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'feature': a:feature,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/benchmark', l:params, l:Callback)
+endfunction
+
 let g:LanguageClient_omniCompleteResults = []
 function! LanguageClient#omniComplete(...) abort
     try
@@ -1546,6 +2222,35 @@ function! LanguageClient#serverStatusMessage() abort
     return g:LanguageClient_serverStatusMessage
 endfunction
 
+" Detach the current buffer from its language server, and stop sending any
+" messages for it until LanguageClient#enableBuffer() is called.
+function! LanguageClient#disableBuffer() abort
+    if get(b:, 'LanguageClient_disabled', 0)
+        return
+    endif
+
+    call LanguageClient#handleBufDelete()
+    let b:LanguageClient_disabled = 1
+    let b:LanguageClient_isServerRunning = 0
+endfunction
+
+function! LanguageClient#enableBuffer() abort
+    if !get(b:, 'LanguageClient_disabled', 0)
+        return
+    endif
+
+    let b:LanguageClient_disabled = 0
+    try
+        call LanguageClient#Notify('languageClient/handleFileType', {
+                    \ 'filename': LSP#filename(),
+                    \ 'position': LSP#position(),
+                    \ 'viewport': LSP#viewport(),
+                    \ })
+    catch
+        call s:Debug('LanguageClient caught exception: ' . string(v:exception))
+    endtry
+endfunction
+
 function! LanguageClient#isServerRunning() abort
     return get(b:, 'LanguageClient_isServerRunning', 0)
 endfunction
@@ -1593,6 +2298,28 @@ function! LanguageClient#java_classFileContents(...) abort
     return LanguageClient#Call('java/classFileContents', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#textDocument_documentLink(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('textDocument/documentLink', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#followDocumentLink(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'position': LSP#position(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/followDocumentLink', l:params, l:Callback)
+endfunction
+
 function! LanguageClient#handleCodeLensAction(...) abort
     let l:Callback = get(a:000, 1, v:null)
     let l:params = {
@@ -1604,9 +2331,71 @@ function! LanguageClient#handleCodeLensAction(...) abort
     return LanguageClient#Call('LanguageClient/handleCodeLensAction', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#codeLensList(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'execute': v:false,
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/codeLensList', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#incomingCalls(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/incomingCalls', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#typeHierarchy(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'cword': expand('<cword>'),
+                \ 'direction': 'subtypes',
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/typeHierarchy', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#showType(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ 'line': LSP#line(),
+                \ 'character': LSP#character(),
+                \ 'handle': s:IsFalse(l:Callback),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/showType', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#refreshDiagnostics(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ 'text': LSP#text(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/refreshDiagnostics', l:params, l:Callback)
+endfunction
+
 function! LanguageClient_contextMenuItems() abort
     return {
                 \ 'Code Action': 'LanguageClient#textDocument_codeAction',
+                \ 'Declaration': 'LanguageClient#textDocument_declaration',
                 \ 'Definition': 'LanguageClient#textDocument_definition',
                 \ 'Document Symbol': 'LanguageClient#textDocument_documentSymbol',
                 \ 'Formatting': 'LanguageClient#textDocument_formatting',
@@ -1625,7 +2414,9 @@ endfunction
 function! LanguageClient_handleContextMenuItem(item) abort
     let l:items = LanguageClient_contextMenuItems()
     silent! exe 'redraw'
-    return call(l:items[a:item], [])
+    " The user is warned about items the server doesn't support.
+    let l:params = {'explicit': v:true}
+    return call(l:items[a:item], a:item ==# 'Workspace Symbol' ? ['', l:params] : [l:params])
 endfunction
 
 function! LanguageClient_contextMenu() abort
@@ -1688,6 +2479,24 @@ function! LanguageClient#showSemanticHighlightSymbols(...) abort
     return LanguageClient#Call('languageClient/showSemanticHighlightSymbols', l:params, l:Callback)
 endfunction
 
+function! LanguageClient#toggleSemanticHighlight(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/toggleSemanticHighlight', l:params, l:Callback)
+endfunction
+
+function! LanguageClient#toggleDiagnostics(...) abort
+    let l:Callback = get(a:000, 1, v:null)
+    let l:params = {
+                \ 'filename': LSP#filename(),
+                \ }
+    call extend(l:params, get(a:000, 0, {}))
+    return LanguageClient#Call('languageClient/toggleDiagnostics', l:params, l:Callback)
+endfunction
+
 function! LanguageClient_showCursorSemanticHighlightSymbols(...) abort
     let l:params = get(a:000, 0, {})
     let l:Callback = get(a:000, 1, function('s:print_cursor_semantic_symbol'))
@@ -1725,10 +2534,15 @@ function! LanguageClient#debugInfo(...) abort
 endfunction
 
 function! s:ClosePopups(...) abort
+  call s:ClearHoverRange(v:null)
   if s:ShouldUseFloatWindow()
     call s:CloseFloatingHover()
   elseif exists('*popup_clear') && s:GetVar('LanguageClient_usePopupHover', v:true)
-    call popup_clear()
+    " Pinned hovers are kept open.
+    for l:winid in s:popup_win_ids
+      call popup_close(l:winid)
+    endfor
+    let s:popup_win_ids = []
   else
     :pclose
   endif
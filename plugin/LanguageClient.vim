@@ -6,66 +6,104 @@ if !exists('g:LanguageClient_semanticHighlightMaps')
     let g:LanguageClient_semanticHighlightMaps = {}
 endif
 
+" Marks a call made through the functions below as explicit, so that the user is warned when the
+" server doesn't support it. The params are the first argument.
+function! s:Explicit(args) abort
+    return [extend({'explicit': v:true}, get(a:args, 0, {}))] + a:args[1:]
+endfunction
+
 function! LanguageClient_textDocument_hover(...)
     return call('LanguageClient#textDocument_hover', a:000)
 endfunction
 
 function! LanguageClient_textDocument_definition(...)
-    return call('LanguageClient#textDocument_definition', a:000)
+    return call('LanguageClient#textDocument_definition', s:Explicit(a:000))
+endfunction
+
+function! LanguageClient_documentSymbolSearch(...)
+    return call('LanguageClient#documentSymbolSearch', s:Explicit(a:000))
+endfunction
+
+function! LanguageClient_textDocument_declaration(...)
+    return call('LanguageClient#textDocument_declaration', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_typeDefinition(...)
-    return call('LanguageClient#textDocument_typeDefinition', a:000)
+    return call('LanguageClient#textDocument_typeDefinition', s:Explicit(a:000))
+endfunction
+
+function! LanguageClient_goToExpressionType(...)
+    return call('LanguageClient#goToExpressionType', a:000)
 endfunction
 
 function! LanguageClient_textDocument_implementation(...)
-    return call('LanguageClient#textDocument_implementation', a:000)
+    return call('LanguageClient#textDocument_implementation', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_rename(...)
-    return call('LanguageClient#textDocument_rename', a:000)
+    return call('LanguageClient#textDocument_rename', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_documentSymbol(...)
-    return call('LanguageClient#textDocument_documentSymbol', a:000)
+    return call('LanguageClient#textDocument_documentSymbol', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_references(...)
-    return call('LanguageClient#textDocument_references', a:000)
+    return call('LanguageClient#textDocument_references', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_codeAction(...)
-    return call('LanguageClient#textDocument_codeAction', a:000)
+    return call('LanguageClient#textDocument_codeAction', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_codeLens(...)
     return call('LanguageClient#textDocument_codeLens', a:000)
 endfunction
 
+function! LanguageClient_textDocument_documentLink(...)
+    return call('LanguageClient#textDocument_documentLink', a:000)
+endfunction
+
+function! LanguageClient_followDocumentLink(...)
+    return call('LanguageClient#followDocumentLink', a:000)
+endfunction
+
+function! LanguageClient_textDocument_linkedEditingRange(...)
+    return call('LanguageClient#textDocument_linkedEditingRange', a:000)
+endfunction
+
 function! LanguageClient_textDocument_completion(...)
     return call('LanguageClient#textDocument_completion', a:000)
 endfunction
 
 function! LanguageClient_textDocument_formatting(...)
-    return call('LanguageClient#textDocument_formatting', a:000)
+    return call('LanguageClient#textDocument_formatting', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_formatting_sync(...)
     return call('LanguageClient#textDocument_formatting_sync', a:000)
 endfunction
 
+function! LanguageClient_textDocument_willSaveWaitUntil(...)
+    return call('LanguageClient#textDocument_willSaveWaitUntil', a:000)
+endfunction
+
 function! LanguageClient_textDocument_rangeFormatting(...)
-    return call('LanguageClient#textDocument_rangeFormatting', a:000)
+    return call('LanguageClient#textDocument_rangeFormatting', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_textDocument_documentHighlight(...)
-    return call('LanguageClient#textDocument_documentHighlight', a:000)
+    return call('LanguageClient#textDocument_documentHighlight', s:Explicit(a:000))
 endfunction
 
 function! LanguageClient_workspace_symbol(...)
     return call('LanguageClient#workspace_symbol', a:000)
 endfunction
 
+function! LanguageClient_workspace_diagnostic(...)
+    return call('LanguageClient#workspace_diagnostic', a:000)
+endfunction
+
 function! LanguageClient_workspace_applyEdit(...)
     return call('LanguageClient#workspace_applyEdit', a:000)
 endfunction
@@ -78,6 +116,10 @@ function! LanguageClient_setLoggingLevel(...)
     return call('LanguageClient#setLoggingLevel', a:000)
 endfunction
 
+function! LanguageClient_setTrace(...)
+    return call('LanguageClient#setTrace', a:000)
+endfunction
+
 function! LanguageClient_registerServerCommands(...)
     return call('LanguageClient#registerServerCommands', a:000)
 endfunction
@@ -102,6 +144,14 @@ function! LanguageClient_serverStatusMessage(...)
     return call('LanguageClient#serverStatusMessage', a:000)
 endfunction
 
+function! LanguageClient_disableBuffer(...)
+    return call('LanguageClient#disableBuffer', a:000)
+endfunction
+
+function! LanguageClient_enableBuffer(...)
+    return call('LanguageClient#enableBuffer', a:000)
+endfunction
+
 function! LanguageClient_isServerRunning(...)
     return call('LanguageClient#isServerRunning', a:000)
 endfunction
@@ -138,10 +188,42 @@ function! LanguageClient_closeFloatingHover(...)
     return call('LanguageClient#closeFloatingHover', a:000)
 endfunction
 
+function! LanguageClient_pinHover(...)
+    return call('LanguageClient#pinHover', a:000)
+endfunction
+
 function! LanguageClient_handleCodeLensAction(...)
     return call('LanguageClient#handleCodeLensAction', a:000)
 endfunction
 
+function! LanguageClient_codeLensList(...)
+    return call('LanguageClient#codeLensList', a:000)
+endfunction
+
+function! LanguageClient_incomingCalls(...)
+    return call('LanguageClient#incomingCalls', a:000)
+endfunction
+
+function! LanguageClient_typeHierarchy(...)
+    return call('LanguageClient#typeHierarchy', a:000)
+endfunction
+
+function! LanguageClient_toggleSemanticHighlight(...)
+    return call('LanguageClient#toggleSemanticHighlight', a:000)
+endfunction
+
+function! LanguageClient_toggleDiagnostics(...)
+    return call('LanguageClient#toggleDiagnostics', a:000)
+endfunction
+
+function! LanguageClient_showType(...)
+    return call('LanguageClient#showType', a:000)
+endfunction
+
+function! LanguageClient_refreshDiagnostics(...)
+    return call('LanguageClient#refreshDiagnostics', a:000)
+endfunction
+
 function! LanguageClient_explainErrorAtPoint(...)
     return call('LanguageClient#explainErrorAtPoint', a:000)
 endfunction
@@ -150,12 +232,61 @@ function! LanguageClient_textDocument_switchSourceHeader(...)
     return call('LanguageClient#textDocument_switchSourceHeader', a:000)
 endfunction
 
+function! LanguageClient_getDiagnostics(...)
+    return call('LanguageClient#getDiagnostics', a:000)
+endfunction
+
+function! LanguageClient_registrations(...)
+    return call('LanguageClient#registrations', a:000)
+endfunction
+
+function! LanguageClient_copyDiagnosticAtPoint(...)
+    return call('LanguageClient#copyDiagnosticAtPoint', a:000)
+endfunction
+
+function! LanguageClient_benchmark(...)
+    return call('LanguageClient#benchmark', a:000)
+endfunction
+
+function! LanguageClient_clangd_gotoDefinition(...)
+    return call('LanguageClient#clangd_gotoDefinition', a:000)
+endfunction
+
+function! LanguageClient_textDocument_selectionRange(...)
+    return call('LanguageClient#textDocument_selectionRange', a:000)
+endfunction
+
+function! LanguageClient_selectionRange_expand(...)
+    return call('LanguageClient#selectionRange_expand', a:000)
+endfunction
+
+function! LanguageClient_selectionRange_shrink(...)
+    return call('LanguageClient#selectionRange_shrink', a:000)
+endfunction
+
 function! LanguageClient_showCompletionItemDocumentation(...)
     return call('LanguageClient#showCompletionItemDocumentation', a:000)
 endfunction
 
 command! -nargs=* LanguageClientStart :call LanguageClient#startServer(<f-args>)
 command! LanguageClientStop call LanguageClient#shutdown()
+command! LanguageClientRestart call LanguageClient#restartServer()
+command! LanguageClientGoToExpressionType call LanguageClient#goToExpressionType()
+command! LanguageClientOpenCargoToml call LanguageClient#rustAnalyzer_openCargoToml()
+command! LanguageClientParentModule call LanguageClient#rustAnalyzer_parentModule()
+command! -nargs=? LanguageClientSsr call LanguageClient#rustAnalyzer_ssr(<q-args> ==# '' ? v:null : <q-args>)
+command! -range LanguageClientJoinLines call LanguageClient#rustAnalyzer_joinLines({'range_start_line': <line1> - 1, 'range_end_line': <line2>})
+command! LanguageClientReloadWorkspace call LanguageClient#rustAnalyzer_reloadWorkspace()
+command! LanguageClientAnalyzerStatus call LanguageClient#rustAnalyzer_analyzerStatus()
+command! -range=% LanguageClientSyntaxTree call LanguageClient#rustAnalyzer_syntaxTree(<range> ? {'range_start_line': <line1> - 1, 'range_end_line': <line2>} : {})
+command! LanguageClientViewHir call LanguageClient#rustAnalyzer_viewHir()
+command! LanguageClientToggleDiagnostics call LanguageClient#toggleDiagnostics()
+command! LanguageClientFollowDocumentLink call LanguageClient#followDocumentLink()
+command! -nargs=1 -complete=customlist,s:CompleteTrace LanguageClientSetTrace call LanguageClient#setTrace(<q-args>)
+
+function! s:CompleteTrace(arglead, cmdline, cursorpos)
+  return filter(['off', 'messages', 'verbose'], 'v:val =~# "^" . a:arglead')
+endfunction
 
 function! s:OnBufEnter()
   if !LanguageClient#HasCommand(&filetype)
@@ -179,8 +310,11 @@ function! s:ConfigureAutocmds()
   augroup languageClient
     autocmd!
     autocmd BufNewFile <buffer> call LanguageClient#handleBufNewFile()
+    autocmd BufWritePre <buffer> call LanguageClient#handleBufWritePre()
     autocmd BufWritePost <buffer> call LanguageClient#handleBufWritePost()
     autocmd BufDelete <buffer> call LanguageClient#handleBufDelete()
+    autocmd BufFilePre <buffer> call LanguageClient#handleBufFilePre()
+    autocmd BufFilePost <buffer> call LanguageClient#handleBufFilePost()
     autocmd TextChanged <buffer> call LanguageClient#handleTextChanged()
     autocmd TextChangedI <buffer> call LanguageClient#handleTextChanged()
     if exists('##TextChangedP')
@@ -193,6 +327,9 @@ function! s:ConfigureAutocmds()
         autocmd CompleteDone <buffer>
                     \ call LanguageClient#textDocument_signatureHelp({}, 's:HandleOutputNothing')
     endif
+    if get(g:, 'LanguageClient_linkedEditing', 0)
+        autocmd InsertEnter <buffer> call LanguageClient#textDocument_linkedEditingRange()
+    endif
     if exists('##CompleteChanged') && get(g:, 'LanguageClient_showCompletionDocs', 1)
       autocmd CompleteChanged <buffer> call LanguageClient#handleCompleteChanged(deepcopy(v:event))
     endif
@@ -200,8 +337,10 @@ function! s:ConfigureAutocmds()
     nnoremap <Plug>(lcn-menu)               :call LanguageClient_contextMenu()<CR>
     nnoremap <Plug>(lcn-hover)              :call LanguageClient_textDocument_hover()<CR>
     nnoremap <Plug>(lcn-rename)             :call LanguageClient_textDocument_rename()<CR>
+    nnoremap <Plug>(lcn-declaration)        :call LanguageClient_textDocument_declaration()<CR>
     nnoremap <Plug>(lcn-definition)         :call LanguageClient_textDocument_definition()<CR>
     nnoremap <Plug>(lcn-type-definition)    :call LanguageClient_textDocument_typeDefinition()<CR>
+    nnoremap <Plug>(lcn-expression-type)    :call LanguageClient_goToExpressionType()<CR>
     nnoremap <Plug>(lcn-references)         :call LanguageClient_textDocument_references()<CR>
     nnoremap <Plug>(lcn-implementation)     :call LanguageClient_textDocument_implementation()<CR>
     nnoremap <Plug>(lcn-code-action)        :call LanguageClient_textDocument_codeAction()<CR>
@@ -210,10 +349,15 @@ function! s:ConfigureAutocmds()
     nnoremap <Plug>(lcn-symbols)            :call LanguageClient_textDocument_documentSymbol()<CR>
     nnoremap <Plug>(lcn-highlight)          :call LanguageClient_textDocument_documentHighlight()<CR>
     nnoremap <Plug>(lcn-explain-error)      :call LanguageClient_explainErrorAtPoint()<CR>
+    nnoremap <Plug>(lcn-copy-error)         :call LanguageClient_copyDiagnosticAtPoint()<CR>
     nnoremap <Plug>(lcn-format)             :call LanguageClient_textDocument_formatting()<CR>
     nnoremap <Plug>(lcn-format-sync)        :call LanguageClient_textDocument_formatting_sync()<CR>
     nnoremap <Plug>(lcn-diagnostics-next)   :call LanguageClient_diagnosticsNext()<CR>
     nnoremap <Plug>(lcn-diagnostics-prev)   :call LanguageClient_diagnosticsPrevious()<CR>
+    nnoremap <Plug>(lcn-expand-selection)   :call LanguageClient_selectionRange_expand()<CR>
+    xnoremap <Plug>(lcn-expand-selection)   :<C-u>call LanguageClient_selectionRange_expand()<CR>
+    xnoremap <Plug>(lcn-shrink-selection)   :<C-u>call LanguageClient_selectionRange_shrink()<CR>
+    nnoremap <Plug>(lcn-follow-link)        :call LanguageClient_followDocumentLink()<CR>
   augroup END
 endfunction
 
@@ -3,9 +3,11 @@ mod server_command;
 pub use server_command::*;
 
 use crate::{
+    sign::DEFAULT_SIGN_PRIORITY,
     types::{
-        CodeLensDisplay, DiagnosticsDisplay, DiagnosticsList, DocumentHighlightDisplay,
-        HoverPreviewOption, RootMarkers, SelectionUI, UseVirtualText,
+        CodeLensDisplay, CompletionEditRange, DiagnosticsDisplay, DiagnosticsList,
+        DocumentHighlightDisplay, HoverPreviewOption, ListKind, RootMarkers, SelectionUI,
+        SettingsPath, SnippetEngine, UseVirtualText, VirtualTextPosition,
     },
     vim::Vim,
 };
@@ -22,33 +24,67 @@ pub struct Config {
     pub selection_ui: SelectionUI,
     pub trace: TraceOption,
     pub settings_path: Vec<String>,
+    pub settings_path_by_language: HashMap<String, Vec<String>>,
     pub load_settings: bool,
     pub root_markers: Option<RootMarkers>,
     pub change_throttle: Option<Duration>,
+    pub document_highlight_throttle: Option<Duration>,
+    pub close_grace_period: Option<Duration>,
+    pub did_open_delay: Option<Duration>,
+    pub diagnostics_pull_delay: Option<Duration>,
     pub wait_output_timeout: Duration,
+    pub request_timeouts: HashMap<String, Duration>,
     pub diagnostics_enable: bool,
     pub diagnostics_list: DiagnosticsList,
+    pub diagnostics_list_by_source: HashMap<String, DiagnosticsList>,
     pub diagnostics_display: HashMap<u64, DiagnosticsDisplay>,
     pub code_lens_display: CodeLensDisplay,
     pub window_log_message_level: MessageType,
     pub hover_preview: HoverPreviewOption,
     pub completion_prefer_text_edit: bool,
+    pub completion_edit_range: CompletionEditRange,
+    pub snippet_engine: Option<SnippetEngine>,
+    pub rename_preview: bool,
     pub is_nvim: bool,
     pub logging_file: Option<PathBuf>,
     pub logging_level: log::LevelFilter,
     pub server_stderr: Option<String>,
     pub diagnostics_signs_max: Option<usize>,
+    pub locations_list_threshold: usize,
+    pub diagnostics_signs_priority: u64,
+    pub diagnostics_signs_priority_by_severity: bool,
     pub diagnostics_max_severity: DiagnosticSeverity,
     pub diagnostics_ignore_sources: Vec<String>,
     pub document_highlight_display: HashMap<u64, DocumentHighlightDisplay>,
     pub selection_ui_auto_open: bool,
+    pub selection_ui_auto_open_threshold: usize,
+    pub selection_ui_auto_open_kinds: HashMap<ListKind, bool>,
+    pub selection_ui_new_list: bool,
+    pub selection_ui_code_actions: bool,
+    pub diagnostics_list_reuse: bool,
     pub use_virtual_text: UseVirtualText,
     pub echo_project_root: bool,
     pub semantic_highlight_maps: HashMap<String, HashMap<String, String>>,
     pub semantic_scope_separator: String,
     pub apply_completion_text_edits: bool,
     pub preferred_markup_kind: Option<Vec<MarkupKind>>,
+    pub markup_kind: HashMap<String, Vec<MarkupKind>>,
+    pub hover_highlight_range: bool,
+    pub completion_kind_labels: HashMap<String, String>,
+    pub list_open_command: HashMap<String, String>,
+    pub linked_editing: bool,
+    pub virtual_text_position: VirtualTextPosition,
+    pub virtual_text_spacing: usize,
+    pub trace_rpc: bool,
+    pub rpc_trace_file: Option<PathBuf>,
+    pub method_aliases: HashMap<String, HashMap<String, String>>,
+    pub progress_echo: bool,
+    pub allowed_commands: Option<Vec<String>>,
     pub hide_virtual_texts_on_insert: bool,
+    pub virtual_text_max_width: Option<usize>,
+    pub virtual_text_max_diagnostics: Option<usize>,
+    pub virtual_text_current_line_only: bool,
+    pub echo_width_reserve: usize,
     pub enable_extensions: Option<HashMap<String, bool>>,
     pub restart_on_crash: bool,
     pub max_restart_retries: u8,
@@ -63,29 +99,63 @@ impl Default for Config {
             auto_start: true,
             selection_ui: SelectionUI::LocationList,
             selection_ui_auto_open: true,
+            selection_ui_auto_open_threshold: 0,
+            selection_ui_auto_open_kinds: HashMap::new(),
+            selection_ui_new_list: true,
+            selection_ui_code_actions: false,
+            diagnostics_list_reuse: false,
             trace: TraceOption::default(),
             diagnostics_enable: true,
             diagnostics_list: DiagnosticsList::Quickfix,
+            diagnostics_list_by_source: HashMap::new(),
             diagnostics_display: DiagnosticsDisplay::default(),
             code_lens_display: CodeLensDisplay::default(),
             diagnostics_signs_max: None,
+            locations_list_threshold: 2,
+            diagnostics_signs_priority: DEFAULT_SIGN_PRIORITY,
+            diagnostics_signs_priority_by_severity: false,
             diagnostics_max_severity: DiagnosticSeverity::Hint,
             diagnostics_ignore_sources: vec![],
             document_highlight_display: DocumentHighlightDisplay::default(),
             window_log_message_level: MessageType::Warning,
             settings_path: vec![format!(".vim{}settings.json", std::path::MAIN_SEPARATOR)],
+            settings_path_by_language: HashMap::new(),
             load_settings: false,
             root_markers: None,
             change_throttle: None,
+            document_highlight_throttle: None,
+            close_grace_period: None,
+            did_open_delay: None,
+            diagnostics_pull_delay: Some(Duration::from_millis(500)),
             wait_output_timeout: Duration::from_secs(10),
+            request_timeouts: HashMap::new(),
             hover_preview: HoverPreviewOption::default(),
             completion_prefer_text_edit: false,
+            completion_edit_range: CompletionEditRange::default(),
+            snippet_engine: None,
+            rename_preview: false,
             apply_completion_text_edits: true,
             use_virtual_text: UseVirtualText::All,
             hide_virtual_texts_on_insert: true,
+            virtual_text_max_width: None,
+            virtual_text_max_diagnostics: None,
+            virtual_text_current_line_only: false,
+            echo_width_reserve: 2,
             echo_project_root: true,
             server_stderr: None,
             preferred_markup_kind: None,
+            markup_kind: HashMap::new(),
+            hover_highlight_range: true,
+            completion_kind_labels: HashMap::new(),
+            list_open_command: HashMap::new(),
+            linked_editing: false,
+            virtual_text_position: VirtualTextPosition::default(),
+            virtual_text_spacing: 0,
+            trace_rpc: false,
+            rpc_trace_file: None,
+            method_aliases: HashMap::new(),
+            progress_echo: true,
+            allowed_commands: None,
             enable_extensions: None,
             is_nvim: false,
             logging_file: None,
@@ -105,30 +175,63 @@ struct DeserializableConfig {
     server_commands: HashMap<String, ServerCommand>,
     selection_ui: Option<String>,
     trace: Option<String>,
-    settings_path: Vec<String>,
+    settings_path: SettingsPath,
     load_settings: u8,
     root_markers: Option<RootMarkers>,
     change_throttle: Option<f64>,
+    document_highlight_throttle: Option<f64>,
+    close_grace_period: Option<f64>,
+    did_open_delay: Option<f64>,
+    diagnostics_pull_delay: Option<f64>,
     wait_output_timeout: Option<f64>,
+    request_timeouts: HashMap<String, u64>,
     diagnostics_enable: u8,
     diagnostics_list: Option<String>,
+    diagnostics_list_by_source: HashMap<String, String>,
     diagnostics_display: HashMap<u64, DiagnosticsDisplay>,
     window_log_message_level: String,
     hover_preview: Option<String>,
     completion_prefer_text_edit: u8,
+    completion_edit_range: Option<String>,
+    snippet_engine: Option<String>,
+    rename_preview: u8,
     is_nvim: u8,
     diagnostics_signs_max: Option<usize>,
+    locations_list_threshold: Option<usize>,
+    diagnostics_signs_priority: Option<u64>,
+    diagnostics_signs_priority_by_severity: u8,
     diagnostics_max_severity: String,
     diagnostics_ignore_sources: Vec<String>,
     document_highlight_display: Option<HashMap<u64, DocumentHighlightDisplay>>,
     selection_ui_auto_open: u8,
+    selection_ui_auto_open_threshold: usize,
+    selection_ui_auto_open_kinds: HashMap<ListKind, u8>,
+    selection_ui_new_list: u8,
+    selection_ui_code_actions: u8,
+    diagnostics_list_reuse: u8,
     use_virtual_text: UseVirtualText,
     echo_project_root: u8,
     semantic_highlight_maps: HashMap<String, HashMap<String, String>>,
     semantic_scope_separator: String,
     apply_completion_text_edits: u8,
     preferred_markup_kind: Option<Vec<MarkupKind>>,
+    markup_kind: HashMap<String, Vec<MarkupKind>>,
+    hover_highlight_range: u8,
+    completion_kind_labels: HashMap<String, String>,
+    list_open_command: HashMap<String, String>,
+    linked_editing: u8,
+    virtual_text_position: Option<String>,
+    virtual_text_spacing: usize,
+    trace_rpc: u8,
+    rpc_trace_file: Option<PathBuf>,
+    method_aliases: HashMap<String, HashMap<String, String>>,
+    progress_echo: u8,
+    allowed_commands: Option<Vec<String>>,
     hide_virtual_texts_on_insert: u8,
+    virtual_text_max_width: Option<usize>,
+    virtual_text_max_diagnostics: Option<usize>,
+    virtual_text_current_line_only: u8,
+    echo_width_reserve: Option<usize>,
     enable_extensions: Option<HashMap<String, bool>>,
     code_lens_display: Option<CodeLensDisplay>,
     restart_on_crash: u8,
@@ -142,30 +245,63 @@ impl Config {
             "server_commands": s:GetVar('LanguageClient_serverCommands', {}),
             "selection_ui": s:getSelectionUI(),
             "trace": get(g:, 'LanguageClient_trace', v:null),
-            "settings_path": map(s:ToList(get(g:, 'LanguageClient_settingsPath', '.vim/settings.json')), 'expand(v:val)'),
+            "settings_path": s:getSettingsPath(),
             "load_settings": !!get(g:, 'LanguageClient_loadSettings', 1),
             "root_markers": get(g:, 'LanguageClient_rootMarkers', v:null),
             "change_throttle": get(g:, 'LanguageClient_changeThrottle', v:null),
+            "document_highlight_throttle": get(g:, 'LanguageClient_documentHighlightThrottle', v:null),
+            "close_grace_period": get(g:, 'LanguageClient_closeGracePeriod', v:null),
+            "did_open_delay": get(g:, 'LanguageClient_didOpenDelay', v:null),
+            "diagnostics_pull_delay": get(g:, 'LanguageClient_diagnosticsPullDelay', 0.5),
             "wait_output_timeout": get(g:, 'LanguageClient_waitOutputTimeout', v:null),
+            "request_timeouts": get(g:, 'LanguageClient_requestTimeouts', {}),
             "diagnostics_enable": !!get(g:, 'LanguageClient_diagnosticsEnable', 1),
             "diagnostics_list": get(g:, 'LanguageClient_diagnosticsList', 'Quickfix'),
+            "diagnostics_list_by_source": get(g:, 'LanguageClient_diagnosticsListBySource', {}),
             "diagnostics_display": get(g:, 'LanguageClient_diagnosticsDisplay', {}),
             "window_log_message_level": get(g:, 'LanguageClient_windowLogMessageLevel', 'Warning'),
             "hover_preview": get(g:, 'LanguageClient_hoverPreview', 'Auto'),
             "completion_prefer_text_edit": get(g:, 'LanguageClient_completionPreferTextEdit', 0),
+            "completion_edit_range": get(g:, 'LanguageClient_completionEditRange', v:null),
+            "snippet_engine": get(g:, 'LanguageClient_snippetEngine', v:null),
+            "rename_preview": !!s:GetVar('LanguageClient_renamePreview', 0),
             "is_nvim": has('nvim'),
             "diagnostics_signs_max": get(g:, 'LanguageClient_diagnosticsSignsMax', v:null),
+            "locations_list_threshold": get(g:, 'LanguageClient_locationsListThreshold', v:null),
+            "diagnostics_signs_priority": get(g:, 'LanguageClient_diagnosticsSignsPriority', v:null),
+            "diagnostics_signs_priority_by_severity": !!get(g:, 'LanguageClient_diagnosticsSignsPriorityBySeverity', 0),
             "diagnostics_max_severity": get(g:, 'LanguageClient_diagnosticsMaxSeverity', 'Hint'),
             "diagnostics_ignore_sources": get(g:, 'LanguageClient_diagnosticsIgnoreSources', []),
             "document_highlight_display": get(g:, 'LanguageClient_documentHighlightDisplay', {}),
             "selection_ui_auto_open": !!s:GetVar('LanguageClient_selectionUI_autoOpen', 1),
+            "selection_ui_auto_open_threshold": s:GetVar('LanguageClient_selectionUI_autoOpenThreshold', 0),
+            "selection_ui_auto_open_kinds": map(copy(s:GetVar('LanguageClient_selectionUI_autoOpenKinds', {})), '!!v:val'),
+            "selection_ui_new_list": !!s:GetVar('LanguageClient_selectionUI_newList', 1),
+            "selection_ui_code_actions": type(get(g:, 'LanguageClient_selectionUI_codeActions')) is s:TYPE.funcref,
+            "diagnostics_list_reuse": !!s:GetVar('LanguageClient_diagnosticsListReuse', 0),
             "use_virtual_text": s:useVirtualText(),
             "echo_project_root": !!s:GetVar('LanguageClient_echoProjectRoot', 1),
             "semantic_highlight_maps": s:GetVar('LanguageClient_semanticHighlightMaps', {}),
             "semantic_scope_separator": s:GetVar('LanguageClient_semanticScopeSeparator', ':'),
             "apply_completion_text_edits": get(g:, 'LanguageClient_applyCompletionAdditionalTextEdits', 1),
             "preferred_markup_kind": get(g:, 'LanguageClient_preferredMarkupKind', v:null),
+            "hover_highlight_range": !!s:GetVar('LanguageClient_hoverHighlightRange', 1),
+            "markup_kind": map(copy(get(g:, 'LanguageClient_markupKind', {})), 'type(v:val) == type([]) ? v:val : [v:val]'),
+            "completion_kind_labels": get(g:, 'LanguageClient_completionKindLabels', {}),
+            "list_open_command": get(g:, 'LanguageClient_listOpenCommand', {}),
+            "linked_editing": !!s:GetVar('LanguageClient_linkedEditing', 0),
+            "virtual_text_position": get(g:, 'LanguageClient_virtualTextPosition', v:null),
+            "virtual_text_spacing": get(g:, 'LanguageClient_virtualTextSpacing', 0),
+            "trace_rpc": !!s:GetVar('LanguageClient_traceRpc', 0),
+            "rpc_trace_file": get(g:, 'LanguageClient_rpcTraceFile', v:null),
+            "method_aliases": get(g:, 'LanguageClient_methodAliases', {}),
+            "progress_echo": !!s:GetVar('LanguageClient_progressEcho', 1),
+            "allowed_commands": get(g:, 'LanguageClient_allowedCommands', v:null),
             "hide_virtual_texts_on_insert": s:GetVar('LanguageClient_hideVirtualTextsOnInsert', 0),
+            "virtual_text_max_width": s:GetVar('LanguageClient_virtualTextMaxWidth', v:null),
+            "virtual_text_max_diagnostics": s:GetVar('LanguageClient_virtualTextMaxDiagnostics', v:null),
+            "virtual_text_current_line_only": !!s:GetVar('LanguageClient_virtualTextCurrentLineOnly', 0),
+            "echo_width_reserve": get(g:, 'LanguageClient_echoWidthReserve', v:null),
             "enable_extensions": get(g:, 'LanguageClient_enableExtensions', v:null),
             "code_lens_display": get(g:, 'LanguageClient_codeLensDisplay', v:null),
             "restart_on_crash": get(g:, 'LanguageClient_restartOnCrash', 1),
@@ -189,48 +325,127 @@ impl Config {
             None => DiagnosticsList::Disabled,
         };
 
+        let diagnostics_list_by_source = res
+            .diagnostics_list_by_source
+            .into_iter()
+            .map(|(source, list)| Ok((source, DiagnosticsList::from_str(&list)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
         let hover_preview = match res.hover_preview {
             Some(s) => HoverPreviewOption::from_str(&s)?,
             None => HoverPreviewOption::Auto,
         };
 
+        let completion_edit_range = match res.completion_edit_range {
+            Some(s) => CompletionEditRange::from_str(&s)?,
+            None => CompletionEditRange::default(),
+        };
+
+        let virtual_text_position = match res.virtual_text_position {
+            Some(s) => VirtualTextPosition::from_str(&s)?,
+            None => VirtualTextPosition::default(),
+        };
+
+        // Languages without paths of their own use the default paths.
+        let (settings_path, settings_path_by_language) = match res.settings_path {
+            SettingsPath::Array(paths) => (paths, HashMap::new()),
+            SettingsPath::Map(map) => (Config::default().settings_path, map),
+        };
+
+        let snippet_engine = match res.snippet_engine {
+            Some(s) => Some(SnippetEngine::from_str(&s)?),
+            None => None,
+        };
+
         Ok(Config {
             auto_start: res.auto_start == 1,
             server_commands: res.server_commands,
             selection_ui,
             trace: trace(&res.trace.unwrap_or("off".to_string()))?,
-            settings_path: res.settings_path,
+            settings_path,
+            settings_path_by_language,
             load_settings: res.load_settings == 1,
             root_markers: res.root_markers,
             change_throttle: res
                 .change_throttle
                 .map(|t| Duration::from_millis((t * 1000.0) as u64)),
+            document_highlight_throttle: res
+                .document_highlight_throttle
+                .map(|t| Duration::from_millis((t * 1000.0) as u64)),
+            close_grace_period: res
+                .close_grace_period
+                .map(|t| Duration::from_millis((t * 1000.0) as u64)),
+            did_open_delay: res
+                .did_open_delay
+                .map(|t| Duration::from_millis((t * 1000.0) as u64)),
+            diagnostics_pull_delay: res
+                .diagnostics_pull_delay
+                .map(|t| Duration::from_millis((t * 1000.0) as u64)),
             wait_output_timeout: Duration::from_millis(
                 (res.wait_output_timeout.unwrap_or(10.0) * 1000.0) as u64,
             ),
+            request_timeouts: res
+                .request_timeouts
+                .into_iter()
+                .map(|(method, ms)| (method, Duration::from_millis(ms)))
+                .collect(),
             diagnostics_enable: res.diagnostics_enable == 1,
             diagnostics_list,
+            diagnostics_list_by_source,
             diagnostics_display: res.diagnostics_display,
             code_lens_display: res.code_lens_display.unwrap_or_default(),
             window_log_message_level: message_type(&res.window_log_message_level)?,
             hover_preview,
             completion_prefer_text_edit: res.completion_prefer_text_edit == 1,
+            completion_edit_range,
+            snippet_engine,
+            rename_preview: res.rename_preview == 1,
             is_nvim: res.is_nvim == 1,
             logging_file: res.logging_file,
             logging_level: res.logging_level,
             server_stderr: res.server_stderr,
             diagnostics_signs_max: res.diagnostics_signs_max,
+            locations_list_threshold: res.locations_list_threshold.unwrap_or(2),
+            diagnostics_signs_priority: res
+                .diagnostics_signs_priority
+                .unwrap_or(DEFAULT_SIGN_PRIORITY),
+            diagnostics_signs_priority_by_severity: res.diagnostics_signs_priority_by_severity == 1,
             diagnostics_max_severity: diagnostics_severity(&res.diagnostics_max_severity)?,
             diagnostics_ignore_sources: res.diagnostics_ignore_sources,
             document_highlight_display: res.document_highlight_display.unwrap_or_default(),
             selection_ui_auto_open: res.selection_ui_auto_open == 1,
+            selection_ui_auto_open_threshold: res.selection_ui_auto_open_threshold,
+            selection_ui_auto_open_kinds: res
+                .selection_ui_auto_open_kinds
+                .into_iter()
+                .map(|(kind, open)| (kind, open == 1))
+                .collect(),
+            selection_ui_new_list: res.selection_ui_new_list == 1,
+            selection_ui_code_actions: res.selection_ui_code_actions == 1,
+            diagnostics_list_reuse: res.diagnostics_list_reuse == 1,
             use_virtual_text: res.use_virtual_text,
             echo_project_root: res.echo_project_root == 1,
             semantic_highlight_maps: res.semantic_highlight_maps,
             semantic_scope_separator: res.semantic_scope_separator,
             apply_completion_text_edits: res.apply_completion_text_edits == 1,
             preferred_markup_kind: res.preferred_markup_kind,
+            markup_kind: res.markup_kind,
+            hover_highlight_range: res.hover_highlight_range == 1,
+            completion_kind_labels: res.completion_kind_labels,
+            list_open_command: res.list_open_command,
+            linked_editing: res.linked_editing == 1,
+            virtual_text_position,
+            virtual_text_spacing: res.virtual_text_spacing,
+            trace_rpc: res.trace_rpc == 1,
+            rpc_trace_file: res.rpc_trace_file,
+            method_aliases: res.method_aliases,
+            progress_echo: res.progress_echo == 1,
+            allowed_commands: res.allowed_commands,
             hide_virtual_texts_on_insert: res.hide_virtual_texts_on_insert == 1,
+            virtual_text_max_width: res.virtual_text_max_width.filter(|w| *w > 0),
+            virtual_text_max_diagnostics: res.virtual_text_max_diagnostics.filter(|n| *n > 0),
+            virtual_text_current_line_only: res.virtual_text_current_line_only == 1,
+            echo_width_reserve: res.echo_width_reserve.unwrap_or(2),
             enable_extensions: res.enable_extensions,
             restart_on_crash: res.restart_on_crash == 1,
             max_restart_retries: res.max_restart_retries,
@@ -1,6 +1,7 @@
 use crate::utils::expand_json_path;
 use jsonrpc_core::Value;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -8,6 +9,9 @@ pub struct ServerDetails {
     pub command: Vec<String>,
     pub name: String,
     pub initialization_options: Option<Value>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub pre_launch: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -48,6 +52,22 @@ impl ServerCommand {
         }
     }
 
+    /// Environment variables to set for the server process, on top of the inherited ones.
+    pub fn env(&self) -> HashMap<String, String> {
+        match self {
+            ServerCommand::Simple(_) => HashMap::new(),
+            ServerCommand::Detailed(cmd) => cmd.env.clone(),
+        }
+    }
+
+    /// Command to run to completion before the server is started, if any.
+    pub fn pre_launch(&self) -> Option<Vec<String>> {
+        match self {
+            ServerCommand::Simple(_) => None,
+            ServerCommand::Detailed(cmd) => cmd.pre_launch.clone(),
+        }
+    }
+
     #[cfg(not(target_os = "windows"))]
     fn name_from_command(cmd: &[String]) -> String {
         // it's safe to assume there is at least one item in cmd, otherwise
@@ -80,4 +100,31 @@ mod test {
         let name = ServerCommand::name_from_command(&vec!["/path/to/gopls".into()]);
         assert_eq!(name.as_str(), "gopls");
     }
+
+    #[test]
+    fn test_deserializes_detailed_command_env() {
+        let command: ServerCommand = serde_json::from_value(serde_json::json!({
+            "name": "rust-analyzer",
+            "command": ["rust-analyzer"],
+            "env": {"RUST_LOG": "info"},
+            "preLaunch": ["cargo", "build"],
+        }))
+        .unwrap();
+        assert_eq!(
+            command.env().get("RUST_LOG").map(String::as_str),
+            Some("info")
+        );
+        assert_eq!(
+            command.pre_launch(),
+            Some(vec!["cargo".to_owned(), "build".to_owned()])
+        );
+
+        let command: ServerCommand = serde_json::from_value(serde_json::json!({
+            "name": "gopls",
+            "command": ["gopls"],
+        }))
+        .unwrap();
+        assert!(command.env().is_empty());
+        assert_eq!(command.pre_launch(), None);
+    }
 }
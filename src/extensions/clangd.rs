@@ -1,7 +1,14 @@
-use crate::{language_client::LanguageClient, utils::ToUrl};
+use crate::{
+    language_client::LanguageClient,
+    types::{Filepath, LineCache, ListKind},
+    utils::{Combine, ToUrl},
+};
 use anyhow::Result;
 use jsonrpc_core::Value;
-use lsp_types::{request::Request, TextDocumentIdentifier};
+use lsp_types::{request::Request, GotoDefinitionResponse, Location, TextDocumentIdentifier, Url};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
 
 pub mod request {
     use lsp_types::{request::Request, TextDocumentIdentifier};
@@ -15,21 +22,105 @@ pub mod request {
     }
 }
 
+const HEADER_EXTENSIONS: &[&str] = &["h", "hh", "hpp", "hxx", "h++", "inl"];
+
+fn is_header(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            HEADER_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+        })
+}
+
 impl LanguageClient {
     pub fn text_document_switch_source_header(&self, params: &Value) -> Result<Value> {
         let filename = self.vim()?.get_filename(params)?;
-        let language_id = self.vim()?.get_language_id(&filename, &Value::Null)?;
+        match self.source_header_counterpart(&filename)? {
+            Some(path) => self.vim()?.edit(&None, path)?,
+            None => self.vim()?.echowarn("No counterpart found!")?,
+        }
+
+        Ok(Value::Null)
+    }
+
+    /// Goes to the definition under the cursor. If the definition is in a source file while the
+    /// current file is a header (or the other way around), offers to jump to the counterpart of
+    /// the current file instead, and if no definition is found at all, jumps to the counterpart.
+    pub fn clangd_goto_definition(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let goto_cmd = self.vim()?.get_goto_cmd(params)?;
+        let result = self.text_document_definition(&params.combine(&json!({ "handle": false })))?;
+        let locations = match Option::<GotoDefinitionResponse>::deserialize(&result)? {
+            None => vec![],
+            Some(GotoDefinitionResponse::Scalar(loc)) => vec![loc],
+            Some(GotoDefinitionResponse::Array(arr)) => arr,
+            Some(GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| Location::new(link.target_uri, link.target_selection_range))
+                .collect(),
+        };
+
+        let definition = match locations.as_slice() {
+            [] => {
+                return self.text_document_switch_source_header(params);
+            }
+            [loc] => loc.clone(),
+            _ => {
+                let title = format!("[LC]: definitions in {}", filename);
+                self.present_list(ListKind::Locations, &title, &locations)?;
+                return Ok(result);
+            }
+        };
+
+        let path = definition.uri.filepath()?;
+        if is_header(&path) != is_header(Path::new(&filename)) {
+            if let Some(counterpart) = self.source_header_counterpart(&filename)? {
+                let options = vec![
+                    "Jump to:".to_string(),
+                    format!(
+                        "1. definition ({}:{})",
+                        path.to_string_lossy(),
+                        definition.range.start.line + 1
+                    ),
+                    format!("2. counterpart ({})", counterpart.to_string_lossy()),
+                ];
+                let index: Option<usize> = self.vim()?.rpcclient.call("s:inputlist", options)?;
+                match index {
+                    Some(1) => {}
+                    Some(2) => {
+                        self.vim()?.edit(&goto_cmd, counterpart)?;
+                        return Ok(result);
+                    }
+                    _ => return Ok(result),
+                }
+            }
+        }
+
+        self.vim()?.edit(&goto_cmd, &path)?;
+        let start = self.position_to_bytes(&LineCache::default(), &path, definition.range.start)?;
+        self.vim()?.cursor(start.line + 1, start.character + 1)?;
+
+        Ok(result)
+    }
+
+    /// Asks clangd for the header of a source file, or the source file of a header.
+    fn source_header_counterpart(&self, filename: &str) -> Result<Option<PathBuf>> {
+        let language_id = self.vim()?.get_language_id(filename, &Value::Null)?;
         let params = TextDocumentIdentifier {
             uri: filename.to_url()?,
         };
 
-        let response: String = self
+        let response: Option<String> = self
             .get_client(&Some(language_id))?
             .call(request::SwitchSourceHeader::METHOD, params)?;
 
-        let path = std::path::Path::new(&response);
-        self.vim()?.edit(&None, path)?;
-
-        Ok(Value::Null)
+        let path = match response {
+            Some(response) => match Url::parse(&response) {
+                Ok(uri) => Some(uri.filepath()?),
+                Err(_) => Some(PathBuf::from(response)),
+            },
+            None => None,
+        };
+        Ok(path)
     }
 }
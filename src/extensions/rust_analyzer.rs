@@ -1,9 +1,18 @@
 use crate::types;
-use crate::{language_client::LanguageClient, types::WorkspaceEditWithCursor, utils::ToUrl};
+use crate::{
+    language_client::LanguageClient,
+    types::{Filepath, LineCache, WorkspaceEditWithCursor, VIM_BUFFER_DISABLED},
+    utils::ToUrl,
+    vim::try_get,
+};
 use anyhow::{anyhow, Result};
 use jsonrpc_core::Value;
-use lsp_types::{request::Request, Command, Location, Range, TextDocumentIdentifier};
+use lsp_types::{
+    request::Request, Command, GotoDefinitionResponse, Location, Position, Range,
+    TextDocumentIdentifier, TextDocumentPositionParams, TextEdit, WorkspaceEdit,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::path::PathBuf;
 
 // Runnable wraps the two possible shapes of a runnable action from rust-analyzer. Old-ish versions
@@ -76,6 +85,44 @@ pub struct InlayHintsParams {
     text_document: TextDocumentIdentifier,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenCargoTomlParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsrParams {
+    query: String,
+    parse_only: bool,
+    text_document: TextDocumentIdentifier,
+    position: Position,
+    selections: Vec<Range>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinLinesParams {
+    text_document: TextDocumentIdentifier,
+    ranges: Vec<Range>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzerStatusParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_document: Option<TextDocumentIdentifier>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxTreeParams {
+    text_document: TextDocumentIdentifier,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<Range>,
+}
+
 pub mod command {
     pub const SHOW_REFERENCES: &str = "rust-analyzer.showReferences";
     pub const SELECT_APPLY_SOURCE_CHANGE: &str = "rust-analyzer.selectAndApplySourceChange";
@@ -92,6 +139,70 @@ pub mod request {
         type Result = Vec<super::InlayHint>;
         const METHOD: &'static str = "rust-analyzer/inlayHints";
     }
+
+    pub enum OpenCargoToml {}
+
+    impl lsp_types::request::Request for OpenCargoToml {
+        type Params = super::OpenCargoTomlParams;
+        type Result = Option<lsp_types::Location>;
+        const METHOD: &'static str = "experimental/openCargoToml";
+    }
+
+    pub enum ParentModule {}
+
+    impl lsp_types::request::Request for ParentModule {
+        type Params = lsp_types::TextDocumentPositionParams;
+        type Result = Option<lsp_types::GotoDefinitionResponse>;
+        const METHOD: &'static str = "experimental/parentModule";
+    }
+
+    pub enum Ssr {}
+
+    impl lsp_types::request::Request for Ssr {
+        type Params = super::SsrParams;
+        type Result = lsp_types::WorkspaceEdit;
+        const METHOD: &'static str = "experimental/ssr";
+    }
+
+    pub enum JoinLines {}
+
+    impl lsp_types::request::Request for JoinLines {
+        type Params = super::JoinLinesParams;
+        type Result = Vec<lsp_types::TextEdit>;
+        const METHOD: &'static str = "experimental/joinLines";
+    }
+
+    pub enum ReloadWorkspace {}
+
+    impl lsp_types::request::Request for ReloadWorkspace {
+        type Params = ();
+        type Result = ();
+        const METHOD: &'static str = "rust-analyzer/reloadWorkspace";
+    }
+
+    pub enum AnalyzerStatus {}
+
+    impl lsp_types::request::Request for AnalyzerStatus {
+        type Params = super::AnalyzerStatusParams;
+        type Result = String;
+        const METHOD: &'static str = "rust-analyzer/analyzerStatus";
+    }
+
+    pub enum SyntaxTree {}
+
+    impl lsp_types::request::Request for SyntaxTree {
+        type Params = super::SyntaxTreeParams;
+        type Result = String;
+        const METHOD: &'static str = "rust-analyzer/syntaxTree";
+    }
+
+    pub enum ViewHir {}
+
+    impl lsp_types::request::Request for ViewHir {
+        type Params = lsp_types::TextDocumentPositionParams;
+        type Result = String;
+        const METHOD: &'static str = "rust-analyzer/viewHir";
+    }
 }
 
 const FILETYPE: &str = "rust";
@@ -133,6 +244,340 @@ impl LanguageClient {
             .collect())
     }
 
+    /// Opens the Cargo.toml of the crate of the current file.
+    pub fn rust_analyzer_open_cargo_toml(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Opening Cargo.toml is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let goto_cmd = self.vim()?.get_goto_cmd(params)?;
+        let location: Option<Location> = self.get_client(&Some(language_id))?.call(
+            request::OpenCargoToml::METHOD,
+            OpenCargoTomlParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+            },
+        )?;
+
+        match &location {
+            Some(location) => self.goto_location(&goto_cmd, location)?,
+            None => self.vim()?.echowarn("No Cargo.toml found!")?,
+        }
+
+        Ok(serde_json::to_value(location)?)
+    }
+
+    /// Goes to the module declaring the current module, listing the modules if there are several.
+    pub fn rust_analyzer_parent_module(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Going to the parent module is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let goto_cmd = self.vim()?.get_goto_cmd(params)?;
+        let position = self.get_position(&filename, params)?;
+        let result: Value = self.get_client(&Some(language_id))?.call(
+            request::ParentModule::METHOD,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                position,
+            },
+        )?;
+
+        let locations = match Option::<GotoDefinitionResponse>::deserialize(&result)? {
+            None => vec![],
+            Some(GotoDefinitionResponse::Scalar(loc)) => vec![loc],
+            Some(GotoDefinitionResponse::Array(arr)) => arr,
+            Some(GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| Location::new(link.target_uri, link.target_selection_range))
+                .collect(),
+        };
+
+        match locations.as_slice() {
+            [] => self.vim()?.echowarn("No parent module found!")?,
+            [location] => self.goto_location(&goto_cmd, location)?,
+            _ => self.present_list(types::ListKind::Locations, "Parent modules", &locations)?,
+        }
+
+        Ok(result)
+    }
+
+    /// Joins the selected lines, or the current line with the next one if no more than a line is
+    /// selected, the way rust-analyzer sees fit, e.g. removing the comma when joining the last
+    /// argument of a call.
+    pub fn rust_analyzer_join_lines(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Joining lines is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let start_line: Option<u64> = try_get("range_start_line", params)?;
+        let end_line: Option<u64> = try_get("range_end_line", params)?;
+        let range = match (start_line, end_line) {
+            (Some(start_line), Some(end_line)) if end_line > start_line + 1 => Range {
+                start: Position::new(start_line, 0),
+                end: Position::new(end_line - 1, 0),
+            },
+            // An empty range joins the line with the next one.
+            _ => {
+                let position = self.get_position(&filename, params)?;
+                Range::new(position, position)
+            }
+        };
+
+        let edits: Vec<TextEdit> = self.get_client(&Some(language_id))?.call(
+            request::JoinLines::METHOD,
+            JoinLinesParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                ranges: vec![range],
+            },
+        )?;
+
+        if !edits.is_empty() {
+            let position = self.vim()?.get_position(params)?;
+            let position = self.apply_text_edits(&filename, &edits, position)?;
+            self.vim()?
+                .cursor(position.line + 1, position.character + 1)?;
+            self.text_document_did_change(params)?;
+        }
+
+        Ok(serde_json::to_value(edits)?)
+    }
+
+    /// Replaces the code matching a structural search and replace rule, like
+    /// `foo($a) ==>> bar($a)`, across the workspace. Paths in the rule are resolved from the
+    /// current position.
+    pub fn rust_analyzer_ssr(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Structural search and replace is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let query = match try_get::<String>("query", params)? {
+            Some(query) => query,
+            None => self
+                .vim()?
+                .rpcclient
+                .call("s:getInput", ["SSR rule: ", ""])?,
+        };
+        if query.is_empty() {
+            return Ok(Value::Null);
+        }
+
+        let position = self.get_position(&filename, params)?;
+        let result: Result<WorkspaceEdit> = self.get_client(&Some(language_id))?.call(
+            request::Ssr::METHOD,
+            SsrParams {
+                query,
+                parse_only: false,
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                position,
+                selections: vec![],
+            },
+        );
+
+        // Errors in the rule are reported by the server as errors of the request, whose message
+        // starts with "Parse error".
+        let edit = match result {
+            Ok(edit) => edit,
+            Err(err) => match err.downcast_ref::<jsonrpc_core::Error>() {
+                Some(error) if error.message.starts_with("Parse error") => {
+                    self.vim()?
+                        .echoerr(format!("Invalid SSR rule: {}", error.message))?;
+                    return Ok(Value::Null);
+                }
+                _ => return Err(err),
+            },
+        };
+
+        self.apply_workspace_edit(&edit)?;
+        Ok(serde_json::to_value(edit)?)
+    }
+
+    /// Makes rust-analyzer reload the workspace, e.g. after a Cargo.toml has been edited.
+    pub fn rust_analyzer_reload_workspace(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Reloading the workspace is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        self.get_client(&Some(language_id))?
+            .call::<Value>(request::ReloadWorkspace::METHOD, Value::Null)?;
+        self.vim()?.echomsg("Workspace reloaded")?;
+
+        Ok(Value::Null)
+    }
+
+    /// Shows the status of rust-analyzer, including the status of the current file, in the
+    /// preview window.
+    pub fn rust_analyzer_analyzer_status(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Showing the analyzer status is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let status: String = self.get_client(&Some(language_id))?.call(
+            request::AnalyzerStatus::METHOD,
+            AnalyzerStatusParams {
+                text_document: Some(TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                }),
+            },
+        )?;
+
+        self.preview(status.as_str(), "__LCNAnalyzerStatus__")?;
+        Ok(Value::String(status))
+    }
+
+    /// Shows the syntax tree of the selected lines, or of the whole file if none are selected, in
+    /// a scratch buffer.
+    pub fn rust_analyzer_syntax_tree(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Showing the syntax tree is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let start_line: Option<u64> = try_get("range_start_line", params)?;
+        let end_line: Option<u64> = try_get("range_end_line", params)?;
+        let range = match (start_line, end_line) {
+            (Some(start_line), Some(end_line)) if end_line > start_line => Some(Range {
+                start: Position::new(start_line, 0),
+                end: Position::new(end_line, 0),
+            }),
+            _ => None,
+        };
+
+        let tree: String = self.get_client(&Some(language_id))?.call(
+            request::SyntaxTree::METHOD,
+            SyntaxTreeParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                range,
+            },
+        )?;
+
+        self.show_scratch_buffer(params, "__LCNSyntaxTree__", "rust_syntax_tree", &tree)?;
+        Ok(Value::String(tree))
+    }
+
+    /// Shows the HIR, the high-level intermediate representation rust-analyzer lowers code to, of
+    /// the function at the cursor in a scratch buffer.
+    pub fn rust_analyzer_view_hir(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.is_rust_analyzer(&language_id)? {
+            self.vim()?
+                .echowarn("Showing the HIR is only supported by rust-analyzer")?;
+            return Ok(Value::Null);
+        }
+
+        let position = self.get_position(&filename, params)?;
+        let hir: String = self.get_client(&Some(language_id))?.call(
+            request::ViewHir::METHOD,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                position,
+            },
+        )?;
+
+        self.show_scratch_buffer(params, "__LCNHir__", "rust", &hir)?;
+        Ok(Value::String(hir))
+    }
+
+    /// Opens a buffer not backed by a file, in a split unless told otherwise, replacing its
+    /// contents with the given text.
+    fn show_scratch_buffer(
+        &self,
+        params: &Value,
+        bufname: &str,
+        filetype: &str,
+        text: &str,
+    ) -> Result<()> {
+        let goto_cmd = self
+            .vim()?
+            .get_goto_cmd(params)?
+            .unwrap_or_else(|| "split".to_string());
+        let lines: Vec<String> = text.lines().map(ToString::to_string).collect();
+
+        self.vim()?
+            .rpcclient
+            .notify("s:Edit", json!([goto_cmd, bufname]))?;
+        // Disabled before the filetype is set, so that the buffer isn't opened on the server.
+        self.vim()?.command(vec![
+            format!("let b:{}=1", VIM_BUFFER_DISABLED),
+            format!(
+                "setlocal buftype=nofile bufhidden=hide noswapfile filetype={}",
+                filetype
+            ),
+            "silent %delete _".to_string(),
+        ])?;
+        self.vim()?.setline(1, &lines)?;
+        Ok(())
+    }
+
+    fn goto_location(&self, goto_cmd: &Option<String>, location: &Location) -> Result<()> {
+        let path = location.uri.filepath()?;
+        self.vim()?.edit(goto_cmd, &path)?;
+        let start = self.position_to_bytes(&LineCache::default(), &path, location.range.start)?;
+        self.vim()?.cursor(start.line + 1, start.character + 1)
+    }
+
+    /// Moves the cursor to the position a source change asks for, in the file it was applied to.
+    fn set_cursor_position(&self, cursor_position: &TextDocumentPositionParams) -> Result<()> {
+        let path = cursor_position.text_document.uri.filepath()?;
+        let position =
+            self.position_to_bytes(&LineCache::default(), &path, cursor_position.position)?;
+        self.vim()?
+            .cursor(position.line + 1, position.character + 1)
+    }
+
+    fn is_rust_analyzer(&self, language_id: &str) -> Result<bool> {
+        self.get_state(|state| {
+            state
+                .capabilities
+                .get(language_id)
+                .and_then(|capabilities| capabilities.server_info.as_ref())
+                .map_or(false, |info| info.name == SERVER_NAME)
+        })
+    }
+
     pub fn handle_rust_analyzer_command(&self, cmd: &Command) -> Result<bool> {
         match cmd.command.as_str() {
             command::SHOW_REFERENCES => {
@@ -145,7 +590,7 @@ impl LanguageClient {
                     .unwrap_or_else(|| Value::Array(vec![]));
                 let locations: Vec<Location> = serde_json::from_value(locations)?;
 
-                self.present_list("References", &locations)?;
+                self.present_list(types::ListKind::Locations, "References", &locations)?;
             }
             command::SELECT_APPLY_SOURCE_CHANGE => {
                 if let Some(ref edits) = cmd.arguments {
@@ -154,10 +599,7 @@ impl LanguageClient {
                         for edit in workspace_edits {
                             self.apply_workspace_edit(&edit.workspace_edit)?;
                             if let Some(cursor_position) = edit.cursor_position {
-                                self.vim()?.cursor(
-                                    cursor_position.position.line + 1,
-                                    cursor_position.position.character + 1,
-                                )?;
+                                self.set_cursor_position(&cursor_position)?;
                             }
                         }
                     }
@@ -169,10 +611,7 @@ impl LanguageClient {
                         let edit = WorkspaceEditWithCursor::deserialize(edit)?;
                         self.apply_workspace_edit(&edit.workspace_edit)?;
                         if let Some(cursor_position) = edit.cursor_position {
-                            self.vim()?.cursor(
-                                cursor_position.position.line + 1,
-                                cursor_position.position.character + 1,
-                            )?;
+                            self.set_cursor_position(&cursor_position)?;
                         }
                     }
                 }
@@ -2,14 +2,17 @@ use crate::config::{Config, ServerCommand};
 use crate::extensions::java;
 use crate::language_client::LanguageClient;
 use crate::sign::Sign;
-use crate::vim::{try_get, Mode};
+use crate::vim::{bufexpr, try_get, Mode};
 use crate::{
-    rpcclient::RpcClient,
+    rpcclient::{RequestTimeouts, RpcClient, RpcClientOptions, RpcTrace},
     types::*,
     utils::{
-        apply_text_edits, code_action_kind_as_str, convert_to_vim_str, decode_parameter_label,
-        escape_single_quote, expand_json_path, get_default_initialization_options, get_root_path,
-        vim_cmd_args_to_value, Canonicalize, Combine, ToUrl,
+        adjust_indentation, apply_completion_item_defaults, apply_text_edits, byte_to_character,
+        character_to_byte, code_action_kind_as_str, completion_items_order,
+        completion_label_details, convert_to_vim_str, decode_parameter_label, escape_single_quote,
+        expand_json_path, get_default_initialization_options, get_root_path, markdown_to_text,
+        range_from_bytes, range_to_bytes, strip_snippet_placeholders,
+        substitute_command_placeholders, vim_cmd_args_to_value, Canonicalize, Combine, ToUrl,
     },
     viewport,
     watcher::FSWatch,
@@ -23,38 +26,45 @@ use log::{debug, error, info, warn};
 use lsp_types::{
     notification::Notification, request::Request, ApplyWorkspaceEditParams,
     ApplyWorkspaceEditResponse, ClientCapabilities, ClientInfo, CodeAction, CodeActionCapability,
-    CodeActionContext, CodeActionKind, CodeActionKindLiteralSupport, CodeActionLiteralSupport,
-    CodeActionOrCommand, CodeActionParams, CodeActionResponse, CodeLens, Command,
-    CompletionCapability, CompletionItem, CompletionItemCapability, CompletionResponse,
-    CompletionTextEdit, Diagnostic, DiagnosticSeverity, DidChangeConfigurationParams,
+    CodeActionCapabilityResolveSupport, CodeActionContext, CodeActionKind,
+    CodeActionKindLiteralSupport, CodeActionLiteralSupport, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, CodeActionResponse, CodeLens, Command, CompletionCapability,
+    CompletionItem, CompletionItemCapability, CompletionResponse, CompletionTextEdit,
+    ConfigurationParams, Diagnostic, DiagnosticSeverity, DidChangeConfigurationParams,
     DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
     DidChangeWatchedFilesRegistrationOptions, DidCloseTextDocumentParams,
     DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentChangeOperation, DocumentChanges,
-    DocumentFormattingParams, DocumentHighlight, DocumentHighlightKind,
-    DocumentRangeFormattingParams, DocumentSymbolParams, DocumentSymbolResponse, Documentation,
-    ExecuteCommandParams, FormattingOptions, GenericCapability, GotoCapability,
-    GotoDefinitionResponse, Hover, HoverCapability, InitializeParams, InitializeResult,
-    InitializedParams, Location, LogMessageParams, MessageType, NumberOrString,
-    ParameterInformation, ParameterInformationSettings, PartialResultParams, Position,
-    ProgressParams, ProgressParamsValue, PublishDiagnosticsClientCapabilities,
-    PublishDiagnosticsParams, Range, ReferenceContext, RegistrationParams, RenameParams,
-    ResourceOp, SemanticHighlightingClientCapability, SemanticHighlightingParams,
-    ShowMessageParams, ShowMessageRequestParams, SignatureHelp, SignatureHelpCapability,
-    SignatureInformationSettings, SymbolInformation, TextDocumentClientCapabilities,
-    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
-    TextDocumentPositionParams, TextEdit, UnregistrationParams, VersionedTextDocumentIdentifier,
-    WorkDoneProgress, WorkDoneProgressParams, WorkspaceClientCapabilities, WorkspaceEdit,
-    WorkspaceSymbolParams,
+    DocumentFormattingParams, DocumentHighlight, DocumentHighlightKind, DocumentLink,
+    DocumentLinkClientCapabilities, DocumentLinkParams, DocumentRangeFormattingParams,
+    DocumentSymbolParams, DocumentSymbolResponse, Documentation, ExecuteCommandParams,
+    FormattingOptions, GenericCapability, GotoCapability, GotoDefinitionResponse, Hover,
+    HoverCapability, HoverContents, InitializeParams, InitializeResult, InitializedParams,
+    InsertReplaceEdit, InsertTextFormat, InsertTextMode, InsertTextModeSupport, LanguageString,
+    Location, LogMessageParams, MarkedString, MarkupContent, MarkupKind, MessageType,
+    NumberOrString, ParameterInformation, ParameterInformationSettings, PartialResultParams,
+    Position, ProgressParams, ProgressParamsValue, PublishDiagnosticsClientCapabilities,
+    PublishDiagnosticsParams, Range, ReferenceContext, RegistrationParams, RenameCapability,
+    RenameParams, ResourceOp, SelectionRange, SelectionRangeClientCapabilities,
+    SelectionRangeParams, SemanticHighlightingClientCapability, SemanticHighlightingParams,
+    ServerCapabilities, ShowMessageParams, ShowMessageRequestParams, SignatureHelp,
+    SignatureHelpCapability, SignatureInformationSettings, SymbolInformation, SymbolKind,
+    SynchronizationCapability, TextDocumentClientCapabilities, TextDocumentContentChangeEvent,
+    TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams, TextDocumentSaveReason,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncSaveOptions, TextEdit,
+    TraceOption, TypeDefinitionProviderCapability, UnregistrationParams, Url,
+    VersionedTextDocumentIdentifier, WillSaveTextDocumentParams, WorkDoneProgress,
+    WorkDoneProgressParams, WorkspaceClientCapabilities, WorkspaceEdit, WorkspaceSymbolParams,
 };
 use maplit::hashmap;
+use pathdiff::diff_paths;
 use serde::de::Deserialize;
 use serde_json::json;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::{read_to_string, File},
     io::{BufRead, BufReader, BufWriter},
     net::TcpStream,
-    path::Path,
+    path::{Path, PathBuf},
     process::Stdio,
     sync::{mpsc, Arc, MutexGuard},
     thread,
@@ -130,7 +140,7 @@ impl LanguageClient {
         Ok(())
     }
 
-    fn get_workspace_settings(&self, root: &str) -> Result<Value> {
+    fn get_workspace_settings(&self, language_id: &str, root: &str) -> Result<Value> {
         if !self.get_config(|c| c.load_settings)? {
             return Ok(Value::Null);
         }
@@ -138,7 +148,13 @@ impl LanguageClient {
         let mut res = Value::Null;
         let mut last_err = None;
         let mut at_least_one_success = false;
-        for orig_path in self.get_config(|c| c.settings_path.clone())? {
+        let settings_path = self.get_config(|c| {
+            c.settings_path_by_language
+                .get(language_id)
+                .unwrap_or(&c.settings_path)
+                .clone()
+        })?;
+        for orig_path in settings_path {
             let path = Path::new(root).join(orig_path);
             let buffer = read_to_string(&path)
                 .with_context(|| format!("Failed to read file ({})", path.to_string_lossy()));
@@ -187,27 +203,27 @@ impl LanguageClient {
     pub fn apply_workspace_edit(&self, edit: &WorkspaceEdit) -> Result<()> {
         let mut filename = self.vim()?.get_filename(&Value::Null)?;
         let mut position = self.vim()?.get_position(&Value::Null)?;
+
+        // Edits are grouped by file, so that each buffer is only fetched and written back once, no
+        // matter how many text document edits target it. Within a file, the edits are still applied
+        // in the order they were sent.
+        let mut file_edits = vec![];
+
         if let Some(ref changes) = edit.document_changes {
             match changes {
                 DocumentChanges::Edits(ref changes) => {
                     for e in changes {
-                        position = self.apply_text_edits(
-                            &e.text_document.uri.filepath()?,
-                            &e.edits,
-                            position,
-                        )?;
+                        add_file_edits(&mut file_edits, e.text_document.uri.filepath()?, &e.edits);
                     }
                 }
                 DocumentChanges::Operations(ref ops) => {
                     for op in ops {
                         match op {
-                            DocumentChangeOperation::Edit(ref e) => {
-                                position = self.apply_text_edits(
-                                    &e.text_document.uri.filepath()?,
-                                    &e.edits,
-                                    position,
-                                )?
-                            }
+                            DocumentChangeOperation::Edit(ref e) => add_file_edits(
+                                &mut file_edits,
+                                e.text_document.uri.filepath()?,
+                                &e.edits,
+                            ),
                             DocumentChangeOperation::Op(ref rop) => match rop {
                                 ResourceOp::Create(file) => {
                                     filename = file.uri.filepath()?.to_string_lossy().into_owned();
@@ -226,21 +242,69 @@ impl LanguageClient {
             }
         } else if let Some(ref changes) = edit.changes {
             for (uri, edits) in changes {
-                position = self.apply_text_edits(&uri.filepath()?, edits, position)?;
+                add_file_edits(&mut file_edits, uri.filepath()?, edits);
             }
         }
+
+        for (path, batches) in &file_edits {
+            position = self.apply_text_edit_batches(path, batches, position)?;
+        }
         self.edit(&None, &filename)?;
         self.vim()?
             .cursor(position.line + 1, position.character + 1)?;
+
+        if !file_edits.is_empty() {
+            let paths: Vec<_> = file_edits
+                .iter()
+                .map(|(path, _)| path.to_string_lossy())
+                .collect();
+            self.vim()?.command(format!(
+                "let {}={}",
+                VIM_WORKSPACE_EDIT_FILES,
+                serde_json::to_string(&paths)?
+            ))?;
+            self.vim()?.rpcclient.notify(
+                "s:ExecuteAutocmd",
+                json!(["LanguageClientWorkspaceEditApplied", paths]),
+            )?;
+        }
         Ok(())
     }
 
     #[tracing::instrument(level = "info", skip(self))]
     pub fn text_document_document_highlight(&self, params: &Value) -> Result<Value> {
-        self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(&Value::Null)?;
+
+        let throttle = self.get_config(|c| c.document_highlight_throttle)?;
+        let skip_request = self.update_state(|state| {
+            let last = state.document_highlight_requests.get(&filename);
+            if let (Some(throttle), Some(last)) = (throttle, last) {
+                if last.elapsed() < throttle {
+                    return Ok(true);
+                }
+            }
+            state
+                .document_highlight_requests
+                .insert(filename.clone(), Instant::now());
+            Ok(false)
+        })?;
+        if skip_request {
+            info!("Skip documentHighlight due to throttling");
+            // the highlights were computed for a previous cursor position, so they are stale.
+            self.vim()?.clear_highlights("__LCN_DOCUMENT_HIGHLIGHT__")?;
+            return Ok(Value::Null);
+        }
+
+        self.text_document_did_change(params)?;
         let language_id = self.vim()?.get_language_id(&filename, &Value::Null)?;
-        let position = self.vim()?.get_position(&Value::Null)?;
+        if !self.check_provider(
+            &language_id,
+            lsp_types::request::DocumentHighlightRequest::METHOD,
+            params,
+        )? {
+            return Ok(Value::Null);
+        }
+        let position = self.get_position(&filename, &Value::Null)?;
 
         let result = self.get_client(&Some(language_id))?.call(
             lsp_types::request::DocumentHighlightRequest::METHOD,
@@ -294,29 +358,69 @@ impl LanguageClient {
         self.vim()?.clear_highlights("__LCN_DOCUMENT_HIGHLIGHT__")
     }
 
+    /// Keeps track of a hover window pinned by vim, to close it along with the others.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn pin_hover(&self, params: &Value) -> Result<()> {
+        let winid: u64 = try_get("winid", params)?.ok_or_else(|| anyhow!("winid not found!"))?;
+        self.update_state(|state| {
+            state.pinned_hovers.push(winid);
+            Ok(())
+        })
+    }
+
+    /// Closes the hover windows that were pinned.
     #[tracing::instrument(level = "info", skip(self))]
-    fn apply_text_edits<P: AsRef<Path> + std::fmt::Debug>(
+    pub fn close_pinned_hovers(&self, _params: &Value) -> Result<()> {
+        let winids = self.update_state(|state| Ok(std::mem::take(&mut state.pinned_hovers)))?;
+        if winids.is_empty() {
+            return Ok(());
+        }
+
+        self.vim()?
+            .rpcclient
+            .notify("s:ClosePinnedHovers", json!([winids]))
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn apply_text_edits<P: AsRef<Path> + std::fmt::Debug>(
         &self,
         path: P,
         edits: &[TextEdit],
         position: Position,
     ) -> Result<Position> {
-        if edits.is_empty() {
+        self.apply_text_edit_batches(path, &[edits], position)
+    }
+
+    /// Applies several batches of edits to the same file, one batch after the other, fetching
+    /// and writing back the buffer contents only once.
+    #[tracing::instrument(level = "info", skip(self))]
+    fn apply_text_edit_batches<P: AsRef<Path> + std::fmt::Debug>(
+        &self,
+        path: P,
+        batches: &[&[TextEdit]],
+        position: Position,
+    ) -> Result<Position> {
+        if batches.iter().all(|edits| edits.is_empty()) {
             return Ok(position);
         }
 
-        let mut edits = edits.to_vec();
-
-        // Edits must be applied from bottom to top, so that earlier edits will not interfere
-        // with the positioning of later edits. Edits that start with the same position must be
-        // applied in reverse order, so that multiple inserts will have their text appear in the
-        // same order the server sent it, and so that a delete/replace (according to the LSP spec,
-        // there can only be one per start position and it must be after the inserts) will work on
-        // the original document, not on the just-inserted text.
-        edits.sort_by_key(|edit| (edit.range.start.line, edit.range.start.character));
-        edits.reverse();
-
-        self.edit(&None, path)?;
+        self.edit(&None, &path)?;
+        let encoding = self.position_encoding(&path.as_ref().to_string_lossy())?;
+
+        // Edits confined to a part of the buffer only fetch and rewrite the lines they touch, which
+        // matters for large files. This is limited to a single batch, as the ranges of a batch
+        // refer to the buffer as left by the previous ones, and to edits not touching the last
+        // line, so that the end of the file needs no special treatment.
+        if let [edits] = batches {
+            let start = edits.iter().map(|edit| edit.range.start.line).min();
+            let end = edits.iter().map(|edit| edit.range.end.line).max();
+            let line_count: u64 = self.vim()?.eval("line('$')")?;
+            if let (Some(start), Some(end)) = (start, end) {
+                if end + 1 < line_count {
+                    return self.apply_text_edits_in_window(edits, start, end, position, encoding);
+                }
+            }
+        }
 
         let mut lines: Vec<String> = self.vim()?.rpcclient.call("getline", json!([1, '$']))?;
         let lines_len_prev = lines.len();
@@ -325,23 +429,105 @@ impl LanguageClient {
             lines.push("".to_owned());
         }
 
-        let (mut lines, position) = apply_text_edits(&lines, &edits, &position)?;
+        let mut position = position;
+        for edits in batches {
+            let mut edits: Vec<_> = edits
+                .iter()
+                .map(|edit| {
+                    TextEdit::new(
+                        range_to_bytes(&lines, &edit.range, encoding),
+                        edit.new_text.clone(),
+                    )
+                })
+                .collect();
+
+            sort_text_edits(&mut edits);
+
+            let (new_lines, new_position) = apply_text_edits(&lines, &edits, &position)?;
+            lines = new_lines;
+            position = new_position;
+        }
 
         if lines.last().map(String::is_empty) == Some(true) && fixendofline {
             lines.pop();
         }
         if lines.len() < lines_len_prev {
             self.vim()?
-                .command(format!("{},{}d", lines.len() + 1, lines_len_prev))?;
+                .command(format!("{},{}d _", lines.len() + 1, lines_len_prev))?;
         }
         self.vim()?.rpcclient.notify("setline", json!([1, lines]))?;
         Ok(position)
     }
 
+    /// Applies edits to the lines from `start` to `end` of the current buffer, fetching and
+    /// writing back only those lines.
+    fn apply_text_edits_in_window(
+        &self,
+        edits: &[TextEdit],
+        start: u64,
+        end: u64,
+        position: Position,
+        encoding: PositionEncoding,
+    ) -> Result<Position> {
+        let lines: Vec<String> = self
+            .vim()?
+            .rpcclient
+            .call("getline", json!([start + 1, end + 1]))?;
+
+        let mut edits: Vec<_> = edits
+            .iter()
+            .map(|edit| {
+                let range = Range::new(
+                    Position::new(edit.range.start.line - start, edit.range.start.character),
+                    Position::new(edit.range.end.line - start, edit.range.end.character),
+                );
+                TextEdit::new(
+                    range_to_bytes(&lines, &range, encoding),
+                    edit.new_text.clone(),
+                )
+            })
+            .collect();
+        sort_text_edits(&mut edits);
+
+        let in_window = position.line >= start && position.line <= end;
+        let window_position = if in_window {
+            Position::new(position.line - start, position.character)
+        } else {
+            Position::default()
+        };
+        let (new_lines, new_position) = apply_text_edits(&lines, &edits, &window_position)?;
+
+        let (old_len, new_len) = (lines.len() as u64, new_lines.len() as u64);
+        if new_len < old_len {
+            self.vim()?
+                .command(format!("{},{}d _", start + new_len + 1, start + old_len))?;
+        }
+        let (replaced, appended) = new_lines.split_at(std::cmp::min(old_len, new_len) as usize);
+        if !replaced.is_empty() {
+            self.vim()?
+                .rpcclient
+                .notify("setline", json!([start + 1, replaced]))?;
+        }
+        if !appended.is_empty() {
+            self.vim()?
+                .rpcclient
+                .notify("append", json!([start + old_len, appended]))?;
+        }
+
+        let position = if in_window {
+            Position::new(new_position.line + start, new_position.character)
+        } else if position.line > end {
+            Position::new(position.line + new_len - old_len, position.character)
+        } else {
+            position
+        };
+        Ok(position)
+    }
+
     // moves the cursor to the next or previous diagnostic, depending on the value of direction.
     pub fn cycle_diagnostics(&self, params: &Value, direction: Direction) -> Result<()> {
         let filename = self.vim()?.get_filename(params)?;
-        let pos = self.vim()?.get_position(params)?;
+        let pos = self.get_position(&filename, params)?;
         let mut diagnostics = self.get_state(|state| state.diagnostics.clone())?;
         if let Some(diagnostics) = diagnostics.get_mut(&filename) {
             if direction == Direction::Next {
@@ -364,9 +550,8 @@ impl LanguageClient {
                     start.line < line || (start.line == line && start.character < col)
                 }
             }) {
-                let line = diagnostic.range.start.line + 1;
-                let col = diagnostic.range.start.character + 1;
-                self.vim()?.cursor(line, col)?;
+                let start = self.range_to_bytes(&filename, &diagnostic.range)?.start;
+                self.vim()?.cursor(start.line + 1, start.character + 1)?;
             } else {
                 self.vim()?.echomsg("No diagnostics found")?;
             }
@@ -379,42 +564,141 @@ impl LanguageClient {
 
     fn update_quickfixlist(&self) -> Result<()> {
         let diagnostics = self.get_state(|state| state.diagnostics.clone())?;
-        let qflist: Vec<_> = diagnostics
-            .iter()
-            .flat_map(|(filename, diagnostics)| {
-                diagnostics
-                    .iter()
-                    .map(|dn| QuickfixEntry {
-                        filename: filename.to_owned(),
-                        lnum: dn.range.start.line + 1,
-                        col: Some(dn.range.start.character + 1),
-                        nr: dn.code.clone().map(|ns| ns.to_string()),
-                        text: Some(dn.message.to_owned()),
-                        typ: dn.severity.map(|sev| sev.to_quickfix_entry_type()),
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .collect();
+        let (default_list, list_by_source) =
+            self.get_config(|c| (c.diagnostics_list, c.diagnostics_list_by_source.clone()))?;
+
+        // Diagnostics are routed to a list based on their source, falling back to the default
+        // diagnostics list.
+        let mut qflist = vec![];
+        let mut loclist = vec![];
+        let hidden = self.get_state(|state| state.diagnostics_hidden.clone())?;
+        for (filename, diagnostics) in diagnostics.iter() {
+            if hidden.contains(filename) {
+                continue;
+            }
+
+            let lines = self.document_lines(filename)?;
+            let encoding = self.position_encoding(filename)?;
+            for dn in diagnostics {
+                let start = range_to_bytes(&lines, &dn.range, encoding).start;
+                let list = dn
+                    .source
+                    .as_ref()
+                    .and_then(|source| list_by_source.get(source))
+                    .copied()
+                    .unwrap_or(default_list);
+                let entry = QuickfixEntry {
+                    filename: filename.to_owned(),
+                    lnum: start.line + 1,
+                    col: Some(start.character + 1),
+                    nr: dn.code.clone().map(|ns| ns.to_string()),
+                    text: Some(dn.message.to_owned()),
+                    typ: dn.severity.map(|sev| sev.to_quickfix_entry_type()),
+                    valid: None,
+                };
+                match list {
+                    DiagnosticsList::Quickfix => qflist.push(entry),
+                    DiagnosticsList::Location => loclist.push(entry),
+                    DiagnosticsList::Disabled => {}
+                }
+            }
+        }
+        // Diagnostics are stored in a HashMap, sort them so the lists don't get shuffled every
+        // time diagnostics are published.
+        sort_quickfix_entries(&mut qflist);
+        sort_quickfix_entries(&mut loclist);
 
         let title = "[LC]: diagnostics";
+        let reuse = self.get_config(|c| c.diagnostics_list_reuse)?;
+        // Only touch the lists that diagnostics can end up in, so that other lists are preserved.
+        let uses_list = |list: DiagnosticsList| {
+            default_list == list || list_by_source.values().any(|l| *l == list)
+        };
 
-        match self.get_config(|c| c.diagnostics_list)? {
-            DiagnosticsList::Quickfix => {
+        if uses_list(DiagnosticsList::Quickfix) {
+            if reuse {
+                self.reuse_diagnostics_list(false, &qflist, title)?;
+            } else {
                 self.vim()?.setqflist(&qflist, "r", title)?;
             }
-            DiagnosticsList::Location => {
-                self.vim()?.setloclist(&qflist, "r", title)?;
+            // The window is only opened when the list is first populated, so that it doesn't
+            // steal the focus every time diagnostics are published while typing.
+            let populated = self.update_state(|state| {
+                let was_populated = state.diagnostics_qflist_populated;
+                state.diagnostics_qflist_populated = !qflist.is_empty();
+                Ok(was_populated)
+            })?;
+            if !populated && self.should_open_list(ListKind::Diagnostics, qflist.len())? {
+                self.vim()?.command("botright cwindow")?;
+            }
+        }
+
+        if uses_list(DiagnosticsList::Location) {
+            if reuse {
+                self.reuse_diagnostics_list(true, &loclist, title)?;
+            } else {
+                self.vim()?.setloclist(&loclist, "r", title)?;
+            }
+            let winid: u64 = self.vim()?.eval("win_getid()")?;
+            let populated = self.update_state(|state| {
+                let was_populated = if loclist.is_empty() {
+                    state.diagnostics_loclists_populated.remove(&winid)
+                } else {
+                    !state.diagnostics_loclists_populated.insert(winid)
+                };
+                Ok(was_populated)
+            })?;
+            if !populated && self.should_open_list(ListKind::Diagnostics, loclist.len())? {
+                self.vim()?.command("lwindow")?;
             }
-            DiagnosticsList::Disabled => {}
         }
 
         Ok(())
     }
 
+    /// Updates the list previously populated with diagnostics in place, leaving any other list in
+    /// the quickfix/location list stack untouched. Location lists are tracked per window.
+    fn reuse_diagnostics_list(
+        &self,
+        loclist: bool,
+        list: &[QuickfixEntry],
+        title: &str,
+    ) -> Result<()> {
+        if !loclist {
+            let id = self.get_state(|state| state.diagnostics_qflist_id)?;
+            let id = self.vim()?.set_list_by_id(false, list, title, id)?;
+            return self.update_state(|state| {
+                state.diagnostics_qflist_id = id;
+                Ok(())
+            });
+        }
+
+        let winid: u64 = self.vim()?.eval("win_getid()")?;
+        let id = self.get_state(|state| {
+            state
+                .diagnostics_loclist_ids
+                .get(&winid)
+                .copied()
+                .unwrap_or_default()
+        })?;
+        let id = self.vim()?.set_list_by_id(true, list, title, id)?;
+        self.update_state(|state| {
+            state.diagnostics_loclist_ids.insert(winid, id);
+            Ok(())
+        })
+    }
+
     fn process_diagnostics(&self, filename: &str, diagnostics: &[Diagnostic]) -> Result<()> {
         if !self.get_state(|state| state.text_documents.contains_key(filename))? {
             return Ok(());
         }
+        // Hidden diagnostics are processed as no diagnostics, which clears them.
+        let diagnostics: &[Diagnostic] =
+            if self.get_state(|state| state.diagnostics_hidden.contains(filename))? {
+                &[]
+            } else {
+                diagnostics
+            };
 
         let text = self.get_state(|state| {
             state
@@ -424,6 +708,7 @@ impl LanguageClient {
                 .unwrap_or_default()
         })?;
         let lines: Vec<_> = text.lines().map(ToOwned::to_owned).collect();
+        let encoding = self.position_encoding(filename)?;
 
         // Line diagnostics.
         let mut line_diagnostics = HashMap::new();
@@ -455,9 +740,10 @@ impl LanguageClient {
 
         let mut highlights = vec![];
         for dn in diagnostics {
-            let line = dn.range.start.line;
-            let character_start = dn.range.start.character;
-            let character_end = dn.range.end.character;
+            let range = range_to_bytes(&lines, &dn.range, encoding);
+            let line = range.start.line;
+            let character_start = range.start.character;
+            let character_end = range.end.character;
 
             let severity = dn.severity.unwrap_or(DiagnosticSeverity::Hint);
             let group = diagnostics_display
@@ -515,38 +801,7 @@ impl LanguageClient {
                     .clone();
                 let ranges: Vec<Vec<_>> = dns
                     .iter()
-                    .flat_map(|dn| {
-                        if dn.range.start.line == dn.range.end.line {
-                            let length = dn.range.end.character - dn.range.start.character;
-                            // Vim line numbers are 1 off
-                            // `matchaddpos` expects an array of [line, col, length]
-                            // for each match.
-                            vec![vec![
-                                dn.range.start.line + 1,
-                                dn.range.start.character + 1,
-                                length,
-                            ]]
-                        } else {
-                            let mut middle_lines: Vec<_> = (dn.range.start.line + 1
-                                ..dn.range.end.line)
-                                .map(|l| vec![l + 1])
-                                .collect();
-                            let start_line = vec![
-                                dn.range.start.line + 1,
-                                dn.range.start.character + 1,
-                                999_999, //Clear to the end of the line
-                            ];
-                            let end_line =
-                                vec![dn.range.end.line + 1, 1, dn.range.end.character + 1];
-                            middle_lines.push(start_line);
-                            // For a multi-ringe range ending at the exact start of the last line,
-                            // don't highlight the first character of the last line.
-                            if dn.range.end.character > 0 {
-                                middle_lines.push(end_line);
-                            }
-                            middle_lines
-                        }
-                    })
+                    .flat_map(|dn| match_positions(&range_to_bytes(&lines, &dn.range, encoding)))
                     .collect();
 
                 let match_id = self
@@ -564,6 +819,23 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Registers the server as a completion source with NCM and NCM2, if either is loaded. Done
+    /// again whenever the server registers completion dynamically, so the trigger characters it
+    /// declares then are picked up.
+    fn register_completion_sources(&self, language_id: &str, result: &Value) -> Result<()> {
+        if let Err(e) = self.register_cm_source(language_id, result) {
+            let message = format!("LanguageClient: failed to register as NCM source: {}", e);
+            error!("{}\n{:?}", message, e);
+            self.vim()?.echoerr(&message)?;
+        }
+        if let Err(e) = self.register_ncm2_source(language_id, result) {
+            let message = format!("LanguageClient: failed to register as NCM source: {}", e);
+            error!("{}\n{:?}", message, e);
+            self.vim()?.echoerr(&message)?;
+        }
+        Ok(())
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     fn register_cm_source(&self, language_id: &str, result: &Value) -> Result<()> {
         let exists_cm_register: u64 = self.vim()?.eval("exists('g:cm_matcher')")?;
@@ -677,7 +949,7 @@ impl LanguageClient {
                 let scope_str = scope_list.iter().join(&scope_separator);
 
                 let mut matched = false;
-                for (scope_regex, hl_group) in &shm {
+                for (scope_regex, hl_group) in sorted_semantic_highlight_map(&shm) {
                     let match_expr = format!(
                         "({} =~ {})",
                         convert_to_vim_str(&scope_str),
@@ -713,10 +985,11 @@ impl LanguageClient {
         Ok(())
     }
 
-    pub fn get_line(&self, path: impl AsRef<Path>, line: u64) -> Result<String> {
+    /// Returns a line of a file, from its buffer if it's loaded or else from disk.
+    pub fn get_raw_line(&self, path: impl AsRef<Path>, line: u64) -> Result<String> {
         let value: Value = self.vim()?.rpcclient.call(
             "getbufline",
-            json!([path.as_ref().to_string_lossy(), line + 1]),
+            json!([bufexpr(&path.as_ref().to_string_lossy()), line + 1]),
         )?;
         let mut texts = <Vec<String>>::deserialize(value)?;
         let mut text = texts.pop().unwrap_or_default();
@@ -729,7 +1002,198 @@ impl LanguageClient {
                 .ok_or_else(|| anyhow!("Failed to get line! line: {}", line))??;
         }
 
-        Ok(text.trim().into())
+        Ok(text)
+    }
+
+    /// Runs `f` with a cache of the lines it reads with `get_cached_line`, so that building a list
+    /// of many locations in the same file fetches the file only once, instead of once per
+    /// location. The cache is local to `f`, as lists may be built concurrently.
+    fn with_line_cache<T>(&self, f: impl FnOnce(&LineCache) -> Result<T>) -> Result<T> {
+        f(&LineCache::default())
+    }
+
+    /// Same as get_raw_line, but reading the whole file into the line cache the first time one
+    /// of its lines is requested.
+    pub fn get_cached_line(&self, cache: &LineCache, path: &Path, line: u64) -> Result<String> {
+        let filename = path.to_string_lossy().into_owned();
+        let index = line.to_usize()?;
+
+        let cached = cache
+            .files
+            .borrow()
+            .get(&filename)
+            .map(|lines| lines.buffer.get(index).cloned().unwrap_or_default());
+        let text = match cached {
+            Some(text) => text,
+            None => {
+                let lines: Vec<String> = self
+                    .vim()?
+                    .rpcclient
+                    .call("getbufline", json!([bufexpr(&filename), 1, "$"]))?;
+                let text = lines.get(index).cloned().unwrap_or_default();
+                cache
+                    .files
+                    .borrow_mut()
+                    .entry(filename.clone())
+                    .or_default()
+                    .buffer = lines;
+                text
+            }
+        };
+        if !text.is_empty() {
+            return Ok(text);
+        }
+
+        let cached = cache
+            .files
+            .borrow()
+            .get(&filename)
+            .and_then(|lines| lines.disk.as_ref())
+            .map(|lines| lines.get(index).cloned());
+        let text = match cached {
+            Some(text) => text,
+            None => {
+                let lines: Vec<String> = std::fs::read_to_string(path)?
+                    .lines()
+                    .map(ToOwned::to_owned)
+                    .collect();
+                let text = lines.get(index).cloned();
+                cache
+                    .files
+                    .borrow_mut()
+                    .entry(filename.clone())
+                    .or_default()
+                    .disk = Some(lines);
+                text
+            }
+        };
+        text.ok_or_else(|| anyhow!("Failed to get line! line: {}", line))
+    }
+
+    /// Returns the position encoding used by the server of the given file. The language of a file
+    /// that isn't open is the filetype of its buffer if it has one, or else that of an open file
+    /// with the same extension.
+    pub fn position_encoding(&self, filename: &str) -> Result<PositionEncoding> {
+        let (encoding, encodings) = self.get_state(|state| {
+            let encoding = state
+                .text_documents
+                .get(filename)
+                .and_then(|document| state.position_encodings.get(&document.language_id))
+                .copied();
+            (encoding, state.position_encodings.clone())
+        })?;
+        if let Some(encoding) = encoding {
+            return Ok(encoding);
+        }
+
+        let mut values = encodings.values();
+        let first = values.next().copied().unwrap_or_default();
+        if values.all(|encoding| *encoding == first) {
+            return Ok(first);
+        }
+
+        let mut language_id = self.vim()?.get_language_id(filename, &Value::Null)?;
+        if language_id.is_empty() {
+            let extension = Path::new(filename).extension();
+            language_id = self.get_state(|state| {
+                state
+                    .text_documents
+                    .iter()
+                    .find(|(name, _)| {
+                        extension.is_some() && Path::new(name).extension() == extension
+                    })
+                    .map(|(_, document)| document.language_id.clone())
+                    .unwrap_or_default()
+            })?;
+        }
+
+        Ok(encodings.get(&language_id).copied().unwrap_or_default())
+    }
+
+    /// Converts a position sent by the server of a file into one with a byte offset, as Vim
+    /// columns are, reading the line from the buffer of the file or else from disk.
+    pub fn position_to_bytes(
+        &self,
+        lines: &LineCache,
+        path: &Path,
+        position: Position,
+    ) -> Result<Position> {
+        let encoding = self.position_encoding(&path.to_string_lossy())?;
+        if encoding == PositionEncoding::Utf8 {
+            return Ok(position);
+        }
+
+        let line = self
+            .get_cached_line(lines, path, position.line)
+            .unwrap_or_default();
+        Ok(Position::new(
+            position.line,
+            character_to_byte(&line, position.character, encoding),
+        ))
+    }
+
+    /// Returns the lines of a document as last sent to its server.
+    fn document_lines(&self, filename: &str) -> Result<Vec<String>> {
+        self.get_state(|state| {
+            state
+                .text_documents
+                .get(filename)
+                .map(|document| document.text.lines().map(ToOwned::to_owned).collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Same as `Vim::get_position`, but with the character offset in the encoding of the server
+    /// of the file instead of in bytes.
+    pub fn get_position(&self, filename: &str, params: &Value) -> Result<Position> {
+        let position = self.vim()?.get_position(params)?;
+        let range = self.range_from_bytes(filename, &Range::new(position, position))?;
+        Ok(range.start)
+    }
+
+    /// Converts a range sent by the server of the file into a range of byte offsets.
+    pub fn range_to_bytes(&self, filename: &str, range: &Range) -> Result<Range> {
+        let encoding = self.position_encoding(filename)?;
+        if encoding == PositionEncoding::Utf8 {
+            return Ok(*range);
+        }
+
+        Ok(range_to_bytes(
+            &self.document_lines(filename)?,
+            range,
+            encoding,
+        ))
+    }
+
+    /// Converts a range of byte offsets into a range in the encoding of the server of the file.
+    pub fn range_from_bytes(&self, filename: &str, range: &Range) -> Result<Range> {
+        let encoding = self.position_encoding(filename)?;
+        if encoding == PositionEncoding::Utf8 {
+            return Ok(*range);
+        }
+
+        Ok(range_from_bytes(
+            &self.document_lines(filename)?,
+            range,
+            encoding,
+        ))
+    }
+
+    /// Whether to run a command sent by the server, because it matches the allowed commands or
+    /// because the user agreed to run it when asked.
+    fn confirm_command(&self, command: &str) -> Result<bool> {
+        let allowed_commands = self.get_config(|c| c.allowed_commands.clone())?;
+        if is_command_allowed(&allowed_commands, command) {
+            return Ok(true);
+        }
+
+        let options = vec![
+            format!("Run command {}?", command),
+            "1. Yes".to_string(),
+            "2. No".to_string(),
+        ];
+        let index: Option<usize> = self.vim()?.rpcclient.call("s:inputlist", options)?;
+        Ok(index == Some(1))
     }
 
     fn try_handle_command_by_client(&self, cmd: &Command) -> Result<bool> {
@@ -778,12 +1242,12 @@ impl LanguageClient {
         })?;
 
         for f in filenames {
-            if let Ok(bufnr) = self.vim()?.eval::<_, Bufnr>(format!("bufnr('{}')", f)) {
+            if let Ok(bufnr) = self.vim()?.get_bufnr(&f, &Value::Null) {
                 // Some Language Server diagnoses non-opened buffer, so we must check if buffer exists.
                 if bufnr > 0 {
                     self.vim()?.rpcclient.notify(
                         "setbufvar",
-                        json!([f, VIM_STATUS_LINE_DIAGNOSTICS_COUNTS, {}]),
+                        json!([bufnr, VIM_STATUS_LINE_DIAGNOSTICS_COUNTS, {}]),
                     )?;
                 }
             }
@@ -796,6 +1260,11 @@ impl LanguageClient {
             state.last_cursor_line = 0;
             state.text_documents.retain(|f, _| !f.starts_with(&root));
             state.roots.remove(language_id);
+            state.traces.remove(language_id);
+            state.registrations.remove(language_id);
+            state.text_document_sync_kinds.remove(language_id);
+            state.file_operations.remove(language_id);
+            state.progress.remove(language_id);
             Ok(())
         })?;
         self.update_quickfixlist()?;
@@ -811,20 +1280,64 @@ impl LanguageClient {
         Ok(())
     }
 
-    fn preview<D>(&self, to_display: &D, bufname: &str) -> Result<()>
+    /// Highlights the range a hover applies to while the hover is shown.
+    fn highlight_hover_range(&self, filename: &str, range: &Range) -> Result<()> {
+        let text = self.get_state(|state| {
+            state
+                .text_documents
+                .get(filename)
+                .map(|d| d.text.clone())
+                .unwrap_or_default()
+        })?;
+        let lines: Vec<_> = text.lines().map(ToOwned::to_owned).collect();
+        let range = range_to_bytes(&lines, range, self.position_encoding(filename)?);
+        let highlights = range_highlights(&lines, &range, "LanguageClientHoverRange");
+
+        self.vim()?
+            .rpcclient
+            .notify("s:HighlightHoverRange", json!([highlights]))
+    }
+
+    pub fn preview<D>(&self, to_display: &D, bufname: &str) -> Result<()>
     where
         D: ToDisplay + ?Sized,
     {
-        let filetype = &to_display.vim_filetype();
-        let lines = to_display.to_display();
+        let mut filetype = to_display.vim_filetype();
+        let mut lines = to_display.to_display();
+        let regions = to_display.code_regions();
+        if filetype.as_deref() == Some("markdown") && self.show_markdown_as_text()? {
+            lines = markdown_to_text(&lines);
+            filetype = Some("text".to_string());
+        }
 
-        self.vim()?
-            .rpcclient
-            .notify("s:OpenHoverPreview", json!([bufname, lines, filetype]))?;
+        self.vim()?.rpcclient.notify(
+            "s:OpenHoverPreview",
+            json!([bufname, lines, filetype, null, null, regions]),
+        )?;
 
         Ok(())
     }
 
+    /// Whether markdown should be shown as plain text, because plain text is preferred for hovers
+    /// or because vim has no syntax to highlight markdown with.
+    fn show_markdown_as_text(&self) -> Result<bool> {
+        let prefers_text = self.get_config(|c| {
+            c.markup_kind
+                .get("hover")
+                .or_else(|| c.preferred_markup_kind.as_ref())
+                .and_then(|kinds| kinds.first())
+                .map_or(false, |kind| *kind == MarkupKind::PlainText)
+        })?;
+        if prefers_text {
+            return Ok(true);
+        }
+
+        let has_syntax: u8 = self
+            .vim()?
+            .eval("!empty(globpath(&runtimepath, 'syntax/markdown.vim'))")?;
+        Ok(has_syntax == 0)
+    }
+
     fn edit(&self, goto_cmd: &Option<String>, path: impl AsRef<Path>) -> Result<()> {
         let path = path.as_ref().to_string_lossy();
         if path.starts_with("jdt://") {
@@ -848,7 +1361,15 @@ impl LanguageClient {
             self.get_state(|state| state.roots.get(&language_id).cloned().unwrap_or_default())?;
 
         let trace = self.get_config(|c| c.trace)?;
-        let preferred_markup_kind = self.get_config(|c| c.preferred_markup_kind.clone())?;
+        // Features without a markup kind of their own use the preferred markup kind.
+        let markup_kind = |feature: &str| {
+            self.get_config(|c| {
+                c.markup_kind
+                    .get(feature)
+                    .cloned()
+                    .or_else(|| c.preferred_markup_kind.clone())
+            })
+        };
         let command = self.get_config(|c| c.server_commands.get(&language_id).cloned())?;
         if command.is_none() {
             return Err(anyhow!(
@@ -858,7 +1379,9 @@ impl LanguageClient {
         }
         let command = command.unwrap();
 
-        let settings = self.get_workspace_settings(&root).unwrap_or_default();
+        let settings = self
+            .get_workspace_settings(&language_id, &root)
+            .unwrap_or_default();
         // warn the user that they are using a deprecated workspace settings
         // file format and direct them to the documentation about the new one
         if settings.pointer("/initializationOptions").is_some() {
@@ -869,113 +1392,206 @@ impl LanguageClient {
 
         let result: Value = self.get_client(&Some(language_id.clone()))?.call(
             lsp_types::request::Initialize::METHOD,
-            #[allow(deprecated)]
-            InitializeParams {
-                client_info: Some(ClientInfo {
-                    name: "LanguageClient-neovim".into(),
-                    version: Some(self.version()),
-                }),
-                process_id: Some(u64::from(std::process::id())),
-                /* deprecated in lsp types, but can't initialize without it */
-                root_path: Some(root.clone()),
-                root_uri: Some(root.to_url()?),
-                initialization_options: initialization_options.clone(),
-                capabilities: ClientCapabilities {
-                    text_document: Some(TextDocumentClientCapabilities {
-                        color_provider: Some(GenericCapability {
-                            dynamic_registration: Some(false),
-                        }),
-                        completion: Some(CompletionCapability {
-                            completion_item: Some(CompletionItemCapability {
-                                snippet_support: Some(has_snippet_support),
-                                documentation_format: preferred_markup_kind.clone(),
-                                // note that if this value was to be changed to true, then
-                                // additional changes around edits should be made, as it currently
-                                // just panics if it encounters a completion item of type
-                                // InsertAndReplace.
-                                insert_replace_support: Some(false),
-                                ..CompletionItemCapability::default()
+            serde_json::to_value(
+                #[allow(deprecated)]
+                InitializeParams {
+                    client_info: Some(ClientInfo {
+                        name: "LanguageClient-neovim".into(),
+                        version: Some(self.version()),
+                    }),
+                    process_id: Some(u64::from(std::process::id())),
+                    /* deprecated in lsp types, but can't initialize without it */
+                    root_path: Some(root.clone()),
+                    root_uri: Some(root.to_url()?),
+                    initialization_options: initialization_options.clone(),
+                    capabilities: ClientCapabilities {
+                        text_document: Some(TextDocumentClientCapabilities {
+                            synchronization: Some(SynchronizationCapability {
+                                dynamic_registration: Some(false),
+                                will_save: Some(true),
+                                will_save_wait_until: Some(true),
+                                did_save: Some(true),
                             }),
-                            ..CompletionCapability::default()
-                        }),
-                        code_action: Some(CodeActionCapability {
-                            code_action_literal_support: Some(CodeActionLiteralSupport {
-                                code_action_kind: CodeActionKindLiteralSupport {
-                                    value_set: [
-                                        CodeActionKind::QUICKFIX,
-                                        CodeActionKind::REFACTOR,
-                                        CodeActionKind::REFACTOR_EXTRACT,
-                                        CodeActionKind::REFACTOR_INLINE,
-                                        CodeActionKind::REFACTOR_REWRITE,
-                                        CodeActionKind::SOURCE,
-                                        CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
-                                    ]
-                                    .iter()
-                                    .map(|kind| kind.as_str().to_owned())
-                                    .collect(),
-                                },
+                            color_provider: Some(GenericCapability {
+                                dynamic_registration: Some(false),
                             }),
-                            ..CodeActionCapability::default()
-                        }),
-                        signature_help: Some(SignatureHelpCapability {
-                            signature_information: Some(SignatureInformationSettings {
-                                active_parameter_support: None,
-                                documentation_format: preferred_markup_kind.clone(),
-                                parameter_information: Some(ParameterInformationSettings {
-                                    label_offset_support: Some(true),
+                            completion: Some(CompletionCapability {
+                                dynamic_registration: Some(true),
+                                completion_item: Some(CompletionItemCapability {
+                                    snippet_support: Some(has_snippet_support),
+                                    documentation_format: markup_kind("completion")?,
+                                    insert_replace_support: Some(true),
+                                    insert_text_mode_support: Some(InsertTextModeSupport {
+                                        value_set: vec![
+                                            InsertTextMode::AsIs,
+                                            InsertTextMode::AdjustIndentation,
+                                        ],
+                                    }),
+                                    ..CompletionItemCapability::default()
+                                }),
+                                ..CompletionCapability::default()
+                            }),
+                            code_action: Some(CodeActionCapability {
+                                dynamic_registration: Some(true),
+                                code_action_literal_support: Some(CodeActionLiteralSupport {
+                                    code_action_kind: CodeActionKindLiteralSupport {
+                                        value_set: [
+                                            CodeActionKind::QUICKFIX,
+                                            CodeActionKind::REFACTOR,
+                                            CodeActionKind::REFACTOR_EXTRACT,
+                                            CodeActionKind::REFACTOR_INLINE,
+                                            CodeActionKind::REFACTOR_REWRITE,
+                                            CodeActionKind::SOURCE,
+                                            CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                                        ]
+                                        .iter()
+                                        .map(|kind| kind.as_str().to_owned())
+                                        .collect(),
+                                    },
+                                }),
+                                data_support: Some(true),
+                                resolve_support: Some(CodeActionCapabilityResolveSupport {
+                                    properties: vec!["edit".to_owned(), "command".to_owned()],
                                 }),
+                                ..CodeActionCapability::default()
+                            }),
+                            signature_help: Some(SignatureHelpCapability {
+                                dynamic_registration: Some(true),
+                                signature_information: Some(SignatureInformationSettings {
+                                    active_parameter_support: None,
+                                    documentation_format: markup_kind("signatureHelp")?,
+                                    parameter_information: Some(ParameterInformationSettings {
+                                        label_offset_support: Some(true),
+                                    }),
+                                }),
+                                ..SignatureHelpCapability::default()
+                            }),
+                            declaration: Some(GotoCapability {
+                                link_support: Some(true),
+                                ..GotoCapability::default()
+                            }),
+                            definition: Some(GotoCapability {
+                                link_support: Some(true),
+                                ..GotoCapability::default()
+                            }),
+                            type_definition: Some(GotoCapability {
+                                link_support: Some(true),
+                                ..GotoCapability::default()
+                            }),
+                            implementation: Some(GotoCapability {
+                                link_support: Some(true),
+                                ..GotoCapability::default()
+                            }),
+                            publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+                                related_information: Some(true),
+                                ..PublishDiagnosticsClientCapabilities::default()
+                            }),
+                            code_lens: Some(GenericCapability {
+                                dynamic_registration: Some(true),
+                            }),
+                            document_link: Some(DocumentLinkClientCapabilities {
+                                dynamic_registration: Some(false),
+                                tooltip_support: Some(true),
+                            }),
+                            semantic_highlighting_capabilities: Some(
+                                SemanticHighlightingClientCapability {
+                                    semantic_highlighting: true,
+                                },
+                            ),
+                            hover: Some(HoverCapability {
+                                dynamic_registration: Some(true),
+                                content_format: markup_kind("hover")?,
+                                ..HoverCapability::default()
+                            }),
+                            selection_range: Some(SelectionRangeClientCapabilities {
+                                dynamic_registration: Some(false),
+                            }),
+                            formatting: Some(GenericCapability {
+                                dynamic_registration: Some(true),
+                            }),
+                            range_formatting: Some(GenericCapability {
+                                dynamic_registration: Some(true),
+                            }),
+                            on_type_formatting: Some(GenericCapability {
+                                dynamic_registration: Some(true),
+                            }),
+                            rename: Some(RenameCapability {
+                                dynamic_registration: Some(true),
+                                ..RenameCapability::default()
                             }),
-                            ..SignatureHelpCapability::default()
+                            ..TextDocumentClientCapabilities::default()
                         }),
-                        declaration: Some(GotoCapability {
-                            link_support: Some(true),
-                            ..GotoCapability::default()
-                        }),
-                        definition: Some(GotoCapability {
-                            link_support: Some(true),
-                            ..GotoCapability::default()
-                        }),
-                        type_definition: Some(GotoCapability {
-                            link_support: Some(true),
-                            ..GotoCapability::default()
-                        }),
-                        implementation: Some(GotoCapability {
-                            link_support: Some(true),
-                            ..GotoCapability::default()
-                        }),
-                        publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
-                            related_information: Some(true),
-                            ..PublishDiagnosticsClientCapabilities::default()
-                        }),
-                        code_lens: Some(GenericCapability {
-                            dynamic_registration: Some(true),
+                        workspace: Some(WorkspaceClientCapabilities {
+                            apply_edit: Some(true),
+                            configuration: Some(true),
+                            did_change_watched_files: Some(GenericCapability {
+                                dynamic_registration: Some(true),
+                            }),
+                            execute_command: Some(GenericCapability {
+                                dynamic_registration: Some(true),
+                            }),
+                            ..WorkspaceClientCapabilities::default()
                         }),
-                        semantic_highlighting_capabilities: Some(
-                            SemanticHighlightingClientCapability {
-                                semantic_highlighting: true,
+                        ..ClientCapabilities::default()
+                    },
+                    trace: Some(trace),
+                    workspace_folders: None,
+                },
+            )?
+            // Pull diagnostics, position encodings, file operations, completion label details,
+            // linked editing ranges and type hierarchies are not yet part of lsp-types.
+            .combine(&json!({
+                "capabilities": {
+                    "window": {
+                        "showDocument": {
+                            "support": true,
+                        },
+                    },
+                    "workspace": {
+                        "fileOperations": {
+                            "dynamicRegistration": false,
+                            "willCreate": true,
+                            "didCreate": true,
+                        },
+                    },
+                    "general": {
+                        "positionEncodings": ["utf-8", "utf-16"],
+                    },
+                    "textDocument": {
+                        "completion": {
+                            "completionItem": {
+                                "labelDetailsSupport": true,
                             },
-                        ),
-                        hover: Some(HoverCapability {
-                            content_format: preferred_markup_kind,
-                            ..HoverCapability::default()
-                        }),
-                        ..TextDocumentClientCapabilities::default()
-                    }),
-                    workspace: Some(WorkspaceClientCapabilities {
-                        apply_edit: Some(true),
-                        did_change_watched_files: Some(GenericCapability {
-                            dynamic_registration: Some(true),
-                        }),
-                        ..WorkspaceClientCapabilities::default()
-                    }),
-                    ..ClientCapabilities::default()
+                        },
+                        "diagnostic": {
+                            "dynamicRegistration": false,
+                            "relatedDocumentSupport": false,
+                        },
+                        "linkedEditingRange": {
+                            "dynamicRegistration": false,
+                        },
+                        "typeHierarchy": {
+                            "dynamicRegistration": false,
+                        },
+                    },
                 },
-                trace: Some(trace),
-                workspace_folders: None,
-            },
+            })),
         )?;
 
         let initialize_result = InitializeResult::deserialize(&result)?;
+        // As per the spec, servers that don't say how to sync documents don't want changes.
+        let sync_kind = match &initialize_result.capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+            Some(TextDocumentSyncCapability::Options(options)) => {
+                options.change.unwrap_or(TextDocumentSyncKind::None)
+            }
+            None => TextDocumentSyncKind::None,
+        };
+        // Servers that don't report an encoding use UTF-16, as the spec mandates.
+        let position_encoding = result
+            .pointer("/capabilities/positionEncoding")
+            .and_then(|encoding| PositionEncoding::deserialize(encoding).ok())
+            .unwrap_or_default();
         self.update_state(|state| {
             let server_name = initialize_result
                 .server_info
@@ -993,20 +1609,58 @@ impl LanguageClient {
             state
                 .capabilities
                 .insert(language_id.clone(), initialize_result);
+            state.server_capabilities.insert(
+                language_id.clone(),
+                result.get("capabilities").cloned().unwrap_or_default(),
+            );
+            state.traces.insert(language_id.clone(), trace);
+            state
+                .text_document_sync_kinds
+                .insert(language_id.clone(), sync_kind);
+            state
+                .position_encodings
+                .insert(language_id.clone(), position_encoding);
+            if !result
+                .pointer("/capabilities/diagnosticProvider")
+                .unwrap_or(&Value::Null)
+                .is_null()
+            {
+                let identifier = result
+                    .pointer("/capabilities/diagnosticProvider/identifier")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string);
+                state
+                    .pull_diagnostics
+                    .insert(language_id.clone(), identifier);
+            }
+            if result.pointer("/capabilities/diagnosticProvider/workspaceDiagnostics")
+                == Some(&Value::Bool(true))
+            {
+                state.workspace_diagnostics.insert(language_id.clone());
+            }
+            if !matches!(
+                result.pointer("/capabilities/callHierarchyProvider"),
+                None | Some(Value::Null) | Some(Value::Bool(false))
+            ) {
+                state.call_hierarchy_providers.insert(language_id.clone());
+            }
+            if !matches!(
+                result.pointer("/capabilities/typeHierarchyProvider"),
+                None | Some(Value::Null) | Some(Value::Bool(false))
+            ) {
+                state.type_hierarchy_providers.insert(language_id.clone());
+            }
+            if let Some(file_operations) = result.pointer("/capabilities/workspace/fileOperations")
+            {
+                state
+                    .file_operations
+                    .insert(language_id.clone(), file_operations.clone());
+            }
 
             Ok(())
         })?;
 
-        if let Err(e) = self.register_cm_source(&language_id, &result) {
-            let message = format!("LanguageClient: failed to register as NCM source: {}", e);
-            error!("{}\n{:?}", message, e);
-            self.vim()?.echoerr(&message)?;
-        }
-        if let Err(e) = self.register_ncm2_source(&language_id, &result) {
-            let message = format!("LanguageClient: failed to register as NCM source: {}", e);
-            error!("{}\n{:?}", message, e);
-            self.vim()?.echoerr(&message)?;
-        }
+        self.register_completion_sources(&language_id, &result)?;
         if let Err(e) = self.parse_semantic_scopes(&language_id, &result) {
             let message = format!("LanguageClient: failed to parse semantic scopes: {}", e);
             error!("{}\n{:?}", message, e);
@@ -1033,17 +1687,34 @@ impl LanguageClient {
         self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let position = self.vim()?.get_position(params)?;
+        let position = self.get_position(&filename, params)?;
 
-        let result = self.get_client(&Some(language_id))?.call(
-            lsp_types::request::HoverRequest::METHOD,
-            TextDocumentPositionParams {
-                text_document: TextDocumentIdentifier {
-                    uri: filename.to_url()?,
+        let mut results = vec![];
+        for (name, client) in self.hover_clients(&language_id)? {
+            let result: Value = client.call(
+                lsp_types::request::HoverRequest::METHOD,
+                TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: filename.to_url()?,
+                    },
+                    position,
                 },
-                position,
-            },
-        )?;
+            )?;
+            if let Some(hover) = Option::<Hover>::deserialize(&result)? {
+                results.push((name, hover));
+            }
+        }
+
+        let result = match results.len() {
+            0 => Value::Null,
+            // a single hover is passed through as is, so that it's rendered the way the server
+            // intended.
+            1 => serde_json::to_value(&results[0].1)?,
+            _ => {
+                results.retain(|(_, hover)| !hover.to_display().is_empty());
+                serde_json::to_value(merge_hovers(&results))?
+            }
+        };
 
         if !self.vim()?.get_handle(params)? {
             return Ok(result);
@@ -1064,15 +1735,88 @@ impl LanguageClient {
                 HoverPreviewOption::Auto => hover.lines_len() > 1,
             };
             if use_preview {
-                self.preview(&hover, "__LCNHover__")?
+                self.preview(&hover, "__LCNHover__")?;
+                if let Some(range) = hover.range {
+                    if self.get_config(|c| c.hover_highlight_range)? {
+                        self.highlight_hover_range(&filename, &range)?;
+                    }
+                }
             } else {
-                self.vim()?.echo_ellipsis(hover.to_string())?
+                let reserve = self.get_config(|c| c.echo_width_reserve)?;
+                self.vim()?.echo_ellipsis(hover.to_string(), reserve)?
             }
         }
 
         Ok(result)
     }
 
+    /// Requests the ranges edited together with the one at the cursor, e.g. the opening and
+    /// closing tags of an element, for vim to mirror the edits made to one of them to the others.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_linked_editing_range(&self, params: &Value) -> Result<Value> {
+        if !self.get_config(|c| c.linked_editing)? {
+            return Ok(Value::Null);
+        }
+
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.check_provider(&language_id, LinkedEditingRangeRequest::METHOD, params)? {
+            return Ok(Value::Null);
+        }
+
+        self.text_document_did_change(params)?;
+        let position = self.get_position(&filename, params)?;
+
+        let result: Value = self.get_client(&Some(language_id))?.call(
+            LinkedEditingRangeRequest::METHOD,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                position,
+            },
+        )?;
+
+        if !self.vim()?.get_handle(params)? {
+            return Ok(result);
+        }
+
+        let ranges = Option::<LinkedEditingRanges>::deserialize(&result)?
+            .map(|linked| linked.ranges)
+            .unwrap_or_default();
+        // Only edits within a line are mirrored, which covers tags.
+        let ranges: Vec<Range> = ranges
+            .iter()
+            .filter(|range| range.start.line == range.end.line)
+            .map(|range| self.range_to_bytes(&filename, range))
+            .collect::<Result<_>>()?;
+        let lines = self.document_lines(&filename)?;
+        let highlights: Vec<Highlight> = ranges
+            .iter()
+            .flat_map(|range| range_highlights(&lines, range, "LanguageClientLinkedEdit"))
+            .collect();
+
+        // The state of the line when insert mode was entered, to tell the edits made since.
+        let insert_enter: Value = try_get("insertEnter", params)?.unwrap_or_default();
+        self.vim()?.rpcclient.notify(
+            "s:SetupLinkedEdit",
+            json!([ranges, highlights, insert_enter]),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Returns the clients to query for hover information along with the name of their server.
+    /// Only a single server per filetype is currently supported, but hovers from every client
+    /// returned here are stacked together.
+    fn hover_clients(&self, language_id: &str) -> Result<Vec<(String, Arc<RpcClient>)>> {
+        let client = self.get_client(&Some(language_id.to_owned()))?;
+        let name = self
+            .get_config(|c| c.server_commands.get(language_id).map(ServerCommand::name))?
+            .unwrap_or_else(|| language_id.to_owned());
+        Ok(vec![(name, client)])
+    }
+
     /// Generic find locations, e.g, definitions, references.
     #[tracing::instrument(level = "info", skip(self))]
     pub fn find_locations(&self, params: &Value) -> Result<Value> {
@@ -1081,7 +1825,10 @@ impl LanguageClient {
             try_get("method", params)?.ok_or_else(|| anyhow!("method not found in request!"))?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let position = self.vim()?.get_position(params)?;
+        if !self.check_provider(&language_id, &method, params)? {
+            return Ok(Value::Null);
+        }
+        let position = self.get_position(&filename, params)?;
         let current_word = self.vim()?.get_current_word(params)?;
         let goto_cmd = self.vim()?.get_goto_cmd(params)?;
 
@@ -1093,6 +1840,17 @@ impl LanguageClient {
         })?
         .combine(params);
 
+        // Responses can arrive out of order when searching repeatedly, so only the latest request
+        // of a method gets to update the UI.
+        let token = self.update_state(|state| {
+            let token = state
+                .find_locations_tokens
+                .entry(method.clone())
+                .or_insert(0);
+            *token += 1;
+            Ok(*token)
+        })?;
+
         let result = self
             .get_client(&Some(language_id))?
             .call(&method, &params)?;
@@ -1101,6 +1859,12 @@ impl LanguageClient {
             return Ok(result);
         }
 
+        let latest = self.get_state(|state| state.find_locations_tokens.get(&method).copied())?;
+        if latest != Some(token) {
+            debug!("Discarding superseded {} response", method);
+            return Ok(result);
+        }
+
         let response = Option::<GotoDefinitionResponse>::deserialize(&result)?;
 
         let locations = match response {
@@ -1113,25 +1877,55 @@ impl LanguageClient {
                 .collect(),
         };
 
+        // Below the threshold, repeating the same search cycles through the locations found
+        // instead of listing them.
+        let threshold = self.get_config(|c| c.locations_list_threshold)?;
         match locations.len() {
             0 => self.vim()?.echowarn("Not found!")?,
-            1 => {
-                let loc = locations.get(0).ok_or_else(|| anyhow!("Not found!"))?;
-                let path = loc.uri.filepath()?.to_string_lossy().into_owned();
-                self.edit(&goto_cmd, path)?;
-                self.vim()?
-                    .cursor(loc.range.start.line + 1, loc.range.start.character + 1)?;
+            count if count < std::cmp::max(threshold, 2) => {
+                let index = self.update_state(|state| {
+                    let index = match &state.location_cycle {
+                        Some((cycle, index)) if *cycle == locations => (index + 1) % count,
+                        _ => 0,
+                    };
+                    state.location_cycle = Some((locations.clone(), index));
+                    Ok(index)
+                })?;
+
+                let loc = &locations[index];
+                let path = loc.uri.filepath()?;
+                self.edit(&goto_cmd, &path)?;
+                let start =
+                    self.position_to_bytes(&LineCache::default(), &path, loc.range.start)?;
+                self.vim()?.cursor(start.line + 1, start.character + 1)?;
                 let cur_file: String = self.vim()?.eval("expand('%')")?;
-                self.vim()?.echomsg_ellipsis(format!(
-                    "{} {}:{}",
-                    cur_file,
-                    loc.range.start.line + 1,
-                    loc.range.start.character + 1
-                ))?;
+                let counter = if count > 1 {
+                    format!("({}/{}) ", index + 1, count)
+                } else {
+                    String::new()
+                };
+                let reserve = self.get_config(|c| c.echo_width_reserve)?;
+                self.vim()?.echomsg_ellipsis(
+                    format!(
+                        "{}{} {}:{}",
+                        counter,
+                        cur_file,
+                        start.line + 1,
+                        start.character + 1
+                    ),
+                    reserve,
+                )?;
             }
             _ => {
                 let title = format!("[LC]: search for {}", current_word);
-                self.present_list(&title, &locations)?
+                let selection_ui = self.get_config(|c| c.selection_ui)?;
+                if method == lsp_types::request::References::METHOD
+                    && !matches!(selection_ui, SelectionUI::Funcref)
+                {
+                    self.present_grouped_locations(&title, locations)?
+                } else {
+                    self.present_list(ListKind::Locations, &title, &locations)?
+                }
             }
         }
 
@@ -1143,7 +1937,10 @@ impl LanguageClient {
         self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let position = self.vim()?.get_position(params)?;
+        if !self.check_provider(&language_id, lsp_types::request::Rename::METHOD, params)? {
+            return Ok(Value::Null);
+        }
+        let position = self.get_position(&filename, params)?;
         let current_word = self.vim()?.get_current_word(params)?;
         let new_name: Option<String> = try_get("newName", params)?;
 
@@ -1180,16 +1977,95 @@ impl LanguageClient {
         }
 
         let edit = WorkspaceEdit::deserialize(&result)?;
+        if self.get_config(|c| c.rename_preview)? && !self.confirm_rename(&edit)? {
+            return Ok(Value::Null);
+        }
         self.apply_workspace_edit(&edit)?;
 
         Ok(result)
     }
 
+    /// Presents the changes of a rename and asks for confirmation before they are applied.
+    fn confirm_rename(&self, edit: &WorkspaceEdit) -> Result<bool> {
+        let mut changes: Vec<(&Url, &[TextEdit])> = vec![];
+        if let Some(ref document_changes) = edit.document_changes {
+            match document_changes {
+                DocumentChanges::Edits(ref edits) => {
+                    for e in edits {
+                        changes.push((&e.text_document.uri, &e.edits));
+                    }
+                }
+                DocumentChanges::Operations(ref ops) => {
+                    for op in ops {
+                        if let DocumentChangeOperation::Edit(ref e) = op {
+                            changes.push((&e.text_document.uri, &e.edits));
+                        }
+                    }
+                }
+            }
+        } else if let Some(ref edit_changes) = edit.changes {
+            for (uri, edits) in edit_changes {
+                changes.push((uri, edits));
+            }
+        }
+
+        let mut items = vec![];
+        for (uri, edits) in &changes {
+            let filename = uri.filepath()?.to_string_lossy().into_owned();
+            for edit in edits.iter() {
+                let start = edit.range.start;
+                let before = self.get_raw_line(&filename, start.line).unwrap_or_default();
+                let encoding = self.position_encoding(&filename)?;
+                let character = character_to_byte(&before, start.character, encoding);
+                let after = if start.line == edit.range.end.line {
+                    let mut line_edit = edit.clone();
+                    line_edit.range.start.line = 0;
+                    line_edit.range.end.line = 0;
+                    line_edit.range = range_to_bytes(&[before.clone()], &line_edit.range, encoding);
+                    let (lines, _) =
+                        apply_text_edits(&[before.clone()], &[line_edit], &Position::default())?;
+                    lines.join("\n")
+                } else {
+                    edit.new_text.clone()
+                };
+
+                items.push(RenamePreviewItem {
+                    filename: filename.clone(),
+                    line: start.line,
+                    character,
+                    before: before.trim().into(),
+                    after: after.trim().into(),
+                });
+            }
+        }
+
+        self.present_list(ListKind::Locations, "[LC]: rename preview", &items)?;
+        let options = vec![
+            format!(
+                "Apply rename to {} locations in {} files?",
+                items.len(),
+                changes.len()
+            ),
+            "1) Yes".to_owned(),
+            "2) No".to_owned(),
+        ];
+        let index: Option<usize> = self.vim()?.rpcclient.call("s:inputlist", options)?;
+        Ok(index == Some(1))
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn text_document_document_symbol(&self, params: &Value) -> Result<Value> {
         self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.check_provider(
+            &language_id,
+            lsp_types::request::DocumentSymbolRequest::METHOD,
+            params,
+        )? {
+            return Ok(Value::Null);
+        }
+        let kinds: Option<Vec<SymbolKind>> = try_get("kinds", params)?;
 
         let result = self.get_client(&Some(language_id))?.call(
             lsp_types::request::DocumentSymbolRequest::METHOD,
@@ -1211,38 +2087,77 @@ impl LanguageClient {
 
         match syms {
             Some(DocumentSymbolResponse::Flat(flat)) => {
-                self.present_list(&title, &flat)?;
+                let flat: Vec<_> = flat
+                    .into_iter()
+                    .filter(|s| kinds.as_ref().map_or(true, |kinds| kinds.contains(&s.kind)))
+                    .collect();
+                self.present_list(ListKind::Symbols, &title, &flat)?;
             }
             Some(DocumentSymbolResponse::Nested(nested)) => {
                 let mut symbols = Vec::new();
+                for ds in &nested {
+                    walk_document_symbol(&mut symbols, None, ds, kinds.as_deref());
+                }
+                for symbol in &mut symbols {
+                    symbol.selection_range =
+                        self.range_to_bytes(&filename, &symbol.selection_range)?;
+                }
 
-                fn walk_document_symbol(
-                    buffer: &mut Vec<lsp_types::DocumentSymbol>,
-                    parent: Option<&lsp_types::DocumentSymbol>,
-                    ds: &lsp_types::DocumentSymbol,
-                ) {
-                    let name = if let Some(parent) = parent {
-                        format!("{}::{}", parent.name, ds.name)
-                    } else {
-                        ds.name.clone()
-                    };
+                self.present_list(ListKind::Symbols, &title, &symbols)?;
+            }
+            _ => (),
+        };
 
-                    buffer.push(lsp_types::DocumentSymbol { name, ..ds.clone() });
+        Ok(result)
+    }
 
-                    if let Some(children) = &ds.children {
-                        for child in children {
-                            walk_document_symbol(buffer, Some(&ds), child);
-                        }
-                    }
-                }
+    /// Lists the symbols of the current buffer whose qualified name contains the query, for
+    /// selection in a filterable picker. When the selection UI is not a picker, the query is
+    /// prompted for first.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn document_symbol_search(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let mut query: String = try_get("query", params)?.unwrap_or_default();
+        if query.is_empty()
+            && self.get_config(|c| !matches!(c.selection_ui, SelectionUI::Funcref))?
+        {
+            query = self
+                .vim()?
+                .rpcclient
+                .call("s:getInput", ["Search symbol: ", ""])?;
+        }
+        let query = query.to_lowercase();
+
+        let result = self.text_document_document_symbol(
+            &json!({
+                "handle": false,
+            })
+            .combine(params),
+        )?;
+        let syms = <Option<DocumentSymbolResponse>>::deserialize(&result)?;
+        let title = format!("[LC]: symbols for {}", filename);
 
+        match syms {
+            Some(DocumentSymbolResponse::Flat(flat)) => {
+                let flat: Vec<_> = flat
+                    .into_iter()
+                    .filter(|s| s.name.to_lowercase().contains(&query))
+                    .collect();
+                self.present_list(ListKind::Symbols, &title, &flat)?;
+            }
+            Some(DocumentSymbolResponse::Nested(nested)) => {
+                let mut symbols = Vec::new();
                 for ds in &nested {
-                    walk_document_symbol(&mut symbols, None, ds);
+                    walk_document_symbol(&mut symbols, None, ds, None);
                 }
-
-                self.present_list(&title, &symbols)?;
+                symbols.retain(|s| s.name.to_lowercase().contains(&query));
+                for symbol in &mut symbols {
+                    symbol.selection_range =
+                        self.range_to_bytes(&filename, &symbol.selection_range)?;
+                }
+                self.present_list(ListKind::Symbols, &title, &symbols)?;
             }
-            _ => (),
+            None => self.vim()?.echowarn("No symbols found!")?,
         };
 
         Ok(result)
@@ -1252,7 +2167,14 @@ impl LanguageClient {
         self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let range = Range::deserialize(&params["range"])?;
+        if !self.check_provider(
+            &language_id,
+            lsp_types::request::CodeActionRequest::METHOD,
+            params,
+        )? {
+            return Ok(Value::Null);
+        }
+        let range = self.range_from_bytes(&filename, &Range::deserialize(&params["range"])?)?;
 
         // Unify filename.
         let filename = filename.canonicalize();
@@ -1347,6 +2269,27 @@ impl LanguageClient {
             return Ok(result);
         }
 
+        if self.get_config(|c| c.selection_ui_code_actions)? {
+            let items = self.with_line_cache(|lines| {
+                actions
+                    .iter()
+                    .enumerate()
+                    .map(|(index, action)| {
+                        Ok(json!({
+                            "index": index,
+                            "title": action.title,
+                            "kind": code_action_kind_as_str(action),
+                            "preview": self.code_action_preview(action, lines)?,
+                        }))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            self.vim()?
+                .rpcclient
+                .notify("s:selectionUI_codeActions", json!([items]))?;
+            return Ok(result);
+        }
+
         self.present_actions("Code Actions", &actions, |idx| -> Result<()> {
             self.handle_code_action_selection(&actions, idx)
         })?;
@@ -1354,21 +2297,87 @@ impl LanguageClient {
         Ok(result)
     }
 
+    /// Renders the edit of a code action as a diff, to preview the action. Actions whose edit is
+    /// only known once resolved have no preview.
+    fn code_action_preview(&self, action: &CodeAction, lines: &LineCache) -> Result<Vec<String>> {
+        let edit = match &action.edit {
+            Some(edit) => edit,
+            None => return Ok(vec![]),
+        };
+
+        let mut file_edits = vec![];
+        match &edit.document_changes {
+            Some(DocumentChanges::Edits(changes)) => {
+                for e in changes {
+                    add_file_edits(&mut file_edits, e.text_document.uri.filepath()?, &e.edits);
+                }
+            }
+            Some(DocumentChanges::Operations(ops)) => {
+                for op in ops {
+                    if let DocumentChangeOperation::Edit(e) = op {
+                        add_file_edits(&mut file_edits, e.text_document.uri.filepath()?, &e.edits);
+                    }
+                }
+            }
+            None => {
+                for (uri, edits) in edit.changes.iter().flatten() {
+                    add_file_edits(&mut file_edits, uri.filepath()?, edits);
+                }
+            }
+        }
+
+        let cwd: String = self.vim()?.eval("getcwd()")?;
+        let mut preview = vec![];
+        for (path, batches) in file_edits {
+            let filename = path.to_string_lossy().into_owned();
+            let encoding = self.position_encoding(&filename)?;
+            let relpath = diff_paths(&path, Path::new(&cwd)).unwrap_or_else(|| path.clone());
+            preview.push(format!("--- {}", relpath.to_string_lossy()));
+
+            let mut edits: Vec<_> = batches.into_iter().flatten().collect();
+            edits.sort_by_key(|edit| (edit.range.start.line, edit.range.start.character));
+            for edit in edits {
+                let old_lines = (edit.range.start.line..=edit.range.end.line)
+                    .map(|line| self.get_cached_line(lines, &path, line).unwrap_or_default())
+                    .collect::<Vec<_>>();
+                preview.extend(render_edit_diff(&old_lines, edit, encoding));
+            }
+        }
+
+        Ok(preview)
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn code_action_sink(&self, params: &Value) -> Result<()> {
+        let index: usize = try_get("index", params)?.ok_or_else(|| anyhow!("index not found!"))?;
+        let actions = self.get_state(|state| state.stashed_code_action_actions.clone())?;
+        if index >= actions.len() {
+            return Err(anyhow!("Action not stashed, please try again"));
+        }
+
+        self.handle_code_action_selection(&actions, index)
+    }
+
     fn handle_code_action_selection(&self, actions: &[CodeAction], idx: usize) -> Result<()> {
         match actions.get(idx) {
             Some(action) => {
+                let action = self.resolve_code_action(action)?;
+                let action = &action;
+
                 // Apply edit before command.
                 if let Some(edit) = &action.edit {
                     self.apply_workspace_edit(edit)?;
                 }
 
                 if let Some(command) = &action.command {
-                    if !self.try_handle_command_by_client(&command)? {
+                    if self.confirm_command(&command.command)?
+                        && !self.try_handle_command_by_client(&command)?
+                    {
                         let params = json!({
                         "command": command.command,
                         "arguments": command.arguments,
                         });
-                        self.workspace_execute_command(&params)?;
+                        self.execute_server_command(&params)?;
                     }
                 }
 
@@ -1383,13 +2392,44 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Resolves the edit/command of a code action that came without either, if the server supports
+    /// it. Otherwise the action is returned as is.
+    fn resolve_code_action(&self, action: &CodeAction) -> Result<CodeAction> {
+        if action.edit.is_some() || action.command.is_some() {
+            return Ok(action.clone());
+        }
+
+        let filename = self.vim()?.get_filename(&Value::Null)?;
+        let language_id = self.vim()?.get_language_id(&filename, &Value::Null)?;
+        let has_capability =
+            self.get_state(|state| match state.capabilities.get(&language_id) {
+                None => false,
+                Some(result) => match result.capabilities.code_action_provider {
+                    Some(CodeActionProviderCapability::Options(ref options)) => {
+                        options.resolve_provider.unwrap_or_default()
+                    }
+                    _ => false,
+                },
+            })?;
+        if !has_capability {
+            return Ok(action.clone());
+        }
+
+        let result: Value = self
+            .get_client(&Some(language_id))?
+            .call(lsp_types::request::CodeActionResolveRequest::METHOD, action)?;
+        let resolved = <Option<CodeAction>>::deserialize(&result)?;
+        Ok(resolved.unwrap_or_else(|| action.clone()))
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn text_document_completion(&self, params: &Value) -> Result<Value> {
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let position = self.vim()?.get_position(params)?;
+        let position = self.get_position(&filename, params)?;
+        self.clear_resolved_completion_items()?;
 
-        let result = self.get_client(&Some(language_id))?.call(
+        let mut result = self.get_client(&Some(language_id))?.call(
             lsp_types::request::Completion::METHOD,
             TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
@@ -1398,6 +2438,7 @@ impl LanguageClient {
                 position,
             },
         )?;
+        apply_completion_item_defaults(&mut result);
 
         if !self.vim()?.get_handle(params)? {
             return Ok(result);
@@ -1411,7 +2452,14 @@ impl LanguageClient {
         self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let position = self.vim()?.get_position(params)?;
+        if !self.check_provider(
+            &language_id,
+            lsp_types::request::SignatureHelpRequest::METHOD,
+            params,
+        )? {
+            return Ok(Value::Null);
+        }
+        let position = self.get_position(&filename, params)?;
 
         let result = self.get_client(&Some(language_id))?.call(
             lsp_types::request::SignatureHelpRequest::METHOD,
@@ -1481,13 +2529,9 @@ impl LanguageClient {
     }
 
     #[tracing::instrument(level = "info", skip(self))]
-    pub fn text_document_references(&self, params: &Value) -> Result<Value> {
-        let include_declaration: bool = try_get("includeDeclaration", params)?.unwrap_or(true);
+    pub fn text_document_declaration(&self, params: &Value) -> Result<Value> {
         let params = json!({
-            "method": lsp_types::request::References::METHOD,
-            "context": ReferenceContext {
-                include_declaration,
-            }
+            "method": lsp_types::request::GotoDeclaration::METHOD,
         })
         .combine(params);
         let result = self.find_locations(&params)?;
@@ -1495,10 +2539,299 @@ impl LanguageClient {
     }
 
     #[tracing::instrument(level = "info", skip(self))]
-    pub fn text_document_formatting(&self, params: &Value) -> Result<Value> {
-        self.text_document_did_change(params)?;
-        let filename = self.vim()?.get_filename(params)?;
+    pub fn text_document_implementation(&self, params: &Value) -> Result<Value> {
+        let params = json!({
+            "method": lsp_types::request::GotoImplementation::METHOD,
+        })
+        .combine(params);
+        let result = self.find_locations(&params)?;
+        Ok(result)
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_type_definition(&self, params: &Value) -> Result<Value> {
+        let params = json!({
+            "method": lsp_types::request::GotoTypeDefinition::METHOD,
+        })
+        .combine(params);
+        let result = self.find_locations(&params)?;
+        Ok(result)
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_references(&self, params: &Value) -> Result<Value> {
+        let include_declaration: bool = try_get("includeDeclaration", params)?.unwrap_or(true);
+        let params = json!({
+            "method": lsp_types::request::References::METHOD,
+            "context": ReferenceContext {
+                include_declaration,
+            }
+        })
+        .combine(params);
+        let result = self.find_locations(&params)?;
+        Ok(result)
+    }
+
+    /// Whether the server of a language provides a feature, given the name of its provider in the
+    /// server capabilities. Providers are either a boolean or the options of the feature.
+    fn has_provider(&self, language_id: &str, provider: &str) -> Result<bool> {
+        self.get_state(|state| {
+            state
+                .server_capabilities
+                .get(language_id)
+                .map_or(false, |capabilities| {
+                    !matches!(
+                        capabilities.get(provider),
+                        None | Some(Value::Null) | Some(Value::Bool(false))
+                    )
+                })
+        })
+    }
+
+    /// Checks that the server of a language provides the feature of a request, warning the user
+    /// when it doesn't and the request was made explicitly, e.g. from a mapping rather than from an
+    /// autocmd. Requests without a provider in the server capabilities are assumed to be supported.
+    fn check_provider(&self, language_id: &str, method: &str, params: &Value) -> Result<bool> {
+        let provider = match capability_provider(method) {
+            Some(provider) => provider,
+            None => return Ok(true),
+        };
+        if self.has_provider(language_id, provider)? {
+            return Ok(true);
+        }
+
+        info!("Server for {} does not support {}", language_id, method);
+        if try_get("explicit", params)?.unwrap_or(false) {
+            let server_name = self
+                .get_state(|state| {
+                    state
+                        .capabilities
+                        .get(language_id)
+                        .and_then(|result| result.server_info.as_ref())
+                        .map(|info| info.name.clone())
+                })?
+                .unwrap_or_else(|| language_id.to_owned());
+            self.vim()?.echowarn(format!(
+                "Server {} does not support {}",
+                server_name, method
+            ))?;
+        }
+        Ok(false)
+    }
+
+    /// Lists the callers of the function under the cursor. For servers without call hierarchies,
+    /// the references to the function are listed instead, as an approximation.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn incoming_calls(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let current_word = self.vim()?.get_current_word(params)?;
+        let has_call_hierarchy =
+            self.get_state(|state| state.call_hierarchy_providers.contains(&language_id))?;
+        let has_references = self.has_provider(&language_id, "referencesProvider")?;
+
+        let (title, result, locations) = if has_call_hierarchy {
+            let position = self.get_position(&filename, params)?;
+            let client = self.get_client(&Some(language_id))?;
+            let items: Option<Vec<Value>> = client.call(
+                CallHierarchyPrepare::METHOD,
+                TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: filename.to_url()?,
+                    },
+                    position,
+                },
+            )?;
+            let item = match items.and_then(|items| items.into_iter().next()) {
+                Some(item) => item,
+                None => {
+                    self.vim()?.echowarn("No function found at point!")?;
+                    return Ok(Value::Null);
+                }
+            };
+
+            let result: Value =
+                client.call(CallHierarchyIncomingCalls::METHOD, json!({ "item": item }))?;
+            let calls = <Option<Vec<CallHierarchyIncomingCall>>>::deserialize(&result)?;
+            let locations: Vec<Location> = calls
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|call| {
+                    let uri = call.from.uri;
+                    call.from_ranges
+                        .into_iter()
+                        .map(move |range| Location::new(uri.clone(), range))
+                })
+                .collect();
+            (
+                format!("[LC]: callers of {}", current_word),
+                result,
+                locations,
+            )
+        } else if has_references {
+            let result = self.find_locations(&params.combine(&json!({
+                "method": lsp_types::request::References::METHOD,
+                "context": ReferenceContext {
+                    include_declaration: false,
+                },
+                "handle": false,
+            })))?;
+            let locations = <Option<Vec<Location>>>::deserialize(&result)?.unwrap_or_default();
+            (
+                format!("[LC]: callers (approx) of {}", current_word),
+                result,
+                locations,
+            )
+        } else {
+            self.vim()?
+                .echowarn("Server supports neither call hierarchies nor references")?;
+            return Ok(Value::Null);
+        };
+
+        if !self.vim()?.get_handle(params)? {
+            return Ok(result);
+        }
+
+        if locations.is_empty() {
+            self.vim()?.echowarn("No callers found!")?;
+        } else {
+            self.present_list(ListKind::Locations, &title, &locations)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Lists the supertypes or the subtypes of the type under the cursor, depending on the
+    /// `direction` param, which defaults to subtypes.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn type_hierarchy(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let current_word = self.vim()?.get_current_word(params)?;
+        let direction: String =
+            try_get("direction", params)?.unwrap_or_else(|| "subtypes".to_string());
+        let method = match direction.as_str() {
+            "supertypes" => TypeHierarchySupertypes::METHOD,
+            "subtypes" => TypeHierarchySubtypes::METHOD,
+            _ => return Err(anyhow!("Invalid type hierarchy direction: {}", direction)),
+        };
+
+        if !self.get_state(|state| state.type_hierarchy_providers.contains(&language_id))? {
+            self.vim()?
+                .echowarn("Server doesn't support type hierarchies")?;
+            return Ok(Value::Null);
+        }
+
+        let position = self.get_position(&filename, params)?;
+        let client = self.get_client(&Some(language_id))?;
+        let items: Option<Vec<Value>> = client.call(
+            TypeHierarchyPrepare::METHOD,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                position,
+            },
+        )?;
+        let item = match items.and_then(|items| items.into_iter().next()) {
+            Some(item) => item,
+            None => {
+                self.vim()?.echowarn("No type found at point!")?;
+                return Ok(Value::Null);
+            }
+        };
+
+        let result: Value = client.call(method, json!({ "item": item }))?;
+        if !self.vim()?.get_handle(params)? {
+            return Ok(result);
+        }
+
+        let locations: Vec<Location> = <Option<Vec<TypeHierarchyItem>>>::deserialize(&result)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| Location::new(item.uri, item.selection_range))
+            .collect();
+        if locations.is_empty() {
+            self.vim()?.echowarn(format!("No {} found!", direction))?;
+        } else {
+            let title = format!("[LC]: {} of {}", direction, current_word);
+            self.present_list(ListKind::Locations, &title, &locations)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Echoes the type of the expression under the cursor, taken from the hover information
+    /// without its documentation.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn show_type(&self, params: &Value) -> Result<Value> {
+        let hover = self.text_document_hover(&params.combine(&json!({ "handle": false })))?;
+        let typ = Option::<Hover>::deserialize(&hover)?
+            .as_ref()
+            .and_then(hover_type);
+
+        if !self.vim()?.get_handle(params)? {
+            return Ok(json!(typ));
+        }
+
+        match &typ {
+            Some(typ) => {
+                let reserve = self.get_config(|c| c.echo_width_reserve)?;
+                self.vim()?.echomsg_ellipsis(typ, reserve)?
+            }
+            None => self
+                .vim()?
+                .echowarn("No type information found for expression")?,
+        }
+
+        Ok(json!(typ))
+    }
+
+    /// Jumps to the definition of the type of the expression under the cursor. Hover information
+    /// is requested first to confirm that there is an expression with a type at the cursor.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn goto_expression_type(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let has_capability =
+            self.get_state(|state| match state.capabilities.get(&language_id) {
+                None => false,
+                Some(result) => match result.capabilities.type_definition_provider {
+                    None | Some(TypeDefinitionProviderCapability::Simple(false)) => false,
+                    Some(_) => true,
+                },
+            })?;
+        if !has_capability {
+            self.vim()?
+                .echowarn("Server does not support textDocument/typeDefinition")?;
+            return Ok(Value::Null);
+        }
+
+        let hover = self.text_document_hover(&params.combine(&json!({ "handle": false })))?;
+        let hover = Option::<Hover>::deserialize(&hover)?;
+        if hover.map_or(true, |hover| hover.to_display().is_empty()) {
+            self.vim()?
+                .echowarn("No type information found for expression")?;
+            return Ok(Value::Null);
+        }
+
+        let params = json!({
+            "method": lsp_types::request::GotoTypeDefinition::METHOD,
+        })
+        .combine(params);
+        self.find_locations(&params)
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_formatting(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.check_provider(&language_id, lsp_types::request::Formatting::METHOD, params)? {
+            return Ok(Value::Null);
+        }
 
         let tab_size = self.vim()?.get_tab_size()?;
         let insert_spaces = self.vim()?.get_insert_spaces(&filename)?;
@@ -1532,11 +2865,81 @@ impl LanguageClient {
         Ok(result)
     }
 
+    /// Tells the server that a file is about to be saved, and applies the edits it wants made to
+    /// the file before that, if any.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_will_save_wait_until(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let (will_save, will_save_wait_until) = self.get_state(|state| {
+            match state
+                .capabilities
+                .get(&language_id)
+                .and_then(|result| result.capabilities.text_document_sync.as_ref())
+            {
+                Some(TextDocumentSyncCapability::Options(options)) => (
+                    options.will_save == Some(true),
+                    options.will_save_wait_until == Some(true),
+                ),
+                _ => (false, false),
+            }
+        })?;
+        if !will_save && !will_save_wait_until {
+            return Ok(Value::Null);
+        }
+
+        self.text_document_did_change(params)?;
+        let reason = try_get("reason", params)?.unwrap_or(TextDocumentSaveReason::Manual);
+        let will_save_params = WillSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: filename.to_url()?,
+            },
+            reason,
+        };
+        let client = self.get_client(&Some(language_id))?;
+        if will_save {
+            client.notify(
+                lsp_types::notification::WillSaveTextDocument::METHOD,
+                &will_save_params,
+            )?;
+        }
+        if !will_save_wait_until {
+            return Ok(Value::Null);
+        }
+
+        let result = client.call(
+            lsp_types::request::WillSaveWaitUntil::METHOD,
+            will_save_params,
+        )?;
+
+        if !self.vim()?.get_handle(params)? {
+            return Ok(result);
+        }
+
+        let edits = <Option<Vec<TextEdit>>>::deserialize(&result)?.unwrap_or_default();
+        if !edits.is_empty() {
+            let position = self.vim()?.get_position(params)?;
+            let position = self.apply_text_edits(&filename, &edits, position)?;
+            self.vim()?
+                .cursor(position.line + 1, position.character + 1)?;
+            self.text_document_did_change(params)?;
+        }
+
+        Ok(result)
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn text_document_range_formatting(&self, params: &Value) -> Result<Value> {
         self.text_document_did_change(params)?;
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.check_provider(
+            &language_id,
+            lsp_types::request::RangeFormatting::METHOD,
+            params,
+        )? {
+            return Ok(Value::Null);
+        }
         let start_line = try_get("range_start_line", params)?
             .map_or_else(|| self.vim()?.eval("LSP#range_start_line()"), Ok)?;
         let end_line = try_get("range_end_line", params)?
@@ -1607,10 +3010,31 @@ impl LanguageClient {
         let pumpos: Value =
             try_get("pumpos", params)?.ok_or_else(|| anyhow!("pumpos not found in request!"))?;
 
-        let result = self.get_client(&Some(language_id))?.call(
-            lsp_types::request::ResolveCompletionItem::METHOD,
-            completion_item,
-        )?;
+        // Items are resolved again as they are selected back and forth in the menu, so the
+        // resolved ones are kept until the completion is done.
+        let key = serde_json::to_string(&(
+            &completion_item.label,
+            &completion_item.detail,
+            &completion_item.data,
+        ))?;
+        let result =
+            match self.get_state(|state| state.resolved_completion_items.get(&key).cloned())? {
+                Some(result) => result,
+                None => {
+                    let result: Value = self.get_client(&Some(language_id))?.call(
+                        lsp_types::request::ResolveCompletionItem::METHOD,
+                        completion_item,
+                    )?;
+                    self.update_state(|state| {
+                        if state.resolved_completion_items.len() >= MAX_RESOLVED_COMPLETION_ITEMS {
+                            state.resolved_completion_items.clear();
+                        }
+                        state.resolved_completion_items.insert(key, result.clone());
+                        Ok(())
+                    })?;
+                    result
+                }
+            };
 
         if !self.vim()?.get_handle(params)? {
             return Ok(result);
@@ -1630,6 +3054,119 @@ impl LanguageClient {
         Ok(Value::Null)
     }
 
+    fn clear_resolved_completion_items(&self) -> Result<()> {
+        self.update_state(|state| {
+            state.resolved_completion_items.clear();
+            Ok(())
+        })
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_selection_range(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let position = self.get_position(&filename, params)?;
+
+        let result = self.get_client(&Some(language_id))?.call(
+            lsp_types::request::SelectionRangeRequest::METHOD,
+            SelectionRangeParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                positions: vec![position],
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            },
+        )?;
+
+        if !self.vim()?.get_handle(params)? {
+            return Ok(result);
+        }
+
+        let selection_ranges = <Option<Vec<SelectionRange>>>::deserialize(&result)?;
+        let selection_range = match selection_ranges.and_then(|srs| srs.into_iter().next()) {
+            Some(selection_range) => selection_range,
+            None => {
+                self.vim()?.echowarn("No selection range found")?;
+                return Ok(result);
+            }
+        };
+
+        // Walk the parent chain, from the innermost range to the outermost one.
+        let mut ranges = vec![];
+        let mut next = Some(selection_range);
+        while let Some(selection_range) = next {
+            ranges.push(selection_range.range);
+            next = selection_range.parent.map(|parent| *parent);
+        }
+
+        let version = self.get_state(|state| {
+            state
+                .text_documents
+                .get(&filename)
+                .map(|document| document.version)
+                .unwrap_or_default()
+        })?;
+        let selection = SelectionRangeState {
+            version,
+            ranges,
+            index: 0,
+        };
+        self.vim()?
+            .select_range(&self.range_to_bytes(&filename, &selection.ranges[0])?)?;
+        self.update_state(|state| {
+            state.selection_ranges.insert(filename.clone(), selection);
+            Ok(())
+        })?;
+
+        Ok(result)
+    }
+
+    /// Moves the visual selection to the next larger (`Direction::Next`) or smaller
+    /// (`Direction::Previous`) selection range, reusing the cached chain of ranges while it is
+    /// still valid for the buffer contents and cursor position.
+    #[tracing::instrument(level = "info", skip(self, direction))]
+    pub fn select_range(&self, params: &Value, direction: Direction) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let position = self.get_position(&filename, params)?;
+        let cached = self.get_state(|state| {
+            let version = state
+                .text_documents
+                .get(&filename)
+                .map(|document| document.version)
+                .unwrap_or_default();
+            state
+                .selection_ranges
+                .get(&filename)
+                .filter(|selection| selection.version == version && selection.contains(position))
+                .cloned()
+        })?;
+
+        let mut selection = match cached {
+            Some(selection) => selection,
+            None if direction == Direction::Next => {
+                return self.text_document_selection_range(params);
+            }
+            None => return Ok(Value::Null),
+        };
+
+        match direction {
+            Direction::Next if selection.index + 1 < selection.ranges.len() => selection.index += 1,
+            Direction::Previous if selection.index > 0 => selection.index -= 1,
+            _ => {}
+        }
+
+        let range = self.range_to_bytes(&filename, &selection.ranges[selection.index])?;
+        self.vim()?.select_range(&range)?;
+        self.update_state(|state| {
+            state.selection_ranges.insert(filename.clone(), selection);
+            Ok(())
+        })?;
+
+        Ok(Value::Null)
+    }
+
     // shows a list of actions for the user to choose one.
     fn present_actions<T, F>(&self, title: &str, actions: &[T], callback: F) -> Result<()>
     where
@@ -1643,7 +3180,7 @@ impl LanguageClient {
         let cwd: String = self.vim()?.eval("getcwd()")?;
         let actions: Result<Vec<String>> = actions
             .iter()
-            .map(|it| ListItem::string_item(it, self, &cwd))
+            .map(|it| ListItem::string_item(it, self, &LineCache::default(), &cwd))
             .collect();
 
         match self.get_config(|c| c.selection_ui)? {
@@ -1674,56 +3211,151 @@ impl LanguageClient {
 
     // shows a list of items, used for things like diagnostics or things that do not need a user
     // selection.
-    pub fn present_list<T>(&self, title: &str, items: &[T]) -> Result<()>
+    pub fn present_list<T>(&self, kind: ListKind, title: &str, items: &[T]) -> Result<()>
     where
         T: ListItem,
     {
         let selection_ui = self.get_config(|c| c.selection_ui)?;
-        let selection_ui_auto_open = self.get_config(|c| c.selection_ui_auto_open)?;
 
         match selection_ui {
             SelectionUI::Funcref => {
                 let cwd: String = self.vim()?.eval("getcwd()")?;
-                let source: Result<Vec<_>> = items
-                    .iter()
-                    .map(|it| ListItem::string_item(it, self, &cwd))
-                    .collect();
-                let source = source?;
+                let source: Vec<_> = self.with_line_cache(|lines| {
+                    items
+                        .iter()
+                        .map(|it| ListItem::string_item(it, self, lines, &cwd))
+                        .collect()
+                })?;
 
                 self.vim()?.rpcclient.notify(
                     "s:selectionUI_funcref",
                     json!([source, format!("s:{}", NOTIFICATION_FZF_SINK_LOCATION)]),
                 )?;
             }
-            SelectionUI::Quickfix => {
-                let list: Result<Vec<_>> = items
-                    .iter()
-                    .map(|it| ListItem::quickfix_item(it, self))
-                    .collect();
-                let list = list?;
-                self.vim()?.setqflist(&list, " ", title)?;
-                if selection_ui_auto_open {
-                    self.vim()?.command("botright copen")?;
-                }
-                self.vim()?.echo("Populated quickfix list.")?;
+            SelectionUI::Quickfix | SelectionUI::LocationList => {
+                let list: Vec<_> = self.with_line_cache(|lines| {
+                    items
+                        .iter()
+                        .map(|it| ListItem::quickfix_item(it, self, lines))
+                        .collect()
+                })?;
+                self.populate_list(kind, title, &list, items.len())?;
             }
-            SelectionUI::LocationList => {
-                let list: Result<Vec<_>> = items
-                    .iter()
-                    .map(|it| ListItem::quickfix_item(it, self))
-                    .collect();
-                let list = list?;
-                self.vim()?.setloclist(&list, " ", title)?;
-                if selection_ui_auto_open {
-                    self.vim()?.command("lopen")?;
-                }
-                self.vim()?.echo("Populated location list.")?;
+        }
+
+        Ok(())
+    }
+
+    /// Presents locations in the quickfix or location list grouped by file, sorted by position,
+    /// with a non-jumpable header entry with the number of locations in front of every file.
+    fn present_grouped_locations(&self, title: &str, mut locations: Vec<Location>) -> Result<()> {
+        locations.sort_by(|a, b| {
+            (a.uri.as_str(), a.range.start.line, a.range.start.character).cmp(&(
+                b.uri.as_str(),
+                b.range.start.line,
+                b.range.start.character,
+            ))
+        });
+
+        let cwd: String = self.vim()?.eval("getcwd()")?;
+        let list =
+            self.with_line_cache(|lines| self.grouped_locations_list(&cwd, &locations, lines))?;
+        self.populate_list(ListKind::Locations, title, &list, locations.len())
+    }
+
+    /// Builds the entries of `present_grouped_locations`.
+    fn grouped_locations_list(
+        &self,
+        cwd: &str,
+        locations: &[Location],
+        lines: &LineCache,
+    ) -> Result<Vec<QuickfixEntry>> {
+        let mut list = vec![];
+        for (uri, group) in &locations.iter().group_by(|loc| &loc.uri) {
+            let group: Vec<_> = group.collect();
+            let filename = uri.filepath()?;
+            let relpath = diff_paths(&filename, Path::new(&cwd)).unwrap_or(filename);
+            list.push(QuickfixEntry {
+                filename: "".into(),
+                lnum: 0,
+                col: None,
+                nr: None,
+                text: Some(format!(
+                    "{} ({} references)",
+                    relpath.to_string_lossy(),
+                    group.len()
+                )),
+                typ: None,
+                valid: Some(false),
+            });
+            for loc in group {
+                list.push(loc.quickfix_item(self, lines)?);
+            }
+        }
+        Ok(list)
+    }
+
+    /// Replaces (or pushes, depending on `LanguageClient_selectionUI_newList`) the quickfix or
+    /// location list, whichever is the selection UI, with the given entries.
+    fn populate_list(
+        &self,
+        kind: ListKind,
+        title: &str,
+        list: &[QuickfixEntry],
+        len: usize,
+    ) -> Result<()> {
+        let selection_ui = self.get_config(|c| c.selection_ui)?;
+        let selection_ui_auto_open = self.should_open_list(kind, len)?;
+        // Either push a new list onto the stack, or replace the current one.
+        let action = if self.get_config(|c| c.selection_ui_new_list)? {
+            " "
+        } else {
+            "r"
+        };
+
+        if let SelectionUI::LocationList = selection_ui {
+            self.vim()?.setloclist(list, action, title)?;
+            if selection_ui_auto_open {
+                let cmd = self.list_open_command("locationList", "lopen")?;
+                self.vim()?.command(cmd)?;
+            }
+            self.vim()?.echo("Populated location list.")?;
+        } else {
+            self.vim()?.setqflist(list, action, title)?;
+            if selection_ui_auto_open {
+                let cmd = self.list_open_command("quickfix", "botright copen")?;
+                self.vim()?.command(cmd)?;
             }
+            self.vim()?.echo("Populated quickfix list.")?;
         }
 
         Ok(())
     }
 
+    /// The command to open the quickfix or location list with, as configured with
+    /// `LanguageClient_listOpenCommand`.
+    fn list_open_command(&self, list: &str, default: &str) -> Result<String> {
+        self.get_config(|c| {
+            c.list_open_command
+                .get(list)
+                .cloned()
+                .unwrap_or_else(|| default.to_string())
+        })
+    }
+
+    /// Whether a list of the given kind and length should be opened after being populated. Lists
+    /// of diagnostics are not opened unless explicitly configured to.
+    fn should_open_list(&self, kind: ListKind, len: usize) -> Result<bool> {
+        self.get_config(|c| {
+            let enabled = c
+                .selection_ui_auto_open_kinds
+                .get(&kind)
+                .copied()
+                .unwrap_or_else(|| kind != ListKind::Diagnostics && c.selection_ui_auto_open);
+            enabled && len > c.selection_ui_auto_open_threshold
+        })
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn workspace_symbol(&self, params: &Value) -> Result<Value> {
         self.text_document_did_change(params)?;
@@ -1747,12 +3379,23 @@ impl LanguageClient {
         let symbols = <Vec<SymbolInformation>>::deserialize(&result)?;
         let title = "[LC]: workspace symbols";
 
-        self.present_list(title, &symbols)?;
+        self.present_list(ListKind::Symbols, title, &symbols)?;
         Ok(result)
     }
 
     #[tracing::instrument(level = "info", skip(self))]
     pub fn workspace_execute_command(&self, params: &Value) -> Result<Value> {
+        let command: String =
+            try_get("command", params)?.ok_or_else(|| anyhow!("command not found in request!"))?;
+        if !self.confirm_command(&command)? {
+            return Ok(Value::Null);
+        }
+
+        self.execute_server_command(params)
+    }
+
+    /// Has the server run a command, without checking it against the allowed commands.
+    fn execute_server_command(&self, params: &Value) -> Result<Value> {
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
         let command: String =
@@ -1791,6 +3434,27 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Answers a server asking for the settings in some sections, from the initialization options
+    /// merged with the current workspace settings.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn workspace_configuration(&self, language_id: &str, params: &Value) -> Result<Value> {
+        let params = ConfigurationParams::deserialize(params)?;
+        let root =
+            self.get_state(|state| state.roots.get(language_id).cloned().unwrap_or_default())?;
+        let workspace_settings = self
+            .get_workspace_settings(language_id, &root)
+            .unwrap_or_default();
+        let settings =
+            self.get_state(|state| state.initialization_options.combine(&workspace_settings))?;
+
+        let values: Vec<Value> = params
+            .items
+            .iter()
+            .map(|item| configuration_section(&settings, item.section.as_deref()))
+            .collect();
+        Ok(json!(values))
+    }
+
     pub fn handle_code_lens_action(&self, params: &Value) -> Result<Value> {
         let filename = self.vim()?.get_filename(params)?;
         let line = self.vim()?.get_position(params)?.line;
@@ -1810,13 +3474,64 @@ impl LanguageClient {
             return Ok(Value::Null);
         }
 
+        self.present_code_lens_actions(&code_lens, false)?;
+        Ok(Value::Null)
+    }
+
+    /// Lists the code lenses of the current file, so that they can be jumped to, or with `execute`
+    /// set, so that the command of the selected one is run.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn code_lens_list(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let execute: bool = try_get("execute", params)?.unwrap_or_default();
+
+        let mut code_lens: Vec<CodeLens> = self.get_state(|state| {
+            state
+                .code_lens
+                .get(&filename)
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|cl| cl.command.is_some())
+                .collect()
+        })?;
+        if code_lens.is_empty() {
+            self.vim()?.echowarn("No code lenses found!")?;
+            return Ok(Value::Null);
+        }
+        code_lens.sort_by_key(|cl| (cl.range.start.line, cl.range.start.character));
+
+        if execute {
+            self.present_code_lens_actions(&code_lens, true)?;
+        } else {
+            let items: Vec<_> = code_lens
+                .into_iter()
+                .map(|code_lens| CodeLensItem {
+                    filename: filename.clone(),
+                    code_lens,
+                })
+                .collect();
+            let title = format!("[LC]: code lenses for {}", filename);
+            self.present_list(ListKind::Locations, &title, &items)?;
+        }
+
+        Ok(Value::Null)
+    }
+
+    /// Presents the commands of the given code lenses for selection, and runs the selected one.
+    /// With `with_line`, titles are prefixed with the line of the code lens.
+    fn present_code_lens_actions(&self, code_lens: &[CodeLens], with_line: bool) -> Result<()> {
         let actions: Result<Vec<CodeAction>> = code_lens
             .iter()
             .map(|cl| match &cl.command {
                 None => Err(anyhow!("no command, skipping")),
                 Some(cmd) => Ok(CodeAction {
                     kind: Some(cmd.command.clone().into()),
-                    title: cmd.title.clone(),
+                    title: if with_line {
+                        format!("line {}: {}", cl.range.start.line + 1, cmd.title)
+                    } else {
+                        cmd.title.clone()
+                    },
                     command: cl.clone().command,
                     diagnostics: None,
                     edit: None,
@@ -1851,22 +3566,90 @@ impl LanguageClient {
             self.handle_code_action_selection(&actions, idx)
         })?;
 
-        Ok(Value::Null)
+        Ok(())
     }
 
     #[tracing::instrument(level = "info", skip(self))]
-    pub fn progress(&self, params: &Value) -> Result<()> {
+    pub fn progress(&self, language_id: &str, params: &Value) -> Result<()> {
+        // Partial results of workspace diagnostics are streamed as progress.
+        if params.get("token") == Some(&json!(WORKSPACE_DIAGNOSTIC_PARTIAL_RESULT_TOKEN)) {
+            let report = WorkspaceDiagnosticReport::deserialize(&params["value"])?;
+            return self.process_workspace_diagnostic_report(language_id, report);
+        }
+
         let params = ProgressParams::deserialize(params)?;
-        let message = match params.value {
-            ProgressParamsValue::WorkDone(wd) => match wd {
+        let key = match &params.token {
+            NumberOrString::Number(n) => n.to_string(),
+            NumberOrString::String(s) => s.clone(),
+        };
+        let ProgressParamsValue::WorkDone(wd) = params.value;
+        let status = self.update_state(|state| {
+            let progress = state.progress.entry(language_id.into()).or_default();
+            match &wd {
                 WorkDoneProgress::Begin(r) => {
-                    Some(format!("{} {}", r.title, r.message.unwrap_or_default()))
+                    progress.insert(
+                        key.clone(),
+                        ProgressState {
+                            title: r.title.clone(),
+                            message: r.message.clone(),
+                            percentage: r.percentage,
+                            frame: 0,
+                        },
+                    );
                 }
-                WorkDoneProgress::Report(r) => r.message,
-                // WorkDoneProgress::End has no value, so we return Done, otherwise the previous
-                // message would be left in screen and it would appear as if it didn't ever finish.
-                WorkDoneProgress::End(_) => Some("Done".into()),
-            },
+                WorkDoneProgress::Report(r) => {
+                    if let Some(progress) = progress.get_mut(&key) {
+                        if r.message.is_some() {
+                            progress.message = r.message.clone();
+                        }
+                        if r.percentage.is_some() {
+                            progress.percentage = r.percentage;
+                        }
+                        progress.frame += 1;
+                    }
+                }
+                WorkDoneProgress::End(_) => {
+                    progress.remove(&key);
+                }
+            }
+            if progress.is_empty() {
+                state.progress.remove(language_id);
+            }
+
+            Ok(state
+                .progress
+                .values()
+                .flat_map(BTreeMap::values)
+                .map(ProgressState::status_message)
+                .collect::<Vec<_>>()
+                .join(" | "))
+        })?;
+
+        self.vim()?.command(vec![
+            format!(
+                "let {}={}",
+                VIM_SERVER_STATUS,
+                if status.is_empty() { 0 } else { 1 }
+            ),
+            format!(
+                "let {}='{}'",
+                VIM_SERVER_STATUS_MESSAGE,
+                &escape_single_quote(status)
+            ),
+        ])?;
+
+        if !self.get_config(|c| c.progress_echo)? {
+            return Ok(());
+        }
+
+        let message = match wd {
+            WorkDoneProgress::Begin(r) => {
+                Some(format!("{} {}", r.title, r.message.unwrap_or_default()))
+            }
+            WorkDoneProgress::Report(r) => r.message,
+            // WorkDoneProgress::End has no value, so we return Done, otherwise the previous
+            // message would be left in screen and it would appear as if it didn't ever finish.
+            WorkDoneProgress::End(_) => Some("Done".into()),
         };
 
         if message.is_none() {
@@ -1943,32 +3726,135 @@ impl LanguageClient {
         Ok(Value::Null)
     }
 
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_document_link(&self, params: &Value) -> Result<Value> {
+        self.text_document_did_change(params)?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+
+        let result: Value = self.get_client(&Some(language_id))?.call(
+            lsp_types::request::DocumentLinkRequest::METHOD,
+            DocumentLinkParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            },
+        )?;
+
+        let links = <Option<Vec<DocumentLink>>>::deserialize(&result)?.unwrap_or_default();
+        self.update_state(|state| {
+            state.document_links.insert(filename, links);
+            Ok(())
+        })?;
+
+        Ok(result)
+    }
+
+    /// Opens the target of the document link under the cursor, resolving it first if the server
+    /// left the target out. Files are opened in vim, anything else is handed to `s:OpenBrowser`.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn follow_document_link(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let position = self.get_position(&filename, params)?;
+
+        self.text_document_document_link(params)?;
+        let link = self.get_state(|state| {
+            state.document_links.get(&filename).and_then(|links| {
+                links
+                    .iter()
+                    .find(|link| link.range.start <= position && position < link.range.end)
+                    .cloned()
+            })
+        })?;
+        let link = match link {
+            Some(link) => link,
+            None => {
+                self.vim()?.echowarn("No document link found")?;
+                return Ok(Value::Null);
+            }
+        };
+
+        let link = if link.target.is_none() {
+            let can_resolve = self.get_state(|state| {
+                state
+                    .capabilities
+                    .get(&language_id)
+                    .and_then(|result| result.capabilities.document_link_provider.as_ref())
+                    .and_then(|provider| provider.resolve_provider)
+                    .unwrap_or_default()
+            })?;
+            if !can_resolve {
+                self.vim()?.echowarn("Document link has no target")?;
+                return Ok(Value::Null);
+            }
+
+            let link: DocumentLink = self
+                .get_client(&Some(language_id))?
+                .call(lsp_types::request::DocumentLinkResolve::METHOD, &link)?;
+            link
+        } else {
+            link
+        };
+
+        let target = match link.target {
+            Some(ref target) => target.clone(),
+            None => {
+                self.vim()?.echowarn("Document link has no target")?;
+                return Ok(Value::Null);
+            }
+        };
+
+        if target.scheme() == "file" {
+            self.vim()?.edit(&None, target.filepath()?)?;
+        } else {
+            self.vim()?
+                .rpcclient
+                .notify("s:OpenBrowser", json!([target.as_str()]))?;
+        }
+
+        Ok(serde_json::to_value(link)?)
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn text_document_did_open(&self, params: &Value) -> Result<()> {
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
         let text = self.vim()?.get_text(&filename)?;
-        let set_omnifunc: bool = self
+        let set_omnifunc: Value = self
             .vim()?
             .eval("s:GetVar('LanguageClient_setOmnifunc', v:true)")?;
+        let set_omnifunc = is_enabled_for(&set_omnifunc, &language_id);
 
-        let text_document = TextDocumentItem {
-            uri: filename.to_url()?,
-            language_id: language_id.clone(),
-            version: 0,
-            text: text.join("\n"),
-        };
-
-        self.update_state(|state| {
-            Ok(state
-                .text_documents
-                .insert(filename.clone(), text_document.clone()))
+        // A document whose buffer was deleted within the close grace period is still open on the
+        // server, so it only has to be brought up to date.
+        let reopened = self.update_state(|state| {
+            Ok(state.pending_closes.remove(&filename).is_some()
+                && state.text_documents.contains_key(&filename))
         })?;
+        if reopened {
+            self.text_document_did_change(params)?;
+        } else {
+            let text_document = TextDocumentItem {
+                uri: filename.to_url()?,
+                language_id: language_id.clone(),
+                version: 0,
+                text: text.join("\n"),
+            };
 
-        self.get_client(&Some(language_id.clone()))?.notify(
-            lsp_types::notification::DidOpenTextDocument::METHOD,
-            DidOpenTextDocumentParams { text_document },
-        )?;
+            self.update_state(|state| {
+                Ok(state
+                    .text_documents
+                    .insert(filename.clone(), text_document.clone()))
+            })?;
+
+            self.get_client(&Some(language_id.clone()))?.notify(
+                lsp_types::notification::DidOpenTextDocument::METHOD,
+                DidOpenTextDocumentParams { text_document },
+            )?;
+        }
 
         if set_omnifunc {
             self.vim()?
@@ -1978,14 +3864,67 @@ impl LanguageClient {
             self.get_state(|state| state.roots.get(&language_id).cloned().unwrap_or_default())?;
         self.vim()?.rpcclient.notify(
             "setbufvar",
-            json!([filename, "LanguageClient_projectRoot", root]),
+            json!([bufexpr(&filename), "LanguageClient_projectRoot", root]),
         )?;
         self.vim()?
             .rpcclient
             .notify("s:ExecuteAutocmd", "LanguageClientTextDocumentDidOpenPost")?;
 
-        self.text_document_code_lens(params)?;
-        self.text_document_inlay_hints(&language_id, &filename)?;
+        // Opening many files at once would flood the server with requests, so they are deferred
+        // until no file was opened for a while.
+        if let Some(delay) = self.get_config(|c| c.did_open_delay)? {
+            let opened_at = Instant::now();
+            self.update_state(|state| {
+                state
+                    .pending_opens
+                    .insert(filename.clone(), language_id.clone());
+                state.last_open = Some(opened_at);
+                Ok(())
+            })?;
+            let language_client = self.clone();
+            thread::spawn(move || {
+                thread::sleep(delay);
+                if let Err(err) = language_client.process_pending_opens(opened_at) {
+                    error!("Error processing opened documents: {:?}", err);
+                }
+            });
+            return Ok(());
+        }
+
+        self.after_did_open(&filename, &language_id)
+    }
+
+    /// Makes the requests that follow opening a document.
+    fn after_did_open(&self, filename: &str, language_id: &str) -> Result<()> {
+        let params = json!({
+            "filename": filename,
+            "languageId": language_id,
+        });
+        self.pull_diagnostics(&params);
+        self.text_document_code_lens(&params)?;
+        self.text_document_inlay_hints(language_id, filename)?;
+
+        Ok(())
+    }
+
+    /// Makes the deferred requests of the documents opened, unless another document was opened
+    /// since `opened_at`, in which case they are made after that one.
+    fn process_pending_opens(&self, opened_at: Instant) -> Result<()> {
+        let pending_opens = self.update_state(|state| {
+            if state.last_open != Some(opened_at) {
+                return Ok(HashMap::new());
+            }
+            state.last_open = None;
+            Ok(std::mem::take(&mut state.pending_opens))
+        })?;
+
+        for (filename, language_id) in pending_opens {
+            // The buffer may have been deleted since.
+            if !self.get_state(|state| state.text_documents.contains_key(&filename))? {
+                continue;
+            }
+            self.after_did_open(&filename, &language_id)?;
+        }
 
         Ok(())
     }
@@ -2032,20 +3971,29 @@ impl LanguageClient {
             Ok(version)
         })?;
 
-        self.get_client(&Some(language_id.clone()))?.notify(
-            lsp_types::notification::DidChangeTextDocument::METHOD,
-            DidChangeTextDocumentParams {
-                text_document: VersionedTextDocumentIdentifier {
-                    uri: filename.to_url()?,
-                    version: Some(version),
+        let sync_kind = self.get_state(|state| {
+            state
+                .text_document_sync_kinds
+                .get(&language_id)
+                .copied()
+                .unwrap_or(TextDocumentSyncKind::Full)
+        })?;
+        if sync_kind != TextDocumentSyncKind::None {
+            self.get_client(&Some(language_id.clone()))?.notify(
+                lsp_types::notification::DidChangeTextDocument::METHOD,
+                DidChangeTextDocumentParams {
+                    text_document: VersionedTextDocumentIdentifier {
+                        uri: filename.to_url()?,
+                        version: Some(version),
+                    },
+                    content_changes: vec![TextDocumentContentChangeEvent {
+                        range: None,
+                        range_length: None,
+                        text,
+                    }],
                 },
-                content_changes: vec![TextDocumentContentChangeEvent {
-                    range: None,
-                    range_length: None,
-                    text,
-                }],
-            },
-        )?;
+            )?;
+        }
 
         self.text_document_code_lens(params)?;
         self.text_document_inlay_hints(&language_id, &filename)?;
@@ -2062,15 +4010,37 @@ impl LanguageClient {
         }
 
         let uri = filename.to_url()?;
+        // Some servers only lint on save, using the text sent along when they asked for it.
+        let include_text = self.get_state(|state| {
+            match state
+                .capabilities
+                .get(&language_id)
+                .and_then(|result| result.capabilities.text_document_sync.as_ref())
+            {
+                Some(TextDocumentSyncCapability::Options(options)) => match options.save {
+                    Some(TextDocumentSyncSaveOptions::SaveOptions(ref save)) => {
+                        save.include_text == Some(true)
+                    }
+                    _ => false,
+                },
+                _ => false,
+            }
+        })?;
+        let text = if include_text {
+            Some(self.vim()?.get_text(&filename)?.join("\n"))
+        } else {
+            None
+        };
 
         self.get_client(&Some(language_id.clone()))?.notify(
             lsp_types::notification::DidSaveTextDocument::METHOD,
             DidSaveTextDocumentParams {
-                text: None,
+                text,
                 text_document: TextDocumentIdentifier { uri },
             },
         )?;
 
+        self.pull_diagnostics(params);
         self.draw_virtual_texts(params)?;
 
         Ok(())
@@ -2092,6 +4062,251 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Pulls the diagnostics of a file from a server that prefers diagnostics to be pulled, and
+    /// processes them the same way as published diagnostics.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn text_document_diagnostic(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let identifier =
+            match self.get_state(|state| state.pull_diagnostics.get(&language_id).cloned())? {
+                Some(identifier) => identifier,
+                None => return Ok(Value::Null),
+            };
+
+        let uri = filename.to_url()?;
+        let canonical_filename = filename.canonicalize();
+        let previous_result_id = self.get_state(|state| {
+            state
+                .diagnostics_result_ids
+                .get(&language_id)
+                .and_then(|ids| ids.get(&canonical_filename))
+                .cloned()
+        })?;
+        let result: Value = self.get_client(&Some(language_id.clone()))?.call(
+            DocumentDiagnosticRequest::METHOD,
+            DocumentDiagnosticParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                identifier,
+                previous_result_id,
+            },
+        )?;
+
+        match DocumentDiagnosticReport::deserialize(&result)? {
+            DocumentDiagnosticReport::Full { result_id, items } => {
+                self.update_state(|state| {
+                    let ids = state
+                        .diagnostics_result_ids
+                        .entry(language_id.clone())
+                        .or_default();
+                    match result_id {
+                        Some(id) => ids.insert(canonical_filename.clone(), id),
+                        None => ids.remove(&canonical_filename),
+                    };
+                    Ok(())
+                })?;
+                self.text_document_publish_diagnostics(&serde_json::to_value(
+                    PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: items,
+                        version: None,
+                    },
+                )?)?;
+            }
+            // Diagnostics didn't change since the last report.
+            DocumentDiagnosticReport::Unchanged { .. } => {}
+        }
+
+        Ok(result)
+    }
+
+    /// Pulls the diagnostics of every file of the workspace, for servers that prefer diagnostics to
+    /// be pulled. Files whose diagnostics didn't change since they were last pulled are reported as
+    /// unchanged by the server, and results are processed as the server streams them in.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn workspace_diagnostic(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        if !self.get_state(|state| state.workspace_diagnostics.contains(&language_id))? {
+            self.vim()?
+                .echowarn("Server doesn't support workspace diagnostics")?;
+            return Ok(Value::Null);
+        }
+
+        // Only the result ids this server reported are sent back to it.
+        let previous_result_ids: Vec<PreviousResultId> = self.update_state(|state| {
+            state.workspace_diagnostics_files.clear();
+            Ok(state
+                .diagnostics_result_ids
+                .get(&language_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|(filename, id)| {
+                    Some(PreviousResultId {
+                        uri: filename.to_url().ok()?,
+                        value: id.clone(),
+                    })
+                })
+                .collect())
+        })?;
+        let identifier =
+            self.get_state(|state| state.pull_diagnostics.get(&language_id).cloned().flatten())?;
+
+        self.vim()?.echomsg("Pulling workspace diagnostics...")?;
+        let result: Value = self.get_client(&Some(language_id.clone()))?.call(
+            WorkspaceDiagnosticRequest::METHOD,
+            WorkspaceDiagnosticParams {
+                identifier,
+                previous_result_ids,
+                work_done_token: Some(NumberOrString::String(
+                    WORKSPACE_DIAGNOSTIC_PROGRESS_TOKEN.into(),
+                )),
+                partial_result_token: Some(NumberOrString::String(
+                    WORKSPACE_DIAGNOSTIC_PARTIAL_RESULT_TOKEN.into(),
+                )),
+            },
+        )?;
+
+        let report = WorkspaceDiagnosticReport::deserialize(&result)?;
+        self.process_workspace_diagnostic_report(&language_id, report)?;
+
+        // Only the diagnostics of the files in the report, streamed or not, are counted.
+        let count = self.get_state(|state| {
+            state
+                .workspace_diagnostics_files
+                .iter()
+                .filter_map(|filename| state.diagnostics.get(filename))
+                .map(Vec::len)
+                .sum::<usize>()
+        })?;
+        self.vim()?
+            .echomsg(format!("Workspace diagnostics: {} found", count))?;
+
+        Ok(result)
+    }
+
+    /// Stores the result ids of the reports of a workspace diagnostics report, and processes the
+    /// diagnostics of the files that changed the same way as published diagnostics.
+    fn process_workspace_diagnostic_report(
+        &self,
+        language_id: &str,
+        report: WorkspaceDiagnosticReport,
+    ) -> Result<()> {
+        for item in report.items {
+            let (uri, result_id, items) = match item {
+                WorkspaceDocumentDiagnosticReport::Full {
+                    uri,
+                    result_id,
+                    items,
+                    ..
+                } => (uri, result_id, Some(items)),
+                // Diagnostics didn't change since the last report.
+                WorkspaceDocumentDiagnosticReport::Unchanged { uri, result_id, .. } => {
+                    (uri, Some(result_id), None)
+                }
+            };
+
+            let filename = uri.filepath()?.canonicalize();
+            self.update_state(|state| {
+                state.workspace_diagnostics_files.insert(filename.clone());
+                let ids = state
+                    .diagnostics_result_ids
+                    .entry(language_id.to_owned())
+                    .or_default();
+                match result_id {
+                    Some(id) => ids.insert(filename.clone(), id),
+                    None => ids.remove(&filename),
+                };
+                Ok(())
+            })?;
+
+            if let Some(items) = items {
+                self.text_document_publish_diagnostics(&serde_json::to_value(
+                    PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: items,
+                        version: None,
+                    },
+                )?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the server check a file again, by pulling its diagnostics from servers that support it,
+    /// or else by telling the server the file was saved, which makes most servers check it.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn refresh_diagnostics(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        self.text_document_did_change(params)?;
+
+        if self.get_state(|state| state.pull_diagnostics.contains_key(&language_id))? {
+            // Without a previous result id the server reports the diagnostics even if they didn't
+            // change.
+            self.update_state(|state| {
+                if let Some(ids) = state.diagnostics_result_ids.get_mut(&language_id) {
+                    ids.remove(&filename.canonicalize());
+                }
+                Ok(())
+            })?;
+            self.text_document_diagnostic(params)?;
+            self.draw_virtual_texts(params)?;
+        } else {
+            self.text_document_did_save(params)?;
+        }
+
+        Ok(Value::Null)
+    }
+
+    /// Pulls diagnostics for a file, logging rather than failing on errors, as the diagnostics are
+    /// pulled in the background of other operations.
+    fn pull_diagnostics(&self, params: &Value) {
+        if let Err(err) = self.text_document_diagnostic(params) {
+            warn!("Failed to pull diagnostics: {:?}", err);
+        }
+    }
+
+    /// Pulls diagnostics for a file once its text stopped changing for a while, see
+    /// `LanguageClient_diagnosticsPullDelay`.
+    fn pull_diagnostics_debounced(&self, filename: &str, params: &Value) -> Result<()> {
+        let delay = match self.get_config(|c| c.diagnostics_pull_delay)? {
+            Some(delay) => delay,
+            None => {
+                self.pull_diagnostics(params);
+                return Ok(());
+            }
+        };
+
+        let changed_at = Instant::now();
+        self.update_state(|state| {
+            state
+                .pending_diagnostic_pulls
+                .insert(filename.to_owned(), changed_at);
+            Ok(())
+        })?;
+        let language_client = self.clone();
+        let filename = filename.to_owned();
+        let params = params.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let latest = language_client.update_state(|state| {
+                if state.pending_diagnostic_pulls.get(&filename) != Some(&changed_at) {
+                    return Ok(false);
+                }
+                state.pending_diagnostic_pulls.remove(&filename);
+                Ok(true)
+            });
+            match latest {
+                Ok(true) => language_client.pull_diagnostics(&params),
+                Ok(false) => {}
+                Err(err) => error!("Error pulling diagnostics: {:?}", err),
+            }
+        });
+        Ok(())
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn text_document_publish_diagnostics(&self, params: &Value) -> Result<()> {
         let params = PublishDiagnosticsParams::deserialize(params)?;
@@ -2171,15 +4386,12 @@ impl LanguageClient {
             *count += 1;
         }
 
-        if let Ok(bufnr) = self
-            .vim()?
-            .eval::<_, Bufnr>(format!("bufnr('{}')", filename))
-        {
+        if let Ok(bufnr) = self.vim()?.get_bufnr(&filename, &Value::Null) {
             // Some Language Server diagnoses non-opened buffer, so we must check if buffer exists.
             if bufnr > 0 {
                 self.vim()?.rpcclient.notify(
                     "setbufvar",
-                    json!([filename, VIM_STATUS_LINE_DIAGNOSTICS_COUNTS, severity_count]),
+                    json!([bufnr, VIM_STATUS_LINE_DIAGNOSTICS_COUNTS, severity_count]),
                 )?;
             }
         }
@@ -2338,16 +4550,19 @@ impl LanguageClient {
             let num_new_semantic_hls = highlights.len();
 
             self.update_state(|state| {
-                state.vim.rpcclient.notify(
-                    "s:ApplySemanticHighlights",
-                    json!([buffer, ns_id, clears, highlights]),
-                )?;
+                // The highlights are still tracked while disabled, to show them when enabled.
+                if state.semantic_highlight_enabled {
+                    state.vim.rpcclient.notify(
+                        "s:ApplySemanticHighlights",
+                        json!([buffer, ns_id, clears, highlights]),
+                    )?;
+                }
 
                 let old_semantic_hl_state = state
                     .semantic_highlights
-                    .insert(language_id.clone(), semantic_hl_state);
+                    .insert(filename.clone(), semantic_hl_state);
 
-                let semantic_hl_state = state.semantic_highlights.get_mut(&language_id).unwrap();
+                let semantic_hl_state = state.semantic_highlights.get_mut(&filename).unwrap();
 
                 let mut combined_hls = Vec::with_capacity(highlights.len());
 
@@ -2414,7 +4629,7 @@ impl LanguageClient {
             self.update_state(|state| {
                 state
                     .semantic_highlights
-                    .insert(language_id.clone(), semantic_hl_state);
+                    .insert(filename.clone(), semantic_hl_state);
                 Ok(())
             })?;
         }
@@ -2442,6 +4657,18 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Writes the traces of the execution of a server to the log.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn log_trace(&self, language_id: &str, params: &Value) -> Result<()> {
+        let params = LogTraceParams::deserialize(params)?;
+        match params.verbose {
+            Some(verbose) => debug!("[{}] {}\n{}", language_id, params.message, verbose),
+            None => debug!("[{}] {}", language_id, params.message),
+        }
+
+        Ok(())
+    }
+
     // shows the given message in vim.
     #[tracing::instrument(level = "info", skip(self))]
     pub fn window_show_message(&self, params: &Value) -> Result<()> {
@@ -2486,6 +4713,45 @@ impl LanguageClient {
         Ok(v)
     }
 
+    /// Shows the document or URL the server asks for. External resources are handed to
+    /// `s:OpenBrowser`, files are opened in vim, or only added to the buffer list if the server
+    /// doesn't want them to take focus.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn window_show_document(&self, params: &Value) -> Result<Value> {
+        let params = ShowDocumentParams::deserialize(params)?;
+        if params.external.unwrap_or_default() || params.uri.scheme() != "file" {
+            self.vim()?
+                .rpcclient
+                .notify("s:OpenBrowser", json!([params.uri.as_str()]))?;
+            return Ok(serde_json::to_value(ShowDocumentResult { success: true })?);
+        }
+
+        let path = params.uri.filepath()?;
+        if !params.take_focus.unwrap_or(true) {
+            self.vim()?
+                .rpcclient
+                .notify("bufadd", json!([path.to_string_lossy()]))?;
+            return Ok(serde_json::to_value(ShowDocumentResult { success: true })?);
+        }
+
+        self.vim()?.edit(&None, &path)?;
+        if let Some(selection) = params.selection {
+            let lines = LineCache::default();
+            let selection = Range::new(
+                self.position_to_bytes(&lines, &path, selection.start)?,
+                self.position_to_bytes(&lines, &path, selection.end)?,
+            );
+            if selection.start == selection.end {
+                self.vim()?
+                    .cursor(selection.start.line + 1, selection.start.character + 1)?;
+            } else {
+                self.vim()?.select_range(&selection)?;
+            }
+        }
+
+        Ok(serde_json::to_value(ShowDocumentResult { success: true })?)
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn client_register_capability(&self, language_id: &str, params: &Value) -> Result<Value> {
         let params = RegistrationParams::deserialize(params)?;
@@ -2533,16 +4799,44 @@ impl LanguageClient {
                         Ok(())
                     })?;
                 }
-                _ => {
-                    warn!("Unknown registration: {:?}", r);
-                }
+                method => match registered_capability(method) {
+                    Some(capability) => self.update_state(|state| {
+                        if let Some(result) = state.capabilities.get_mut(language_id) {
+                            let options = r.register_options.as_ref().unwrap_or(&Value::Null);
+                            result.capabilities =
+                                set_capability(&result.capabilities, capability, Some(options))?;
+                        }
+                        Ok(())
+                    })?,
+                    None => warn!("Unknown registration: {:?}", r),
+                },
             }
         }
 
+        let completion_registered = params
+            .registrations
+            .iter()
+            .any(|r| r.method == lsp_types::request::Completion::METHOD);
         self.update_state(|state| {
-            state.registrations.extend(params.registrations);
+            state
+                .registrations
+                .entry(language_id.to_owned())
+                .or_default()
+                .extend(params.registrations);
             Ok(())
         })?;
+
+        if completion_registered {
+            let result = self.get_state(|state| {
+                state
+                    .capabilities
+                    .get(language_id)
+                    .map(serde_json::to_value)
+            })?;
+            if let Some(result) = result {
+                self.register_completion_sources(language_id, &result?)?;
+            }
+        }
         Ok(Value::Null)
     }
 
@@ -2550,17 +4844,19 @@ impl LanguageClient {
     pub fn client_unregister_capability(&self, language_id: &str, params: &Value) -> Result<Value> {
         let params = UnregistrationParams::deserialize(params)?;
         let mut regs_removed = vec![];
-        for r in &params.unregisterations {
-            if let Some(idx) = self.get_state(|state| {
-                state
-                    .registrations
-                    .iter()
-                    .position(|i| i.id == r.id && i.method == r.method)
-            })? {
-                regs_removed
-                    .push(self.update_state(|state| Ok(state.registrations.swap_remove(idx)))?);
+        self.update_state(|state| {
+            if let Some(registrations) = state.registrations.get_mut(language_id) {
+                for r in &params.unregisterations {
+                    if let Some(idx) = registrations
+                        .iter()
+                        .position(|i| i.id == r.id && i.method == r.method)
+                    {
+                        regs_removed.push(registrations.swap_remove(idx));
+                    }
+                }
             }
-        }
+            Ok(())
+        })?;
 
         for r in &regs_removed {
             match r.method.as_str() {
@@ -2577,9 +4873,16 @@ impl LanguageClient {
                         Ok(())
                     })?;
                 }
-                _ => {
-                    warn!("Unknown registration: {:?}", r);
-                }
+                method => match registered_capability(method) {
+                    Some(capability) => self.update_state(|state| {
+                        if let Some(result) = state.capabilities.get_mut(language_id) {
+                            result.capabilities =
+                                set_capability(&result.capabilities, capability, None)?;
+                        }
+                        Ok(())
+                    })?,
+                    None => warn!("Unknown registration: {:?}", r),
+                },
             }
         }
 
@@ -2595,9 +4898,10 @@ impl LanguageClient {
             .get_client(&Some(language_id.clone()))?
             .call(lsp_types::request::Shutdown::METHOD, Value::Null)?;
 
-        self.vim()?
-            .rpcclient
-            .notify("setbufvar", json!([filename, VIM_IS_SERVER_RUNNING, 0]))?;
+        self.vim()?.rpcclient.notify(
+            "setbufvar",
+            json!([bufexpr(&filename), VIM_IS_SERVER_RUNNING, 0]),
+        )?;
 
         Ok(Value::Null)
     }
@@ -2661,6 +4965,27 @@ impl LanguageClient {
         Ok(Value::Null)
     }
 
+    /// Changes the trace level of the server of the current buffer, and of the servers started
+    /// afterwards.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn set_trace(&self, params: &Value) -> Result<Value> {
+        let trace: TraceOption =
+            try_get("trace", params)?.ok_or_else(|| anyhow!("trace not found!"))?;
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+
+        self.get_client(&Some(language_id.clone()))?
+            .notify(SetTrace::METHOD, SetTraceParams { value: trace })?;
+        self.update_state(|state| {
+            state.traces.insert(language_id, trace);
+            Ok(())
+        })?;
+
+        self.vim()?
+            .echomsg(format!("Language server trace: {}", trace_str(trace)))?;
+        Ok(Value::Null)
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn set_diagnostics_list(&self, params: &Value) -> Result<Value> {
         let diagnostics_list = try_get("diagnosticsList", params)?
@@ -2698,6 +5023,7 @@ impl LanguageClient {
     #[tracing::instrument(level = "info", skip(self))]
     pub fn omnicomplete(&self, params: &Value) -> Result<Value> {
         let result = self.text_document_completion(params)?;
+        let label_details = completion_label_details(&result);
         let result = <Option<CompletionResponse>>::deserialize(result)?;
         let result = result.unwrap_or_else(|| CompletionResponse::Array(vec![]));
         let matches = match result {
@@ -2706,10 +5032,19 @@ impl LanguageClient {
         };
 
         let complete_position: Option<u64> = try_get("complete_position", params)?;
+        let kind_labels = self.get_config(|c| c.completion_kind_labels.clone())?;
 
-        let matches: Result<Vec<VimCompleteItem>> = matches
-            .iter()
-            .map(|item| VimCompleteItem::from_lsp(item, complete_position))
+        let matches: Result<Vec<VimCompleteItem>> = completion_items_order(&matches)
+            .into_iter()
+            .map(|idx| {
+                let details = label_details.get(idx).cloned().flatten();
+                VimCompleteItem::from_lsp(
+                    &matches[idx],
+                    details.as_ref(),
+                    &kind_labels,
+                    complete_position,
+                )
+            })
             .collect();
         let matches = matches?;
         Ok(serde_json::to_value(matches)?)
@@ -2736,6 +5071,61 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Whether the server registered for the given file operation on files matching the file.
+    fn has_file_operation(
+        &self,
+        language_id: &str,
+        operation: &str,
+        filename: &str,
+    ) -> Result<bool> {
+        self.get_state(|state| {
+            state
+                .file_operations
+                .get(language_id)
+                .and_then(|operations| operations.get(operation))
+                .map_or(false, |options| {
+                    matches_file_operation_filters(options, filename)
+                })
+        })
+    }
+
+    /// Applies the edits the server wants made before a file is created, e.g. to declare a new
+    /// module, if it registered for file creations matching it.
+    fn will_create_file(&self, language_id: &str, filename: &str) -> Result<()> {
+        if !self.has_file_operation(language_id, "willCreate", filename)? {
+            return Ok(());
+        }
+
+        let params = CreateFilesParams {
+            files: vec![FileCreate {
+                uri: filename.to_url()?,
+            }],
+        };
+        let edit: Option<WorkspaceEdit> = self
+            .get_client(&Some(language_id.to_owned()))?
+            .call(WillCreateFiles::METHOD, &params)?;
+        if let Some(edit) = edit {
+            self.apply_workspace_edit(&edit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Tells the server that a file was created, if it registered for file creations matching it.
+    fn did_create_file(&self, language_id: &str, filename: &str) -> Result<()> {
+        if !self.has_file_operation(language_id, "didCreate", filename)? {
+            return Ok(());
+        }
+
+        let params = CreateFilesParams {
+            files: vec![FileCreate {
+                uri: filename.to_url()?,
+            }],
+        };
+        self.get_client(&Some(language_id.to_owned()))?
+            .notify(DidCreateFiles::METHOD, params)
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn handle_buf_enter(&self, params: &Value) -> Result<()> {
         if self.vim()?.get_filename(params)?.is_empty() {
@@ -2743,16 +5133,21 @@ impl LanguageClient {
         }
 
         let filename = self.vim()?.get_filename(params)?.canonicalize();
+        if self.vim()?.is_buffer_disabled(&filename)? {
+            return Ok(());
+        }
         let language_id = self.vim()?.get_language_id(&filename, params)?;
 
         if self.get_state(|state| state.clients.contains_key(&Some(language_id.clone())))? {
-            self.vim()?
-                .rpcclient
-                .notify("setbufvar", json!([filename, VIM_IS_SERVER_RUNNING, 1]))?;
+            self.vim()?.rpcclient.notify(
+                "setbufvar",
+                json!([bufexpr(&filename), VIM_IS_SERVER_RUNNING, 1]),
+            )?;
         } else {
-            self.vim()?
-                .rpcclient
-                .notify("setbufvar", json!([filename, VIM_IS_SERVER_RUNNING, 0]))?;
+            self.vim()?.rpcclient.notify(
+                "setbufvar",
+                json!([bufexpr(&filename), VIM_IS_SERVER_RUNNING, 0]),
+            )?;
         }
         Ok(())
     }
@@ -2764,6 +5159,9 @@ impl LanguageClient {
         }
 
         let filename = self.vim()?.get_filename(params)?.canonicalize();
+        if self.vim()?.is_buffer_disabled(&filename)? {
+            return Ok(());
+        }
         let language_id = self.vim()?.get_language_id(&filename, params)?;
 
         if self.get_state(|state| state.clients.contains_key(&Some(language_id.clone())))? {
@@ -2817,12 +5215,37 @@ impl LanguageClient {
         }
 
         self.text_document_did_change(params)?;
+        self.pull_diagnostics_debounced(&filename, params)?;
         Ok(())
     }
 
+    /// Runs before a buffer is written: a new file is about to be created by the write, and the
+    /// server may want to edit the document before it is saved.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn handle_buf_write_pre(&self, params: &Value) -> Result<Value> {
+        if try_get("newFile", params)?.unwrap_or(false) {
+            let filename = self.vim()?.get_filename(params)?;
+            let language_id = self.vim()?.get_language_id(&filename, params)?;
+            if let Err(err) = self.will_create_file(&language_id, &filename) {
+                warn!("Failed to notify the server of the new file. {}", err);
+            }
+        }
+
+        self.text_document_will_save_wait_until(params)
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     pub fn handle_buf_write_post(&self, params: &Value) -> Result<()> {
         self.text_document_did_save(params)?;
+
+        if try_get("newFile", params)?.unwrap_or(false) {
+            let filename = self.vim()?.get_filename(params)?;
+            let language_id = self.vim()?.get_language_id(&filename, params)?;
+            if let Err(err) = self.did_create_file(&language_id, &filename) {
+                warn!("Failed to notify the server of the new file. {}", err);
+            }
+        }
+
         Ok(())
     }
 
@@ -2834,6 +5257,25 @@ impl LanguageClient {
             return Ok(());
         }
 
+        // Keep the document open on the server for a while, in case the file is opened again.
+        if let Some(grace_period) = self.get_config(|c| c.close_grace_period)? {
+            let deleted_at = Instant::now();
+            self.update_state(|state| {
+                state.pending_closes.insert(filename.clone(), deleted_at);
+                Ok(())
+            })?;
+            let language_client = self.clone();
+            thread::spawn(move || {
+                thread::sleep(grace_period);
+                if let Err(err) =
+                    language_client.close_pending_document(&filename, &language_id, deleted_at)
+                {
+                    error!("Error closing document {}: {:?}", filename, err);
+                }
+            });
+            return Ok(());
+        }
+
         self.update_state(|state| {
             state.text_documents.retain(|f, _| f != &filename);
             state.diagnostics.retain(|f, _| f != &filename);
@@ -2844,10 +5286,53 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Closes a document whose buffer was deleted at `deleted_at`, unless it was opened again since.
+    fn close_pending_document(
+        &self,
+        filename: &str,
+        language_id: &str,
+        deleted_at: Instant,
+    ) -> Result<()> {
+        let close = self.update_state(|state| {
+            if state.pending_closes.get(filename) != Some(&deleted_at) {
+                return Ok(false);
+            }
+
+            state.pending_closes.remove(filename);
+            let open = state.text_documents.remove(filename).is_some();
+            state.diagnostics.retain(|f, _| f != filename);
+            state.line_diagnostics.retain(|fl, _| fl.0 != filename);
+            Ok(open)
+        })?;
+        if !close {
+            return Ok(());
+        }
+
+        self.get_client(&Some(language_id.to_owned()))?.notify(
+            lsp_types::notification::DidCloseTextDocument::METHOD,
+            DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier {
+                    uri: filename.to_url()?,
+                },
+            },
+        )?;
+        Ok(())
+    }
+
     #[tracing::instrument(level = "info", skip(self))]
     fn get_signs_to_display(&self, filename: &str, viewport: &Viewport) -> Result<Vec<Sign>> {
         let max_signs = self.get_config(|c| c.diagnostics_signs_max.unwrap_or(std::usize::MAX))?;
+        let (priority, by_severity) = self.get_config(|c| {
+            (
+                c.diagnostics_signs_priority,
+                c.diagnostics_signs_priority_by_severity,
+            )
+        })?;
         let signs: Vec<_> = self.get_state(|state| {
+            if state.diagnostics_hidden.contains(filename) {
+                return vec![];
+            }
+
             let diagnostics = state.diagnostics.get(filename).cloned().unwrap_or_default();
             let mut diagnostics = diagnostics
                 .iter()
@@ -2863,7 +5348,7 @@ impl LanguageClient {
             diagnostics
                 .into_iter()
                 .take(max_signs)
-                .map(Into::into)
+                .map(|diag| Sign::new(diag, priority, by_severity))
                 .collect()
         })?;
 
@@ -2884,7 +5369,8 @@ impl LanguageClient {
             return Ok(());
         }
 
-        if line != self.get_state(|state| state.last_cursor_line)? {
+        let line_changed = line != self.get_state(|state| state.last_cursor_line)?;
+        if line_changed {
             let message = self.get_state(|state| {
                 state
                     .line_diagnostics
@@ -2894,7 +5380,8 @@ impl LanguageClient {
             })?;
 
             if message != self.get_state(|state| state.last_line_diagnostic.clone())? {
-                self.vim()?.echo_ellipsis(&message)?;
+                let reserve = self.get_config(|c| c.echo_width_reserve)?;
+                self.vim()?.echo_ellipsis(&message, reserve)?;
                 self.update_state(|state| {
                     state.last_line_diagnostic = message;
                     Ok(())
@@ -2913,6 +5400,10 @@ impl LanguageClient {
             // if the viewport hasn't changed and force_redraw is not set, we can safely exit this
             // function early and save us some sign and virtual text redrawing.
             Some(pv) if pv == current_viewport && !force_redraw => {
+                // Diagnostics shown on the current line only have to follow the cursor though.
+                if line_changed && self.get_config(|c| c.virtual_text_current_line_only)? {
+                    self.draw_virtual_texts(params)?;
+                }
                 return Ok(());
             }
             _ => {}
@@ -2989,8 +5480,11 @@ impl LanguageClient {
         }
 
         // diagnostics
-        if UseVirtualText::All == use_virtual_text
-            || UseVirtualText::Diagnostics == use_virtual_text
+        let diagnostics_hidden =
+            self.get_state(|state| state.diagnostics_hidden.contains(filename))?;
+        if (UseVirtualText::All == use_virtual_text
+            || UseVirtualText::Diagnostics == use_virtual_text)
+            && !diagnostics_hidden
         {
             let vt_diagnostics = self
                 .virtual_texts_from_diagnostics(filename, &viewport)?
@@ -3017,25 +5511,45 @@ impl LanguageClient {
         let mut virtual_texts = vec![];
         let diagnostics = self.get_state(|state| state.diagnostics.clone())?;
         let diagnostics_display = self.get_config(|c| c.diagnostics_display.clone())?;
-        let diag_list = diagnostics.get(filename);
-        if let Some(diag_list) = diag_list {
-            for diag in diag_list {
-                if viewport.overlaps(diag.range) {
-                    let mut explanation = diag.message.clone();
-                    if let Some(source) = &diag.source {
-                        explanation = format!("{}: {}\n", source, explanation);
-                    }
-                    virtual_texts.push(VirtualText {
-                        line: diag.range.start.line,
-                        text: explanation.replace("\n", "  "),
-                        hl_group: diagnostics_display
-                            .get(&(diag.severity.unwrap_or(DiagnosticSeverity::Hint) as u64))
-                            .ok_or_else(|| anyhow!("Failed to get display"))?
-                            .virtual_texthl
-                            .clone(),
-                    });
-                }
+        let max_width = self.get_config(|c| c.virtual_text_max_width)?;
+        let max_diagnostics = self.get_config(|c| c.virtual_text_max_diagnostics)?;
+        let position = self.get_config(|c| c.virtual_text_position)?;
+        let spacing = self.get_config(|c| c.virtual_text_spacing)?;
+        let current_line = if self.get_config(|c| c.virtual_text_current_line_only)? {
+            Some(self.get_state(|state| state.last_cursor_line)?)
+        } else {
+            None
+        };
+
+        // All diagnostics of a line are shown in a single virtual text.
+        let mut lines: BTreeMap<u64, Vec<&Diagnostic>> = BTreeMap::new();
+        for diag in diagnostics.get(filename).into_iter().flatten() {
+            if current_line.map_or(false, |line| line != diag.range.start.line) {
+                continue;
+            }
+            if viewport.overlaps(diag.range) {
+                lines.entry(diag.range.start.line).or_default().push(diag);
+            }
+        }
+
+        for (line, mut diags) in lines {
+            diags.sort_by_key(|diag| diag.severity.unwrap_or(DiagnosticSeverity::Hint) as u8);
+            let severity = diags[0].severity.unwrap_or(DiagnosticSeverity::Hint);
+            let mut text = aggregate_line_diagnostics(&diags, max_diagnostics, max_width.is_some());
+            if let Some(max_width) = max_width {
+                text = truncate_virtual_text(&text, max_width);
             }
+            virtual_texts.push(VirtualText {
+                line,
+                text,
+                hl_group: diagnostics_display
+                    .get(&(severity as u64))
+                    .ok_or_else(|| anyhow!("Failed to get display"))?
+                    .virtual_texthl
+                    .clone(),
+                position,
+                spacing,
+            });
         }
 
         Ok(virtual_texts)
@@ -3066,6 +5580,8 @@ impl LanguageClient {
                 line: hint.range.end.line,
                 text: hint.label,
                 hl_group: hl_group.clone(),
+                position: VirtualTextPosition::Eol,
+                spacing: 0,
             })
             .collect();
         Ok(virtual_texts)
@@ -3104,6 +5620,8 @@ impl LanguageClient {
                         line,
                         text,
                         hl_group: hl_group.clone(),
+                        position: VirtualTextPosition::Eol,
+                        spacing: 0,
                     }),
                 }
             }
@@ -3113,6 +5631,7 @@ impl LanguageClient {
     }
 
     pub fn handle_complete_done(&self, params: &Value) -> Result<()> {
+        self.clear_resolved_completion_items()?;
         let filename = self.vim()?.get_filename(params)?;
         let position = self.vim()?.get_position(params)?;
         let completed_item: VimCompleteItem = try_get("completed_item", params)?
@@ -3128,13 +5647,30 @@ impl LanguageClient {
             _ => return Ok(()),
         };
 
+        let encoding = self.position_encoding(&filename)?;
+        let snippet_engine = self.get_config(|c| c.snippet_engine)?;
+        let is_snippet = lspitem.insert_text_format == Some(InsertTextFormat::Snippet);
+        // Snippet body to hand over to the snippet engine once the completed word is removed.
+        let mut snippet = None;
+
         let mut edits = vec![];
         if self.get_config(|c| c.completion_prefer_text_edit)? {
-            if let Some(CompletionTextEdit::InsertAndReplace(_)) = lspitem.text_edit {
-                error!("insert and replace is not supported");
-            }
+            let edit_range = match self.get_config(|c| c.completion_edit_range)? {
+                CompletionEditRange::Auto => {
+                    let line = self.get_raw_line(&filename, position.line)?;
+                    if has_trailing_identifier(&line, position.character) {
+                        CompletionEditRange::Replace
+                    } else {
+                        CompletionEditRange::Insert
+                    }
+                }
+                edit_range => edit_range,
+            };
+            let edit = lspitem
+                .text_edit
+                .map(|text_edit| completion_text_edit(text_edit, edit_range));
 
-            if let Some(CompletionTextEdit::Edit(edit)) = lspitem.text_edit {
+            if let Some((edit, trailing)) = edit {
                 // The text edit should be at the completion point, and deleting the partial text
                 // that the user had typed when the language server provided the completion.
                 //
@@ -3142,16 +5678,64 @@ impl LanguageClient {
                 // already inserted.
                 //
                 // Check that we're not doing anything stupid before going ahead with this.
+                //
+                // The cursor position and the length of the completed word are in bytes, so the
+                // range is compared in bytes and converted back using the current line, which the
+                // edits are applied to.
                 let mut edit = edit;
-                edit.range.end.character =
-                    edit.range.start.character + completed_item.word.len() as u64;
-                if edit.range.end != position || edit.range.start.line != edit.range.end.line {
+                let line = self.get_raw_line(&filename, position.line)?;
+                let start = character_to_byte(&line, edit.range.start.character, encoding);
+                let end = start + completed_item.word.len() as u64;
+                if Position::new(edit.range.start.line, end) != position
+                    || edit.range.start.line != edit.range.end.line
+                {
                     return Ok(());
                 }
+                edit.range.end.character = byte_to_character(&line, end, encoding) + trailing;
+                if is_snippet {
+                    if snippet_engine.is_some() {
+                        snippet = Some(std::mem::take(&mut edit.new_text));
+                    } else {
+                        edit.new_text = strip_snippet_placeholders(&edit.new_text);
+                    }
+                }
                 edits.push(edit);
             }
         }
 
+        // Snippet items without a text edit were inserted verbatim by vim, so the snippet syntax
+        // still has to be replaced by either the expanded snippet or its plain text.
+        if is_snippet && edits.is_empty() {
+            let word = &completed_item.word;
+            let line = self.get_raw_line(&filename, position.line)?;
+            let start = position.character.saturating_sub(word.len() as u64);
+            let range = Range::new(
+                Position::new(position.line, byte_to_character(&line, start, encoding)),
+                Position::new(
+                    position.line,
+                    byte_to_character(&line, position.character, encoding),
+                ),
+            );
+            if snippet_engine.is_some() {
+                snippet = Some(lspitem.insert_text.clone().unwrap_or_else(|| word.clone()));
+                edits.push(TextEdit::new(range, String::new()));
+            } else {
+                let text = strip_snippet_placeholders(word);
+                if &text != word {
+                    edits.push(TextEdit::new(range, text));
+                }
+            }
+        }
+
+        // Only the completion itself is reindented, not the additional edits.
+        if lspitem.insert_text_mode == Some(InsertTextMode::AdjustIndentation) {
+            let line = self.get_raw_line(&filename, position.line)?;
+            let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+            for edit in &mut edits {
+                edit.new_text = adjust_indentation(&edit.new_text, &indent);
+            }
+        }
+
         if self.get_config(|c| c.apply_completion_text_edits)? {
             if let Some(aedits) = lspitem.additional_text_edits {
                 edits.extend(aedits);
@@ -3164,7 +5748,15 @@ impl LanguageClient {
 
         let position = self.apply_text_edits(filename, &edits, position)?;
         self.vim()?
-            .cursor(position.line + 1, position.character + 1)
+            .cursor(position.line + 1, position.character + 1)?;
+
+        if let (Some(engine), Some(snippet)) = (snippet_engine, snippet) {
+            self.vim()?
+                .rpcclient
+                .notify("s:ExpandSnippet", json!([engine, snippet]))?;
+        }
+
+        Ok(())
     }
 
     #[tracing::instrument(level = "info", skip(self))]
@@ -3284,6 +5876,79 @@ impl LanguageClient {
         Ok(json!(semantic_scopes))
     }
 
+    /// Hides the diagnostics of the current buffer, or shows them again. The server keeps sending
+    /// diagnostics while they are hidden, so the latest ones are shown when showing them again.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn toggle_diagnostics(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?.canonicalize();
+        let hidden = self.update_state(|state| {
+            if !state.diagnostics_hidden.remove(&filename) {
+                state.diagnostics_hidden.insert(filename.clone());
+            }
+            Ok(state.diagnostics_hidden.contains(&filename))
+        })?;
+
+        let diagnostics = self.get_state(|state| {
+            state
+                .diagnostics
+                .get(&filename)
+                .cloned()
+                .unwrap_or_default()
+        })?;
+        self.update_quickfixlist()?;
+        self.process_diagnostics(&filename, &diagnostics)?;
+        self.handle_cursor_moved(params, true)?;
+
+        if hidden {
+            self.vim()?.echomsg("Diagnostics hidden")?;
+        } else {
+            self.vim()?.echomsg("Diagnostics shown")?;
+        }
+
+        Ok(json!(!hidden))
+    }
+
+    /// Hides the semantic highlights of all buffers, or shows those of the current buffer again.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn toggle_semantic_highlight(&self, params: &Value) -> Result<Value> {
+        let enabled = self.update_state(|state| {
+            state.semantic_highlight_enabled = !state.semantic_highlight_enabled;
+            Ok(state.semantic_highlight_enabled)
+        })?;
+        let ns_id = self.get_or_create_namespace(&LCNamespace::SemanticHighlight)?;
+
+        if enabled {
+            let filename = self.vim()?.get_filename(params)?;
+            let buffer = self.vim()?.get_bufnr(&filename, params)?;
+            let line_count: u64 = self
+                .vim()?
+                .eval(format!("len(getbufline({}, 1, '$'))", buffer))?;
+            let highlights = self.get_state(|state| {
+                state
+                    .semantic_highlights
+                    .get(&filename.canonicalize())
+                    .and_then(|hl_state| hl_state.highlights.clone())
+                    .unwrap_or_default()
+            })?;
+            let clears = vec![ClearNamespace {
+                line_start: 0,
+                line_end: line_count,
+            }];
+            self.vim()?.rpcclient.notify(
+                "s:ApplySemanticHighlights",
+                json!([buffer, ns_id, clears, highlights]),
+            )?;
+            self.vim()?.echomsg("Semantic highlighting enabled")?;
+        } else {
+            self.vim()?
+                .rpcclient
+                .notify("s:ClearSemanticHighlights", json!([ns_id]))?;
+            self.vim()?.echomsg("Semantic highlighting disabled")?;
+        }
+
+        Ok(json!(enabled))
+    }
+
     pub fn semantic_highlight_symbols(&self, params: &Value) -> Result<Value> {
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
@@ -3291,7 +5956,10 @@ impl LanguageClient {
         let (opt_scopes, opt_hl_state) = self.get_state(|state| {
             (
                 state.semantic_scopes.get(&language_id).cloned(),
-                state.semantic_highlights.get(&language_id).cloned(),
+                state
+                    .semantic_highlights
+                    .get(&filename.canonicalize())
+                    .cloned(),
             )
         })?;
 
@@ -3334,19 +6002,25 @@ impl LanguageClient {
             "character": character,
             "handle": false,
         }))?;
+        let label_details = completion_label_details(&result);
+        let kind_labels = self.get_config(|c| c.completion_kind_labels.clone())?;
         let result = <Option<CompletionResponse>>::deserialize(result)?;
         let result = result.unwrap_or_else(|| CompletionResponse::Array(vec![]));
         let is_incomplete = match result {
             CompletionResponse::Array(_) => false,
             CompletionResponse::List(ref list) => list.is_incomplete,
         };
-        let matches: Result<Vec<VimCompleteItem>> = match result {
+        let items = match result {
             CompletionResponse::Array(arr) => arr,
             CompletionResponse::List(list) => list.items,
-        }
-        .iter()
-        .map(|item| VimCompleteItem::from_lsp(item, None))
-        .collect();
+        };
+        let matches: Result<Vec<VimCompleteItem>> = completion_items_order(&items)
+            .into_iter()
+            .map(|idx| {
+                let details = label_details.get(idx).cloned().flatten();
+                VimCompleteItem::from_lsp(&items[idx], details.as_ref(), &kind_labels, None)
+            })
+            .collect();
         let matches = matches?;
         self.vim()?.rpcclient.notify(
             "cm#complete",
@@ -3376,53 +6050,180 @@ impl LanguageClient {
         let is_incomplete;
         let matches;
         if let Ok(ref value) = result {
+            let label_details = completion_label_details(value);
+            let kind_labels = self.get_config(|c| c.completion_kind_labels.clone())?;
             let completion = <Option<CompletionResponse>>::deserialize(value)?;
             let completion = completion.unwrap_or_else(|| CompletionResponse::Array(vec![]));
             is_incomplete = match completion {
                 CompletionResponse::List(ref list) => list.is_incomplete,
                 _ => false,
             };
-            let matches_result: Result<Vec<VimCompleteItem>> = match completion {
+            let items = match completion {
                 CompletionResponse::Array(arr) => arr,
                 CompletionResponse::List(list) => list.items,
+            };
+            let matches_result: Result<Vec<VimCompleteItem>> = completion_items_order(&items)
+                .into_iter()
+                .map(|idx| {
+                    let details = label_details.get(idx).cloned().flatten();
+                    VimCompleteItem::from_lsp(&items[idx], details.as_ref(), &kind_labels, None)
+                })
+                .collect();
+            matches = matches_result?;
+        } else {
+            is_incomplete = true;
+            matches = vec![];
+        }
+        self.vim()?.rpcclient.notify(
+            "ncm2#complete",
+            json!([orig_ctx, ctx.startccol, matches, is_incomplete]),
+        )?;
+        result
+    }
+
+    /// Returns the capabilities the servers registered dynamically, by language id, each with its
+    /// id, method and options.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn registrations(&self, _params: &Value) -> Result<Value> {
+        let registrations = self.get_state(|state| state.registrations.clone())?;
+        Ok(serde_json::to_value(registrations)?)
+    }
+
+    /// Runs a request (hover, definition or completion) at the cursor a number of times without
+    /// handling its results, and reports the distribution of the round-trip latencies.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn benchmark(&self, params: &Value) -> Result<Value> {
+        let feature: String =
+            try_get("feature", params)?.ok_or_else(|| anyhow!("feature not specified"))?;
+        let runs: u64 = try_get("runs", params)?.unwrap_or(10);
+        if runs == 0 {
+            return Err(anyhow!("runs must be greater than 0"));
+        }
+
+        self.text_document_did_change(params)?;
+        let params = params.combine(&json!({ "handle": false }));
+        let mut latencies = Vec::with_capacity(runs as usize);
+        for _ in 0..runs {
+            let start = Instant::now();
+            match feature.as_str() {
+                "hover" => self.text_document_hover(&params)?,
+                "definition" => self.text_document_definition(&params)?,
+                "completion" => self.text_document_completion(&params)?,
+                _ => return Err(anyhow!("Unsupported feature: {}", feature)),
+            };
+            latencies.push(start.elapsed());
+        }
+        latencies.sort();
+
+        let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+        let min = millis(latencies[0]);
+        let median = millis(percentile(&latencies, 50));
+        let p90 = millis(percentile(&latencies, 90));
+        let max = millis(latencies[latencies.len() - 1]);
+        self.vim()?.echomsg(format!(
+            "[LC] {}: {} runs, min {:.1}ms, median {:.1}ms, p90 {:.1}ms, max {:.1}ms",
+            feature, runs, min, median, p90, max
+        ))?;
+
+        Ok(json!({
+            "feature": feature,
+            "runs": runs,
+            "min": min,
+            "median": median,
+            "p90": p90,
+            "max": max,
+            "samples": latencies.into_iter().map(millis).collect::<Vec<_>>(),
+        }))
+    }
+
+    /// Returns the diagnostics of a file, by default the current one.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn get_diagnostics(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        // Unify name to avoid mismatch due to case insensitivity.
+        let filename = filename.canonicalize();
+
+        let diagnostics = self.get_state(|state| {
+            state
+                .diagnostics
+                .get(&filename)
+                .map(|diagnostics| {
+                    diagnostics
+                        .iter()
+                        .map(|dn| {
+                            json!({
+                                "line": dn.range.start.line,
+                                "character": dn.range.start.character,
+                                "endLine": dn.range.end.line,
+                                "endCharacter": dn.range.end.character,
+                                "severity": dn.severity,
+                                "source": dn.source,
+                                "code": dn.code,
+                                "message": dn.message,
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        })?;
+
+        Ok(Value::Array(diagnostics))
+    }
+
+    /// The diagnostic of a file at a position, if any.
+    fn diagnostic_at(&self, filename: &str, position: Position) -> Result<Option<Diagnostic>> {
+        self.get_state(|state| {
+            state.diagnostics.get(filename).and_then(|diagnostics| {
+                diagnostics
+                    .iter()
+                    .find(|dn| position >= dn.range.start && position < dn.range.end)
+                    .cloned()
+            })
+        })
+    }
+
+    /// Copies the message of the diagnostic under the cursor, with its source and code, to a
+    /// register, the clipboard unless another one is given.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn copy_diagnostic_at_point(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let position = self.get_position(&filename, params)?;
+        let register: String = try_get("register", params)?.unwrap_or_else(|| "+".to_string());
+        let diag = match self.diagnostic_at(&filename, position)? {
+            Some(diag) => diag,
+            None => {
+                self.vim()?.echowarn("No diagnostic under the cursor")?;
+                return Ok(Value::Null);
             }
-            .iter()
-            .map(|item| VimCompleteItem::from_lsp(item, None))
-            .collect();
-            matches = matches_result?;
-        } else {
-            is_incomplete = true;
-            matches = vec![];
-        }
-        self.vim()?.rpcclient.notify(
-            "ncm2#complete",
-            json!([orig_ctx, ctx.startccol, matches, is_incomplete]),
-        )?;
-        result
+        };
+
+        let message = match (&diag.source, &diag.code) {
+            (Some(source), Some(code)) => {
+                format!("{}({}): {}", source, code.to_string(), diag.message)
+            }
+            (Some(source), None) => format!("{}: {}", source, diag.message),
+            (None, Some(code)) => format!("{}: {}", code.to_string(), diag.message),
+            (None, None) => diag.message,
+        };
+        self.vim()?
+            .rpcclient
+            .notify("setreg", json!([register, message]))?;
+        Ok(Value::String(message))
     }
 
     #[tracing::instrument(level = "info", skip(self))]
     pub fn explain_error_at_point(&self, params: &Value) -> Result<Value> {
         let silent_mode: bool = try_get("silent", params)?.unwrap_or_default();
         let filename = self.vim()?.get_filename(params)?;
-        let position = self.vim()?.get_position(params)?;
-        let diag = self.get_state(|state| {
-            state
-                .diagnostics
-                .get(&filename)
-                .ok_or_else(|| anyhow!("No diagnostics found: filename: {}", filename,))?
-                .iter()
-                .find(|dn| position >= dn.range.start && position < dn.range.end)
-                .cloned()
-                .ok_or_else(|| {
-                    anyhow!(
-                        "No diagnostics found: filename: {}, line: {}, character: {}",
-                        filename,
-                        position.line,
-                        position.character
-                    )
-                })
-        })?;
+        let position = self.get_position(&filename, params)?;
+        let diag = self.diagnostic_at(&filename, position)?.ok_or_else(|| {
+            anyhow!(
+                "No diagnostics found: filename: {}, line: {}, character: {}",
+                filename,
+                position.line,
+                position.character
+            )
+        });
 
         if silent_mode && diag.is_err() {
             return Ok(Value::Null);
@@ -3586,6 +6387,8 @@ impl LanguageClient {
                 })
             })
         })??;
+        let env = command.env();
+        let pre_launch = command.pre_launch();
         let command = command.get_command();
 
         let root_path: Option<String> = try_get("rootPath", &params)?;
@@ -3602,7 +6405,8 @@ impl LanguageClient {
         };
         let message = format!("Project root: {}", root);
         if self.get_config(|c| c.echo_project_root)? {
-            self.vim()?.echomsg_ellipsis(&message)?;
+            let reserve = self.get_config(|c| c.echo_width_reserve)?;
+            self.vim()?.echomsg_ellipsis(&message, reserve)?;
         }
         info!("{}", message);
         self.update_state(|state| {
@@ -3610,27 +6414,37 @@ impl LanguageClient {
             Ok(())
         })?;
 
+        let env: HashMap<_, _> = env
+            .into_iter()
+            .map(|(key, value)| match shellexpand::full(&value) {
+                Ok(expanded) => (key, expanded.as_ref().into()),
+                Err(err) => {
+                    warn!("Error expanding ({}): {}", value, err);
+                    (key, value.clone())
+                }
+            })
+            .collect();
+
+        if let Some(pre_launch) = pre_launch {
+            let pre_launch = expand_command(pre_launch, &root, &filename, &language_id);
+            run_pre_launch(&pre_launch, &env, &root)?;
+        }
+
         let (child_id, reader, writer): (_, Box<dyn SyncRead>, Box<dyn SyncWrite>) =
             if command.get(0).map(|c| c.starts_with("tcp://")) == Some(true) {
                 let addr = command
                     .get(0)
                     .map(|s| s.replace("tcp://", ""))
                     .ok_or_else(|| anyhow!("Server command can't be empty!"))?;
-                let stream = TcpStream::connect(addr)?;
+                let stream = connect_with_retry(&addr)?;
                 let reader = Box::new(BufReader::new(stream.try_clone()?));
                 let writer = Box::new(BufWriter::new(stream));
                 (None, reader, writer)
+            } else if let Some(path) = local_socket_path(command.get(0))? {
+                let (reader, writer) = connect_local_socket(&path)?;
+                (None, reader, writer)
             } else {
-                let command: Vec<_> = command
-                    .into_iter()
-                    .map(|cmd| match shellexpand::full(&cmd) {
-                        Ok(cmd) => cmd.as_ref().into(),
-                        Err(err) => {
-                            warn!("Error expanding ({}): {}", cmd, err);
-                            cmd.clone()
-                        }
-                    })
-                    .collect();
+                let command = expand_command(command, &root, &filename, &language_id);
 
                 let stderr = match self.get_config(|c| c.server_stderr.clone())? {
                     Some(ref path) => std::fs::OpenOptions::new()
@@ -3646,6 +6460,7 @@ impl LanguageClient {
                     command.get(0).ok_or_else(|| anyhow!("Empty command!"))?,
                 )
                 .args(&command[1..])
+                .envs(&env)
                 .current_dir(&root)
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
@@ -3669,11 +6484,20 @@ impl LanguageClient {
 
         let lcn = self.clone();
         let on_server_crash = move |language_id: &LanguageId| {
-            if let Err(err) = lcn.on_server_crash(language_id) {
+            if let Err(err) = lcn.on_server_crash(language_id, child_id) {
                 error!("Restart attempt failed: {}", err);
             }
         };
 
+        let trace = if self.get_config(|c| c.trace_rpc)? {
+            let path = self
+                .get_config(|c| c.rpc_trace_file.clone())?
+                .unwrap_or_else(|| std::env::temp_dir().join("LanguageClient-rpc.log"));
+            Some(RpcTrace::open(&path)?)
+        } else {
+            None
+        };
+
         let client = RpcClient::new(
             Some(language_id.clone()),
             reader,
@@ -3681,6 +6505,19 @@ impl LanguageClient {
             child_id,
             self.get_state(|state| state.tx.clone())?,
             on_server_crash,
+            RpcClientOptions {
+                timeouts: self.get_config(|c| RequestTimeouts {
+                    default: c.wait_output_timeout,
+                    methods: c.request_timeouts.clone(),
+                })?,
+                trace,
+                method_aliases: self.get_config(|c| {
+                    c.method_aliases
+                        .get(&language_id)
+                        .cloned()
+                        .unwrap_or_default()
+                })?,
+            },
         )?;
         self.update_state(|state| {
             state
@@ -3698,7 +6535,7 @@ impl LanguageClient {
 
         let root =
             self.get_state(|state| state.roots.get(&language_id).cloned().unwrap_or_default())?;
-        match self.get_workspace_settings(&root) {
+        match self.get_workspace_settings(&language_id, &root) {
             Ok(Value::Null) => (),
             Ok(settings) => self.workspace_did_change_configuration(&json!({
                 "languageId": language_id,
@@ -3707,9 +6544,10 @@ impl LanguageClient {
             Err(err) => warn!("Failed to get workspace settings: {}", err),
         }
 
-        self.vim()?
-            .rpcclient
-            .notify("setbufvar", json!([filename, VIM_IS_SERVER_RUNNING, 1]))?;
+        self.vim()?.rpcclient.notify(
+            "setbufvar",
+            json!([bufexpr(&filename), VIM_IS_SERVER_RUNNING, 1]),
+        )?;
 
         self.vim()?
             .rpcclient
@@ -3718,11 +6556,18 @@ impl LanguageClient {
     }
 
     #[tracing::instrument(level = "info", skip(self))]
-    fn on_server_crash(&self, language_id: &LanguageId) -> Result<()> {
+    fn on_server_crash(&self, language_id: &LanguageId, process_id: Option<u32>) -> Result<()> {
         if language_id.is_none() {
             return Ok(());
         }
 
+        // The server exiting may have been replaced already, e.g. when restarting it.
+        let current_process_id =
+            self.get_state(|state| state.clients.get(language_id).map(|c| c.process_id))?;
+        if matches!(current_process_id, Some(id) if id != process_id) {
+            return Ok(());
+        }
+
         // we don't want to restart if the server was shut down by the user, so check
         // VIM_IS_SERVER_RUNNING as that should be true at this point only if the server exited
         // unexpectedly.
@@ -3738,9 +6583,10 @@ impl LanguageClient {
         self.vim()?
             .rpcclient
             .notify("s:ExecuteAutocmd", "LanguageServerCrashed")?;
-        self.vim()?
-            .rpcclient
-            .notify("setbufvar", json!([filename, VIM_IS_SERVER_RUNNING, 0]))?;
+        self.vim()?.rpcclient.notify(
+            "setbufvar",
+            json!([bufexpr(&filename), VIM_IS_SERVER_RUNNING, 0]),
+        )?;
 
         if !self.get_config(|c| c.restart_on_crash)? {
             return Ok(());
@@ -3781,6 +6627,54 @@ impl LanguageClient {
         Ok(())
     }
 
+    /// Stops the server of the current buffer and starts it again, opening the buffers of its
+    /// language on the new server.
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn restart_server(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        self.vim()?
+            .echomsg(format!("Restarting server for {}...", language_id))?;
+
+        if self.get_state(|state| state.clients.contains_key(&Some(language_id.clone())))? {
+            // The server is marked as not running first, so that it exiting isn't taken for a
+            // crash, which would start another server alongside the one started below.
+            self.vim()?.rpcclient.notify(
+                "setbufvar",
+                json!([bufexpr(&filename), VIM_IS_SERVER_RUNNING, 0]),
+            )?;
+            // The server may well be unresponsive, so errors stopping it are only logged.
+            if let Err(err) = self.shutdown(params) {
+                warn!("Failed to shut down server: {:?}", err);
+            }
+            self.exit(params)?;
+        }
+        self.update_state(|state| {
+            state.restarts.remove(&Some(language_id.clone()));
+            Ok(())
+        })?;
+
+        self.start_server(&json!({
+            "languageId": language_id,
+            "filename": filename,
+        }))?;
+
+        let filenames: Vec<String> = self.vim()?.eval(format!(
+            "map(filter(range(1, bufnr('$')), 'bufloaded(v:val) && getbufvar(v:val, \"&filetype\") ==# \"{}\"'), 'fnamemodify(bufname(v:val), \":p\")')",
+            language_id
+        ))?;
+        for filename in filenames {
+            self.text_document_did_open(&json!({
+                "languageId": language_id,
+                "filename": filename,
+            }))?;
+        }
+
+        self.vim()?
+            .echomsg(format!("Restarted server for {}", language_id))?;
+        Ok(Value::Null)
+    }
+
     pub fn handle_server_exited(&self, params: &Value) -> Result<()> {
         let filename = self.vim()?.get_filename(params)?;
         let language_id = self.vim()?.get_language_id(&filename, params)?;
@@ -3843,81 +6737,670 @@ impl LanguageClient {
             }))?;
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn workspace_did_change_watched_files(&self, params: &Value) -> Result<()> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+
+        let params = DidChangeWatchedFilesParams::deserialize(params)?;
+        self.get_client(&Some(language_id))?.notify(
+            lsp_types::notification::DidChangeWatchedFiles::METHOD,
+            params,
+        )?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn java_class_file_contents(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+
+        let content: String = self
+            .get_client(&Some(language_id))?
+            .call(REQUEST_CLASS_FILE_CONTENTS, params)?;
+
+        let lines: Vec<String> = content
+            .lines()
+            .map(std::string::ToString::to_string)
+            .collect();
+
+        let goto_cmd = self
+            .vim()?
+            .get_goto_cmd(params)?
+            .unwrap_or_else(|| "edit".to_string());
+
+        let uri: String =
+            try_get("uri", params)?.ok_or_else(|| anyhow!("uri not found in request!"))?;
+
+        self.vim()?
+            .rpcclient
+            .notify("s:Edit", json!([goto_cmd, uri]))?;
+
+        self.vim()?.setline(1, &lines)?;
+        self.vim()?
+            .command("setlocal buftype=nofile filetype=java noswapfile")?;
+
+        Ok(Value::String(content))
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    pub fn debug_info(&self, params: &Value) -> Result<Value> {
+        let filename = self.vim()?.get_filename(params)?;
+        let language_id = self.vim()?.get_language_id(&filename, params)?;
+        let server_stderr = self.get_config(|c| c.server_stderr.clone().unwrap_or_default())?;
+        let configured_trace = self.get_config(|c| c.trace)?;
+        let mut msg = String::new();
+        self.get_state(|state| {
+            msg += &format!(
+                "Project root: {}\n",
+                state.roots.get(&language_id).cloned().unwrap_or_default()
+            );
+            msg += &format!(
+                "Language server process id: {:?}\n",
+                state
+                    .clients
+                    .get(&Some(language_id.clone()))
+                    .map(|c| c.process_id)
+                    .unwrap_or_default(),
+            );
+            msg += &format!("Language server stderr: {}\n", server_stderr,);
+            msg += &format!(
+                "Language server trace: {} (configured: {})\n",
+                state
+                    .traces
+                    .get(&language_id)
+                    .map_or("not running".to_owned(), |t| trace_str(*t).to_owned()),
+                trace_str(configured_trace),
+            );
+            msg += &format!("Log level: {}\n", state.logger.level);
+            msg += &format!("Log file: {:?}\n", state.logger.path);
+        })?;
+        self.vim()?.echo(&msg)?;
+        Ok(json!(msg))
+    }
+}
+
+fn trace_str(trace: TraceOption) -> &'static str {
+    match trace {
+        TraceOption::Off => "off",
+        TraceOption::Messages => "messages",
+        TraceOption::Verbose => "verbose",
+    }
+}
+
+/// Expands environment variables and `~` in the arguments of a server (or pre launch) command,
+/// then substitutes its placeholders.
+fn expand_command(
+    command: Vec<String>,
+    root: &str,
+    filename: &str,
+    language_id: &str,
+) -> Vec<String> {
+    command
+        .into_iter()
+        .map(|cmd| match shellexpand::full(&cmd) {
+            Ok(cmd) => cmd.as_ref().into(),
+            Err(err) => {
+                warn!("Error expanding ({}): {}", cmd, err);
+                cmd.clone()
+            }
+        })
+        .map(|cmd| substitute_command_placeholders(&cmd, root, filename, language_id))
+        .collect()
+}
+
+/// Runs the pre launch command of a server to completion, failing if it doesn't succeed.
+fn run_pre_launch(command: &[String], env: &HashMap<String, String>, root: &str) -> Result<()> {
+    let program = command
+        .get(0)
+        .ok_or_else(|| anyhow!("Empty pre launch command!"))?;
+    let output = std::process::Command::new(program)
+        .args(&command[1..])
+        .envs(env)
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run pre launch command ({:?})", command))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Pre launch command ({:?}) failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the path of the unix domain socket (or named pipe on windows) of a server command of
+/// the form `unix:///path/to/socket` (or `npipe:////./pipe/name`), if it is one.
+/// Commands using the scheme of the other platform are an error, rather than a program to run.
+fn local_socket_path(command: Option<&String>) -> Result<Option<String>> {
+    let command = match command {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let (scheme, other_scheme) = if cfg!(windows) {
+        ("npipe://", "unix://")
+    } else {
+        ("unix://", "npipe://")
+    };
+    if command.starts_with(other_scheme) {
+        return Err(anyhow!(
+            "{} server commands aren't supported on this platform, use {} instead",
+            other_scheme,
+            scheme
+        ));
+    }
+    if !command.starts_with(scheme) {
+        return Ok(None);
+    }
+
+    let path = command.trim_start_matches(scheme);
+    if cfg!(windows) {
+        Ok(Some(path.replace('/', "\\")))
+    } else {
+        Ok(Some(path.to_owned()))
+    }
+}
+
+#[cfg(unix)]
+fn connect_local_socket(path: &str) -> Result<(Box<dyn SyncRead>, Box<dyn SyncWrite>)> {
+    if !Path::new(path).exists() {
+        return Err(anyhow!("Language server socket ({}) doesn't exist", path));
+    }
+
+    let stream = std::os::unix::net::UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to language server at {}", path))?;
+    let reader = Box::new(BufReader::new(stream.try_clone()?));
+    let writer = Box::new(BufWriter::new(stream));
+    Ok((reader, writer))
+}
+
+// The pipe isn't checked for existence beforehand, as that can use up the instance of the pipe
+// the server is waiting on.
+#[cfg(windows)]
+fn connect_local_socket(path: &str) -> Result<(Box<dyn SyncRead>, Box<dyn SyncWrite>)> {
+    let pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to connect to language server at {}", path))?;
+    let reader = Box::new(BufReader::new(pipe.try_clone()?));
+    let writer = Box::new(BufWriter::new(pipe));
+    Ok((reader, writer))
+}
+
+/// Joins the messages of the diagnostics of a line, most severe first, into a single text. Repeated
+/// messages are only shown once, as is the source of consecutive messages from the same source. At
+/// most `max` messages are shown, followed by the number of messages left out. With
+/// `first_line_only`, only the first line of multi-line messages is shown.
+fn aggregate_line_diagnostics(
+    diags: &[&Diagnostic],
+    max: Option<usize>,
+    first_line_only: bool,
+) -> String {
+    let diags: Vec<_> = diags
+        .iter()
+        .unique_by(|diag| (&diag.source, &diag.message))
+        .collect();
+    let shown = max.unwrap_or(diags.len()).min(diags.len());
+
+    let mut messages = vec![];
+    let mut last_source = None;
+    for diag in &diags[..shown] {
+        let message = if first_line_only {
+            diag.message.lines().next().unwrap_or_default().to_owned()
+        } else {
+            diag.message.replace("\n", "  ")
+        };
+        match &diag.source {
+            Some(source) if last_source != Some(source) => {
+                messages.push(format!("{}: {}", source, message))
+            }
+            _ => messages.push(message),
+        }
+        last_source = diag.source.as_ref();
+    }
+
+    let mut text = messages.join(" | ");
+    if diags.len() > shown {
+        text += &format!(" (+{} more)", diags.len() - shown);
+    }
+    text
+}
+
+/// Truncates a virtual text to at most `max_width` characters, ending it with an ellipsis if it
+/// was truncated.
+fn truncate_virtual_text(text: &str, max_width: usize) -> String {
+    if text.chars().count() <= max_width {
+        return text.to_owned();
+    }
+
+    let mut truncated: String = text.chars().take(max_width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Splits a range, in bytes, into a highlight per line.
+fn range_highlights(lines: &[String], range: &Range, group: &str) -> Vec<Highlight> {
+    (range.start.line..=range.end.line)
+        .filter_map(|line| {
+            let text = lines.get(line as usize)?;
+            let character_start = if line == range.start.line {
+                range.start.character
+            } else {
+                0
+            };
+            let character_end = if line == range.end.line {
+                range.end.character
+            } else {
+                text.len() as u64
+            };
+            if character_end <= character_start {
+                return None;
+            }
+
+            Some(Highlight {
+                line,
+                character_start,
+                character_end,
+                group: group.to_owned(),
+                text: text
+                    .get(character_start as usize..character_end as usize)
+                    .map(ToOwned::to_owned)
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Converts a range into the positions `matchaddpos` expects: `[line, col, length]` for partial
+/// lines and `[line]` for whole lines, all 1-based. A multi-line range covers the first line from
+/// the start character to the end of the line, the lines in between as a whole, and the last line
+/// up to the end character, which is exclusive.
+fn match_positions(range: &Range) -> Vec<Vec<u64>> {
+    let (start, end) = (range.start, range.end);
+    if start.line == end.line {
+        return vec![vec![
+            start.line + 1,
+            start.character + 1,
+            end.character.saturating_sub(start.character),
+        ]];
+    }
+
+    // Highlight to the end of the line.
+    let mut positions = vec![vec![start.line + 1, start.character + 1, 999_999]];
+    positions.extend((start.line + 1..end.line).map(|l| vec![l + 1]));
+    // A range ending at the very start of the last line doesn't cover any of it.
+    if end.character > 0 {
+        positions.push(vec![end.line + 1, 1, end.character]);
+    }
+    positions
+}
+
+/// Orders the entries of a semantic highlight map by precedence, so that the highlight group a
+/// scope maps to doesn't depend on the iteration order of the map. Longer regexes, which tend to be
+/// more specific, are tried first, and regexes of the same length are ordered alphabetically.
+fn sorted_semantic_highlight_map(map: &HashMap<String, String>) -> Vec<(&String, &String)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    entries
+}
+
+/// Sorts quickfix entries by filename, then position.
+fn sort_quickfix_entries(entries: &mut [QuickfixEntry]) {
+    entries.sort_by(|a, b| (&a.filename, a.lnum, a.col).cmp(&(&b.filename, b.lnum, b.col)));
+}
+
+/// Flattens a document symbol and its children into the buffer, qualifying the name of each
+/// symbol with the name of its parent (`parent::child`). Children of symbols filtered out by kind
+/// are still walked, so that e.g. methods are found inside of an impl block when only methods were
+/// asked for.
+fn walk_document_symbol(
+    buffer: &mut Vec<lsp_types::DocumentSymbol>,
+    parent: Option<&lsp_types::DocumentSymbol>,
+    ds: &lsp_types::DocumentSymbol,
+    kinds: Option<&[SymbolKind]>,
+) {
+    let name = if let Some(parent) = parent {
+        format!("{}::{}", parent.name, ds.name)
+    } else {
+        ds.name.clone()
+    };
+
+    if kinds.map_or(true, |kinds| kinds.contains(&ds.kind)) {
+        buffer.push(lsp_types::DocumentSymbol { name, ..ds.clone() });
+    }
+
+    if let Some(children) = &ds.children {
+        for child in children {
+            walk_document_symbol(buffer, Some(ds), child, kinds);
+        }
+    }
+}
+
+/// Connects to a TCP language server, retrying for a little while in case the server is still
+/// starting up.
+fn connect_with_retry(addr: &str) -> Result<TcpStream> {
+    const MAX_ATTEMPTS: u64 = 5;
+
+    let mut attempt = 1;
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return Ok(stream),
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                warn!(
+                    "Failed to connect to {} (attempt {}/{}): {}",
+                    addr, attempt, MAX_ATTEMPTS, err
+                );
+                thread::sleep(Duration::from_millis(200 * attempt));
+                attempt += 1;
+            }
+            Err(err) => {
+                return Err(Error::from(err)
+                    .context(format!("Failed to connect to language server at {}", addr)))
+            }
+        }
+    }
+}
+
+/// Merges the hovers of several servers into a single markdown hover, with a header per server.
+fn merge_hovers(hovers: &[(String, Hover)]) -> Hover {
+    let mut lines = vec![];
+    for (name, hover) in hovers {
+        if !lines.is_empty() {
+            lines.push("---".to_owned());
+        }
+        lines.push(format!("**{}**", name));
+        lines.push(String::new());
+        match hover.vim_filetype().as_deref() {
+            Some("markdown") | None => lines.extend(hover.to_display()),
+            Some(filetype) => {
+                let filetype = if filetype == "text" { "" } else { filetype };
+                lines.push(format!("```{}", filetype));
+                lines.extend(hover.to_display());
+                lines.push("```".to_owned());
+            }
+        }
+    }
+
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: lines.join("\n"),
+        }),
+        range: None,
     }
+}
 
-    #[tracing::instrument(level = "info", skip(self))]
-    pub fn workspace_did_change_watched_files(&self, params: &Value) -> Result<()> {
-        let filename = self.vim()?.get_filename(params)?;
-        let language_id = self.vim()?.get_language_id(&filename, params)?;
+/// The server capability enabled by the dynamic registration of a method, for the methods whose
+/// dynamic registration is supported.
+fn registered_capability(method: &str) -> Option<&'static str> {
+    let capability = match method {
+        lsp_types::request::Completion::METHOD => "completionProvider",
+        lsp_types::request::HoverRequest::METHOD => "hoverProvider",
+        lsp_types::request::SignatureHelpRequest::METHOD => "signatureHelpProvider",
+        lsp_types::request::CodeActionRequest::METHOD => "codeActionProvider",
+        lsp_types::request::CodeLensRequest::METHOD => "codeLensProvider",
+        lsp_types::request::Formatting::METHOD => "documentFormattingProvider",
+        lsp_types::request::RangeFormatting::METHOD => "documentRangeFormattingProvider",
+        lsp_types::request::OnTypeFormatting::METHOD => "documentOnTypeFormattingProvider",
+        lsp_types::request::Rename::METHOD => "renameProvider",
+        lsp_types::request::ExecuteCommand::METHOD => "executeCommandProvider",
+        _ => return None,
+    };
+    Some(capability)
+}
 
-        let params = DidChangeWatchedFilesParams::deserialize(params)?;
-        self.get_client(&Some(language_id))?.notify(
-            lsp_types::notification::DidChangeWatchedFiles::METHOD,
-            params,
-        )?;
+/// Sets a server capability from the options it was registered with, or removes it when
+/// unregistered. Capabilities that are only a boolean are set to true, whatever the options.
+fn set_capability(
+    capabilities: &ServerCapabilities,
+    capability: &str,
+    options: Option<&Value>,
+) -> Result<ServerCapabilities> {
+    let candidates = match options {
+        None => vec![Value::Null],
+        Some(Value::Null) => vec![json!({}), json!(true)],
+        Some(options) => vec![options.clone(), json!(true)],
+    };
+
+    let mut value = serde_json::to_value(capabilities)?;
+    for candidate in candidates {
+        value[capability] = candidate;
+        if let Ok(capabilities) = ServerCapabilities::deserialize(&value) {
+            return Ok(capabilities);
+        }
+    }
+    Err(anyhow!("Invalid registration options for {}", capability))
+}
 
-        Ok(())
+/// The p-th percentile of a non-empty, sorted list of durations, using the nearest-rank method.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.max(1).min(sorted.len()) - 1]
+}
+
+/// Whether an option that is either a boolean or a map from filetypes to booleans is enabled for
+/// the given filetype. Filetypes missing from the map are enabled.
+fn is_enabled_for(option: &Value, filetype: &str) -> bool {
+    let value = match option {
+        Value::Object(filetypes) => match filetypes.get(filetype) {
+            Some(value) => value,
+            None => return true,
+        },
+        value => value,
+    };
+
+    match value {
+        Value::Bool(enabled) => *enabled,
+        Value::Number(n) => n.as_i64() != Some(0),
+        _ => true,
     }
+}
 
-    #[tracing::instrument(level = "info", skip(self))]
-    pub fn java_class_file_contents(&self, params: &Value) -> Result<Value> {
-        let filename = self.vim()?.get_filename(params)?;
-        let language_id = self.vim()?.get_language_id(&filename, params)?;
+/// Whether a command may be run without asking, because it matches one of the glob patterns of the
+/// allowed commands, or because no allowed commands are set.
+fn is_command_allowed(allowed_commands: &Option<Vec<String>>, command: &str) -> bool {
+    match allowed_commands {
+        None => true,
+        Some(patterns) => patterns
+            .iter()
+            .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+            .any(|pattern| pattern.matches(command)),
+    }
+}
 
-        let content: String = self
-            .get_client(&Some(language_id))?
-            .call(REQUEST_CLASS_FILE_CONTENTS, params)?;
+/// Whether a file matches the filters of a file operation a server registered for.
+fn matches_file_operation_filters(options: &Value, path: &str) -> bool {
+    let filters = match options.get("filters").and_then(Value::as_array) {
+        Some(filters) => filters,
+        None => return false,
+    };
 
-        let lines: Vec<String> = content
-            .lines()
-            .map(std::string::ToString::to_string)
-            .collect();
+    filters.iter().any(|filter| {
+        let scheme = filter.get("scheme").and_then(Value::as_str);
+        let matches = filter.pointer("/pattern/matches").and_then(Value::as_str);
+        if scheme.map_or(false, |scheme| scheme != "file") || matches == Some("folder") {
+            return false;
+        }
 
-        let goto_cmd = self
-            .vim()?
-            .get_goto_cmd(params)?
-            .unwrap_or_else(|| "edit".to_string());
+        let glob = match filter.pointer("/pattern/glob").and_then(Value::as_str) {
+            Some(glob) => glob,
+            None => return false,
+        };
+        let ignore_case = filter
+            .pointer("/pattern/options/ignoreCase")
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        let options = glob::MatchOptions {
+            case_sensitive: !ignore_case,
+            ..glob::MatchOptions::new()
+        };
+        expand_braces(glob)
+            .iter()
+            .filter_map(|alternative| glob::Pattern::new(alternative).ok())
+            .any(|pattern| pattern.matches_with(path, options))
+    })
+}
 
-        let uri: String =
-            try_get("uri", params)?.ok_or_else(|| anyhow!("uri not found in request!"))?;
+/// Expands the alternatives of a glob pattern, e.g. `*.{rs,toml}`, which the glob crate doesn't
+/// support.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None => return vec![pattern.to_owned()],
+    };
+    let close = match pattern[open..].find('}') {
+        Some(close) => open + close,
+        None => return vec![pattern.to_owned()],
+    };
+
+    let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+    pattern[open + 1..close]
+        .split(',')
+        .flat_map(|alternative| expand_braces(&format!("{}{}{}", prefix, alternative, suffix)))
+        .collect()
+}
 
-        self.vim()?
-            .rpcclient
-            .notify("s:Edit", json!([goto_cmd, uri]))?;
+/// Renders an edit as a hunk of a unified diff, given the lines it spans.
+fn render_edit_diff(
+    old_lines: &[String],
+    edit: &TextEdit,
+    encoding: PositionEncoding,
+) -> Vec<String> {
+    let first = old_lines.first().map(String::as_str).unwrap_or_default();
+    let last = old_lines.last().map(String::as_str).unwrap_or_default();
+    let start = character_to_byte(first, edit.range.start.character, encoding) as usize;
+    let end = character_to_byte(last, edit.range.end.character, encoding) as usize;
+    let prefix = first.get(..start).unwrap_or(first);
+    let suffix = last.get(end..).unwrap_or_default();
+    let new_text = format!("{}{}{}", prefix, edit.new_text, suffix);
+    let new_lines: Vec<_> = new_text.split('\n').collect();
+
+    let line = edit.range.start.line + 1;
+    let mut hunk = vec![format!(
+        "@@ -{},{} +{},{} @@",
+        line,
+        old_lines.len(),
+        line,
+        new_lines.len()
+    )];
+    hunk.extend(old_lines.iter().map(|l| format!("-{}", l)));
+    hunk.extend(new_lines.iter().map(|l| format!("+{}", l)));
+    hunk
+}
 
-        self.vim()?.setline(1, &lines)?;
-        self.vim()?
-            .command("setlocal buftype=nofile filetype=java noswapfile")?;
+/// Returns the name of the provider in the server capabilities of the feature of a request.
+fn capability_provider(method: &str) -> Option<&'static str> {
+    use lsp_types::request::*;
+
+    let provider = match method {
+        GotoDefinition::METHOD => "definitionProvider",
+        GotoDeclaration::METHOD => "declarationProvider",
+        GotoImplementation::METHOD => "implementationProvider",
+        GotoTypeDefinition::METHOD => "typeDefinitionProvider",
+        References::METHOD => "referencesProvider",
+        Rename::METHOD => "renameProvider",
+        CodeActionRequest::METHOD => "codeActionProvider",
+        DocumentSymbolRequest::METHOD => "documentSymbolProvider",
+        SignatureHelpRequest::METHOD => "signatureHelpProvider",
+        DocumentHighlightRequest::METHOD => "documentHighlightProvider",
+        Formatting::METHOD => "documentFormattingProvider",
+        RangeFormatting::METHOD => "documentRangeFormattingProvider",
+        LinkedEditingRangeRequest::METHOD => "linkedEditingRangeProvider",
+        _ => return None,
+    };
+    Some(provider)
+}
 
-        Ok(Value::String(content))
+/// Returns the settings in a dotted section, like `rust-analyzer.cargo`, or all settings if no
+/// section is given.
+fn configuration_section(settings: &Value, section: Option<&str>) -> Value {
+    match section {
+        None | Some("") => settings.clone(),
+        Some(section) => settings
+            .pointer(&format!("/{}", section.replace('.', "/")))
+            .cloned()
+            .unwrap_or_default(),
     }
+}
 
-    #[tracing::instrument(level = "info", skip(self))]
-    pub fn debug_info(&self, params: &Value) -> Result<Value> {
-        let filename = self.vim()?.get_filename(params)?;
-        let language_id = self.vim()?.get_language_id(&filename, params)?;
-        let server_stderr = self.get_config(|c| c.server_stderr.clone().unwrap_or_default())?;
-        let mut msg = String::new();
-        self.get_state(|state| {
-            msg += &format!(
-                "Project root: {}\n",
-                state.roots.get(&language_id).cloned().unwrap_or_default()
-            );
-            msg += &format!(
-                "Language server process id: {:?}\n",
-                state
-                    .clients
-                    .get(&Some(language_id.clone()))
-                    .map(|c| c.process_id)
-                    .unwrap_or_default(),
-            );
-            msg += &format!("Language server stderr: {}\n", server_stderr,);
-            msg += &format!("Log level: {}\n", state.logger.level);
-            msg += &format!("Log file: {:?}\n", state.logger.path);
-        })?;
-        self.vim()?.echo(&msg)?;
-        Ok(json!(msg))
+/// Returns the type in a hover, that is the last code block before the documentation, which
+/// servers like rust-analyzer separate from the code blocks with a horizontal rule.
+fn hover_type(hover: &Hover) -> Option<String> {
+    let mut blocks = vec![];
+    match &hover.contents {
+        HoverContents::Scalar(MarkedString::LanguageString(ls)) => blocks.push(ls.value.clone()),
+        HoverContents::Array(arr) => {
+            for ms in arr {
+                if let MarkedString::LanguageString(ls) = ms {
+                    blocks.push(ls.value.clone());
+                }
+            }
+        }
+        HoverContents::Scalar(MarkedString::String(s)) => blocks = markdown_code_blocks(s),
+        HoverContents::Markup(mc) if mc.kind == MarkupKind::Markdown => {
+            blocks = markdown_code_blocks(&mc.value)
+        }
+        HoverContents::Markup(_) => {}
+    }
+
+    blocks
+        .into_iter()
+        .rev()
+        .map(|block| block.split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|block| !block.is_empty())
+}
+
+/// Returns the contents of the fenced code blocks of markdown, up to the first horizontal rule.
+fn markdown_code_blocks(markdown: &str) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut block: Option<Vec<&str>> = None;
+    for line in markdown.lines() {
+        let fence = line.trim_start().starts_with("```");
+        match block.as_mut() {
+            Some(lines) if fence => {
+                blocks.push(lines.join("\n"));
+                block = None;
+            }
+            Some(lines) => lines.push(line),
+            None if fence => block = Some(vec![]),
+            None if line.trim() == "---" => break,
+            None => {}
+        }
+    }
+    blocks
+}
+
+/// Sorts edits in the order they must be applied in.
+///
+/// Edits must be applied from bottom to top, so that earlier edits will not interfere with the
+/// positioning of later edits. Edits that start with the same position must be applied in reverse
+/// order, so that multiple inserts will have their text appear in the same order the server sent
+/// it, and so that a delete/replace (according to the LSP spec, there can only be one per start
+/// position and it must be after the inserts) will work on the original document, not on the
+/// just-inserted text.
+fn sort_text_edits(edits: &mut [TextEdit]) {
+    edits.sort_by_key(|edit| (edit.range.start.line, edit.range.start.character));
+    edits.reverse();
+}
+
+/// Adds a batch of edits for the given file, keeping the batches of each file together.
+fn add_file_edits<'a>(
+    file_edits: &mut Vec<(PathBuf, Vec<&'a [TextEdit]>)>,
+    path: PathBuf,
+    edits: &'a [TextEdit],
+) {
+    match file_edits.iter_mut().find(|(p, _)| *p == path) {
+        Some((_, batches)) => batches.push(edits),
+        None => file_edits.push((path, vec![edits])),
     }
 }
 
@@ -3942,11 +7425,292 @@ fn merged_initialization_options(
     }
 }
 
+/// Whether the character at the given byte column of a line, i.e. right after the cursor, is part
+/// of an identifier.
+fn has_trailing_identifier(line: &str, character: u64) -> bool {
+    line.get(character as usize..)
+        .and_then(|s| s.chars().next())
+        .map_or(false, |c| c.is_alphanumeric() || c == '_')
+}
+
+/// Returns the edit to apply for a completion item, along with the number of characters after the
+/// cursor that it should also replace. The latter is only non-zero when using the replace range of
+/// an insert-replace edit.
+fn completion_text_edit(
+    text_edit: CompletionTextEdit,
+    edit_range: CompletionEditRange,
+) -> (TextEdit, u64) {
+    match text_edit {
+        CompletionTextEdit::Edit(edit) => (edit, 0),
+        CompletionTextEdit::InsertAndReplace(edit) => match edit_range {
+            CompletionEditRange::Insert | CompletionEditRange::Auto => {
+                (TextEdit::new(edit.insert, edit.new_text), 0)
+            }
+            CompletionEditRange::Replace => {
+                let trailing = edit
+                    .replace
+                    .end
+                    .character
+                    .saturating_sub(edit.insert.end.character);
+                (TextEdit::new(edit.replace, edit.new_text), trailing)
+            }
+        },
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::config::{ServerCommand, ServerDetails};
 
+    #[test]
+    fn test_merge_hovers() {
+        let hovers = vec![
+            (
+                "rust-analyzer".to_owned(),
+                Hover {
+                    contents: HoverContents::Scalar(MarkedString::LanguageString(LanguageString {
+                        language: "rust".into(),
+                        value: "fn foo()".into(),
+                    })),
+                    range: None,
+                },
+            ),
+            (
+                "other".to_owned(),
+                Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: "Some *docs*".into(),
+                    }),
+                    range: None,
+                },
+            ),
+        ];
+
+        let merged = merge_hovers(&hovers);
+        assert_eq!(
+            merged.to_display(),
+            vec![
+                "**rust-analyzer**",
+                "",
+                "```rust",
+                "fn foo()",
+                "```",
+                "---",
+                "**other**",
+                "",
+                "Some *docs*",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_line_diagnostics() {
+        let diag = |severity, source: &str, message: &str| Diagnostic {
+            severity: Some(severity),
+            source: Some(source.into()),
+            message: message.into(),
+            ..Diagnostic::default()
+        };
+        let error = diag(
+            DiagnosticSeverity::Error,
+            "rustc",
+            "mismatched types\nexpected u8",
+        );
+        let duplicate = error.clone();
+        let warning = diag(DiagnosticSeverity::Warning, "rustc", "unused variable");
+        let lint = diag(DiagnosticSeverity::Warning, "clippy", "needless borrow");
+        let diags = vec![&error, &duplicate, &warning, &lint];
+
+        assert_eq!(
+            aggregate_line_diagnostics(&diags, None, false),
+            "rustc: mismatched types  expected u8 | unused variable | clippy: needless borrow"
+        );
+        assert_eq!(
+            aggregate_line_diagnostics(&diags, Some(2), true),
+            "rustc: mismatched types | unused variable (+1 more)"
+        );
+    }
+
+    #[test]
+    fn test_truncate_virtual_text() {
+        assert_eq!(truncate_virtual_text("short", 10), "short");
+        assert_eq!(truncate_virtual_text("exactly10!", 10), "exactly10!");
+        assert_eq!(truncate_virtual_text("mismatched types", 10), "mismatche…");
+    }
+
+    #[test]
+    fn test_match_positions_single_line() {
+        let range = Range::new(Position::new(2, 4), Position::new(2, 10));
+        assert_eq!(match_positions(&range), vec![vec![3, 5, 6]]);
+    }
+
+    #[test]
+    fn test_match_positions_two_lines() {
+        let range = Range::new(Position::new(2, 4), Position::new(3, 3));
+        assert_eq!(
+            match_positions(&range),
+            vec![vec![3, 5, 999_999], vec![4, 1, 3]]
+        );
+    }
+
+    #[test]
+    fn test_match_positions_multiple_lines() {
+        let range = Range::new(Position::new(2, 4), Position::new(5, 2));
+        assert_eq!(
+            match_positions(&range),
+            vec![vec![3, 5, 999_999], vec![4], vec![5], vec![6, 1, 2]]
+        );
+    }
+
+    #[test]
+    fn test_match_positions_ending_at_line_start() {
+        let range = Range::new(Position::new(2, 4), Position::new(4, 0));
+        assert_eq!(match_positions(&range), vec![vec![3, 5, 999_999], vec![4]]);
+    }
+
+    #[test]
+    fn test_range_highlights() {
+        let lines: Vec<String> = vec!["let x = foo", "    .bar()", "    .baz();"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let highlight = |line, character_start, character_end, text: &str| Highlight {
+            line,
+            character_start,
+            character_end,
+            group: "Group".into(),
+            text: text.into(),
+        };
+
+        let range = Range::new(Position::new(0, 8), Position::new(0, 11));
+        assert_eq!(
+            range_highlights(&lines, &range, "Group"),
+            vec![highlight(0, 8, 11, "foo")]
+        );
+
+        let range = Range::new(Position::new(0, 8), Position::new(2, 10));
+        assert_eq!(
+            range_highlights(&lines, &range, "Group"),
+            vec![
+                highlight(0, 8, 11, "foo"),
+                highlight(1, 0, 10, "    .bar()"),
+                highlight(2, 0, 10, "    .baz()"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sorted_semantic_highlight_map() {
+        let map = hashmap! {
+            "^entity.name".to_owned() => "Identifier".to_owned(),
+            "^entity.name.function".to_owned() => "Function".to_owned(),
+            "^entity.name.type.".to_owned() => "Type".to_owned(),
+            "^meta".to_owned() => "Normal".to_owned(),
+        };
+
+        let keys: Vec<_> = sorted_semantic_highlight_map(&map)
+            .into_iter()
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "^entity.name.function",
+                "^entity.name.type.",
+                "^entity.name",
+                "^meta"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_quickfix_entries() {
+        let entry = |filename: &str, lnum: u64, col: u64| QuickfixEntry {
+            filename: filename.into(),
+            lnum,
+            col: Some(col),
+            nr: None,
+            text: None,
+            typ: None,
+            valid: None,
+        };
+        let mut entries = vec![
+            entry("b.rs", 1, 1),
+            entry("a.rs", 2, 1),
+            entry("a.rs", 1, 5),
+            entry("a.rs", 1, 2),
+        ];
+
+        sort_quickfix_entries(&mut entries);
+
+        let positions: Vec<_> = entries
+            .iter()
+            .map(|e| (e.filename.as_str(), e.lnum, e.col))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                ("a.rs", 1, Some(2)),
+                ("a.rs", 1, Some(5)),
+                ("a.rs", 2, Some(1)),
+                ("b.rs", 1, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_has_trailing_identifier() {
+        assert!(has_trailing_identifier("foo.barbaz()", 7));
+        assert!(!has_trailing_identifier("foo.bar()", 7));
+        assert!(!has_trailing_identifier("foo.bar", 7));
+        assert!(!has_trailing_identifier("foo.bar baz", 7));
+        assert!(has_trailing_identifier("é.barbaz()", 8));
+        assert!(!has_trailing_identifier("é.bar()", 6));
+    }
+
+    #[test]
+    fn test_completion_text_edit() {
+        let edit = TextEdit::new(
+            Range::new(Position::new(0, 4), Position::new(0, 6)),
+            "foo".into(),
+        );
+        assert_eq!(
+            completion_text_edit(
+                CompletionTextEdit::Edit(edit.clone()),
+                CompletionEditRange::Replace
+            ),
+            (edit, 0)
+        );
+
+        let insert_replace = CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+            new_text: "foobar".into(),
+            insert: Range::new(Position::new(0, 4), Position::new(0, 6)),
+            replace: Range::new(Position::new(0, 4), Position::new(0, 9)),
+        });
+        assert_eq!(
+            completion_text_edit(insert_replace.clone(), CompletionEditRange::Insert),
+            (
+                TextEdit::new(
+                    Range::new(Position::new(0, 4), Position::new(0, 6)),
+                    "foobar".into()
+                ),
+                0
+            )
+        );
+        assert_eq!(
+            completion_text_edit(insert_replace, CompletionEditRange::Replace),
+            (
+                TextEdit::new(
+                    Range::new(Position::new(0, 4), Position::new(0, 9)),
+                    "foobar".into()
+                ),
+                3
+            )
+        );
+    }
+
     #[test]
     fn test_expands_initialization_options() {
         let settings = json!({
@@ -3965,6 +7729,8 @@ mod test {
             initialization_options: Some(json!({
                 "inlayHints.enable": true,
             })),
+            env: HashMap::new(),
+            pre_launch: None,
         });
 
         let options = merged_initialization_options(&command, &settings)
@@ -3997,6 +7763,8 @@ mod test {
             name: "gopls".into(),
             command: vec!["gopls".into()],
             initialization_options: None,
+            env: HashMap::new(),
+            pre_launch: None,
         });
 
         let options = merged_initialization_options(&command, &settings)
@@ -4023,6 +7791,8 @@ mod test {
             initialization_options: Some(json!({
                 "usePlaceholders": true,
             })),
+            env: HashMap::new(),
+            pre_launch: None,
         });
 
         let options = merged_initialization_options(&command, &settings)
@@ -4056,4 +7826,171 @@ mod test {
             options.unwrap()
         );
     }
+
+    #[test]
+    fn test_set_capability() {
+        let capabilities = ServerCapabilities::default();
+        let options = json!({ "documentSelector": [{ "language": "rust" }] });
+
+        let capabilities =
+            set_capability(&capabilities, "documentFormattingProvider", Some(&options)).unwrap();
+        let capabilities = set_capability(
+            &capabilities,
+            "completionProvider",
+            Some(&json!({ "triggerCharacters": ["."] })),
+        )
+        .unwrap();
+        let value = serde_json::to_value(&capabilities).unwrap();
+        assert_ne!(value["documentFormattingProvider"], Value::Null);
+        assert_eq!(
+            value.pointer("/completionProvider/triggerCharacters"),
+            Some(&json!(["."]))
+        );
+
+        let capabilities =
+            set_capability(&capabilities, "documentFormattingProvider", None).unwrap();
+        let value = serde_json::to_value(&capabilities).unwrap();
+        assert_eq!(value["documentFormattingProvider"], Value::Null);
+        assert_ne!(value["completionProvider"], Value::Null);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<_> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 0), Duration::from_millis(1));
+        assert_eq!(percentile(&latencies, 50), Duration::from_millis(5));
+        assert_eq!(percentile(&latencies, 90), Duration::from_millis(9));
+        assert_eq!(percentile(&latencies, 100), Duration::from_millis(10));
+        assert_eq!(
+            percentile(&[Duration::from_millis(3)], 90),
+            Duration::from_millis(3)
+        );
+    }
+
+    #[test]
+    fn test_is_enabled_for() {
+        assert!(is_enabled_for(&json!(true), "rust"));
+        assert!(!is_enabled_for(&json!(false), "rust"));
+        assert!(!is_enabled_for(&json!(0), "rust"));
+
+        let option = json!({ "rust": 1, "markdown": false });
+        assert!(is_enabled_for(&option, "rust"));
+        assert!(!is_enabled_for(&option, "markdown"));
+        assert!(is_enabled_for(&option, "go"));
+    }
+
+    #[test]
+    fn test_is_command_allowed() {
+        let allowed_commands = Some(vec![
+            "rust-analyzer.*".to_string(),
+            "java.apply.workspaceEdit".to_string(),
+        ]);
+
+        assert!(is_command_allowed(&None, "rm"));
+        assert!(is_command_allowed(
+            &allowed_commands,
+            "rust-analyzer.runSingle"
+        ));
+        assert!(is_command_allowed(
+            &allowed_commands,
+            "java.apply.workspaceEdit"
+        ));
+        assert!(!is_command_allowed(&allowed_commands, "java.apply"));
+        assert!(!is_command_allowed(
+            &Some(vec![]),
+            "rust-analyzer.runSingle"
+        ));
+    }
+
+    #[test]
+    fn test_matches_file_operation_filters() {
+        let options = json!({
+            "filters": [
+                { "scheme": "file", "pattern": { "glob": "**/*.{rs,toml}", "matches": "file" } },
+                { "pattern": { "glob": "**/*.PY", "options": { "ignoreCase": true } } },
+                { "scheme": "untitled", "pattern": { "glob": "**/*.md" } },
+            ],
+        });
+
+        assert!(matches_file_operation_filters(&options, "/src/main.rs"));
+        assert!(matches_file_operation_filters(&options, "/Cargo.toml"));
+        assert!(matches_file_operation_filters(&options, "/src/main.py"));
+        assert!(!matches_file_operation_filters(&options, "/README.md"));
+        assert!(!matches_file_operation_filters(&options, "/src/main.c"));
+        assert!(!matches_file_operation_filters(&json!({}), "/src/main.rs"));
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(expand_braces("**/*.rs"), vec!["**/*.rs"]);
+        assert_eq!(
+            expand_braces("{src,tests}/*.{rs,toml}"),
+            vec!["src/*.rs", "src/*.toml", "tests/*.rs", "tests/*.toml"]
+        );
+    }
+
+    #[test]
+    fn test_render_edit_diff() {
+        let old_lines = vec!["let x = foo(1);".to_owned(), "bar(x);".to_owned()];
+        let edit = TextEdit::new(
+            Range::new(Position::new(4, 8), Position::new(5, 3)),
+            "baz(1);\nqux".to_owned(),
+        );
+
+        assert_eq!(
+            render_edit_diff(&old_lines, &edit, PositionEncoding::Utf16),
+            vec![
+                "@@ -5,2 +5,2 @@",
+                "-let x = foo(1);",
+                "-bar(x);",
+                "+let x = baz(1);",
+                "+qux(x);",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hover_type() {
+        let hover = |value: &str| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: value.to_owned(),
+            }),
+            range: None,
+        };
+
+        assert_eq!(
+            hover_type(&hover("```rust\nlet x: i32\n```")),
+            Some("let x: i32".to_owned())
+        );
+        assert_eq!(
+            hover_type(&hover(
+                "```rust\ncrate::foo\n```\n\n```rust\npub fn foo(\n    x: i32,\n) -> i32\n```\n---\n\n```rust\nfoo(1)\n```"
+            )),
+            Some("pub fn foo( x: i32, ) -> i32".to_owned())
+        );
+        assert_eq!(hover_type(&hover("Just some docs")), None);
+    }
+
+    #[test]
+    fn test_configuration_section() {
+        let settings = expand_json_path(json!({
+            "rust-analyzer.cargo.allFeatures": true,
+            "gopls": { "staticcheck": false },
+        }));
+
+        assert_eq!(
+            configuration_section(&settings, Some("rust-analyzer.cargo")),
+            json!({ "allFeatures": true })
+        );
+        assert_eq!(
+            configuration_section(&settings, Some("gopls.staticcheck")),
+            json!(false)
+        );
+        assert_eq!(
+            configuration_section(&settings, Some("gopls.missing")),
+            Value::Null
+        );
+        assert_eq!(configuration_section(&settings, None), settings);
+    }
 }
@@ -16,7 +16,7 @@ mod watcher;
 use anyhow::Result;
 use language_client::LanguageClient;
 use logger::Logger;
-use rpcclient::RpcClient;
+use rpcclient::{RpcClient, RpcClientOptions};
 use std::{
     io::{BufReader, BufWriter},
     sync::Arc,
@@ -41,6 +41,7 @@ fn main() -> Result<()> {
         None,
         tx.clone(),
         |_: &LanguageId| {},
+        RpcClientOptions::default(),
     )?);
 
     let state = State::new(tx, rpcclient, logger);
@@ -1,20 +1,33 @@
 use crate::types::{Call, Id, LSError, LanguageId, RawMessage, ToInt, ToParams, ToRpcError};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
 use log::*;
 use regex::Regex;
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 use std::io::Write;
 use std::str::FromStr;
 use std::{
     collections::HashMap,
+    fs::{File, OpenOptions},
     io::BufRead,
+    path::Path,
     sync::atomic::{AtomicU64, Ordering},
+    sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
 
 const CONTENT_MODIFIED_ERROR_CODE: i64 = -32801;
+const REQUEST_CANCELLED_ERROR_CODE: i64 = -32800;
+/// Payloads longer than this are cut in the RPC trace.
+const MAX_TRACED_PAYLOAD_LEN: usize = 10_000;
+/// Requests that can take long to answer, such as initialize on slow-starting servers, wait at
+/// least this long unless given a timeout of their own.
+const LONG_RUNNING_TIMEOUTS: &[(&str, Duration)] = &[
+    ("initialize", Duration::from_secs(60)),
+    ("workspace/diagnostic", Duration::from_secs(600)),
+];
 
 lazy_static! {
     // this regex is used to remove some additional fields that we get from some servers, namely:
@@ -23,6 +36,130 @@ lazy_static! {
         Regex::new(r#",\s?"(?:meta|requestMethod)":(?:"\w+(/\w+)?"|\{\})"#).unwrap();
 }
 
+/// How long to wait for the response to a request, per method.
+#[derive(Debug, Clone)]
+pub struct RequestTimeouts {
+    pub default: Duration,
+    pub methods: HashMap<String, Duration>,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self {
+            default: Duration::from_secs(60),
+            methods: HashMap::new(),
+        }
+    }
+}
+
+impl RequestTimeouts {
+    pub fn get(&self, method: &str) -> Duration {
+        if let Some(timeout) = self.methods.get(method) {
+            return *timeout;
+        }
+
+        LONG_RUNNING_TIMEOUTS
+            .iter()
+            .find(|(m, _)| *m == method)
+            .map_or(self.default, |(_, timeout)| self.default.max(*timeout))
+    }
+}
+
+/// Settings of a client that depend on the server it talks to.
+#[derive(Default)]
+pub struct RpcClientOptions {
+    pub timeouts: RequestTimeouts,
+    pub trace: Option<RpcTrace>,
+    /// Methods the server implements under another name, by the name they have in the protocol.
+    pub method_aliases: HashMap<String, String>,
+}
+
+/// Log of the messages exchanged with a server, written when `LanguageClient_traceRpc` is set.
+#[derive(Clone)]
+pub struct RpcTrace {
+    file: Arc<Mutex<File>>,
+}
+
+impl RpcTrace {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open file ({})", path.display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    fn log(&self, direction: &str, language_id: &LanguageId, message: &str) {
+        let entry = trace_entry(direction, language_id, message);
+        let result = match self.file.lock() {
+            Ok(mut file) => file.write_all(entry.as_bytes()),
+            Err(err) => {
+                warn!("Failed to lock RPC trace file: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = result {
+            warn!("Failed to write RPC trace: {}", err);
+        }
+    }
+}
+
+/// Formats a message for the RPC trace: its method, or `response` for responses, its id, and its
+/// pretty-printed params or result.
+fn trace_entry(direction: &str, language_id: &LanguageId, message: &str) -> String {
+    let value: Value = match serde_json::from_str(message) {
+        Ok(value) => value,
+        Err(_) => {
+            return format!(
+                "{} {:?} invalid message\n{}\n\n",
+                direction,
+                language_id,
+                cap_payload(message.to_owned())
+            )
+        }
+    };
+
+    let kind = value
+        .get("method")
+        .and_then(Value::as_str)
+        .unwrap_or("response");
+    let id = value
+        .get("id")
+        .map_or_else(String::new, |id| format!(" #{}", id));
+    let payload = ["params", "result", "error"]
+        .iter()
+        .find_map(|key| value.get(*key))
+        .unwrap_or(&Value::Null);
+    let payload = serde_json::to_string_pretty(payload).unwrap_or_default();
+    format!(
+        "{} {:?} {}{}\n{}\n\n",
+        direction,
+        language_id,
+        kind,
+        id,
+        cap_payload(payload)
+    )
+}
+
+fn cap_payload(payload: String) -> String {
+    if payload.len() <= MAX_TRACED_PAYLOAD_LEN {
+        return payload;
+    }
+
+    let mut end = MAX_TRACED_PAYLOAD_LEN;
+    while !payload.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!(
+        "{}\n... ({} more bytes)",
+        &payload[..end],
+        payload.len() - end
+    )
+}
+
 #[derive(Serialize)]
 pub struct RpcClient {
     language_id: LanguageId,
@@ -32,6 +169,11 @@ pub struct RpcClient {
     writer_tx: Sender<RawMessage>,
     #[serde(skip_serializing)]
     reader_tx: Sender<(Id, Sender<jsonrpc_core::Output>)>,
+    #[serde(skip_serializing)]
+    timeouts: RequestTimeouts,
+    // standard method => method the server implements it as.
+    #[serde(skip_serializing)]
+    method_aliases: HashMap<String, String>,
     pub process_id: Option<u32>,
 }
 
@@ -44,20 +186,27 @@ impl RpcClient {
         process_id: Option<u32>,
         sink: Sender<Call>,
         on_crash: impl Fn(&LanguageId) + Clone + Send + 'static,
+        options: RpcClientOptions,
     ) -> Result<Self> {
+        let RpcClientOptions {
+            timeouts,
+            trace,
+            method_aliases,
+        } = options;
         let (reader_tx, reader_rx): (Sender<(Id, Sender<jsonrpc_core::Output>)>, _) = unbounded();
 
         let language_id_clone = language_id.clone();
         let reader_thread_name = format!("reader-{:?}", language_id);
         let on_crash_clone = on_crash.clone();
+        let trace_clone = trace.clone();
         thread::Builder::new()
             .name(reader_thread_name.clone())
             .spawn(move || {
-                if let Err(err) = loop_read(reader, reader_rx, &sink, &language_id_clone) {
+                if let Err(err) =
+                    loop_read(reader, reader_rx, &sink, &language_id_clone, &trace_clone)
+                {
                     match err.downcast_ref::<std::io::Error>() {
-                        Some(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                            on_crash_clone(&language_id_clone)
-                        }
+                        Some(err) if is_disconnected(err) => on_crash_clone(&language_id_clone),
                         _ => {}
                     }
 
@@ -71,11 +220,9 @@ impl RpcClient {
         thread::Builder::new()
             .name(writer_thread_name.clone())
             .spawn(move || {
-                if let Err(err) = loop_write(writer, &writer_rx, &language_id_clone) {
+                if let Err(err) = loop_write(writer, &writer_rx, &language_id_clone, &trace) {
                     match err.downcast_ref::<std::io::Error>() {
-                        Some(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {
-                            on_crash(&language_id_clone)
-                        }
+                        Some(err) if is_disconnected(err) => on_crash(&language_id_clone),
                         _ => {}
                     }
 
@@ -89,6 +236,8 @@ impl RpcClient {
             process_id,
             reader_tx,
             writer_tx,
+            timeouts,
+            method_aliases,
         })
     }
 
@@ -98,18 +247,34 @@ impl RpcClient {
         params: impl Serialize,
     ) -> Result<R> {
         let method = method.as_ref();
+        let params = params.to_params()?;
+        match self.call_once(method, params.clone()) {
+            // The document was modified while the server was processing the request, so it's
+            // retried once against the updated document before giving up.
+            Err(err) if is_content_modified_error(&err) => {
+                info!("Retrying {} after content modified error", method);
+                self.call_once(method, params)
+            }
+            result => result,
+        }
+    }
+
+    fn call_once<R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: jsonrpc_core::Params,
+    ) -> Result<R> {
         let id = self.id.fetch_add(1, Ordering::SeqCst);
         let msg = jsonrpc_core::MethodCall {
             jsonrpc: Some(jsonrpc_core::Version::V2),
             id: jsonrpc_core::Id::Num(id),
-            method: method.to_owned(),
-            params: params.to_params()?,
+            method: self.server_method(method).to_owned(),
+            params,
         };
         let (tx, rx) = bounded(1);
         self.reader_tx.send((id, tx))?;
         self.writer_tx.send(RawMessage::MethodCall(msg))?;
-        // TODO: duration from config.
-        match rx.recv_timeout(Duration::from_secs(60))? {
+        match rx.recv_timeout(self.timeouts.get(method))? {
             jsonrpc_core::Output::Success(ok) => Ok(serde_json::from_value(ok.result)?),
             // NOTE: Errors with code -32801 correspond to the protocol's ContentModified error,
             // which we don't want to show to the user and should ignore, as the result of the
@@ -120,7 +285,18 @@ impl RpcClient {
             {
                 Err(anyhow::Error::from(LSError::ContentModified))
             }
-            jsonrpc_core::Output::Failure(err) => Err(anyhow!("Error: {:?}", err)),
+            // Errors with code -32800 are sent by the server as the response to a request that
+            // was cancelled, which is an expected outcome rather than an actual failure.
+            jsonrpc_core::Output::Failure(err)
+                if err.error.code.code() == REQUEST_CANCELLED_ERROR_CODE =>
+            {
+                Err(anyhow::Error::from(LSError::RequestCancelled))
+            }
+            // The error of the server is kept as the source, for callers that handle some of them.
+            jsonrpc_core::Output::Failure(err) => {
+                let message = format!("Error: {:?}", err);
+                Err(anyhow::Error::new(err.error).context(message))
+            }
         }
     }
 
@@ -129,13 +305,21 @@ impl RpcClient {
 
         let msg = jsonrpc_core::Notification {
             jsonrpc: Some(jsonrpc_core::Version::V2),
-            method: method.to_owned(),
+            method: self.server_method(method).to_owned(),
             params: params.to_params()?,
         };
         self.writer_tx.send(RawMessage::Notification(msg))?;
         Ok(())
     }
 
+    /// The method the server implements a method as, for servers that haven't caught up with a
+    /// renaming of the protocol yet.
+    fn server_method<'a>(&'a self, method: &'a str) -> &'a str {
+        self.method_aliases
+            .get(method)
+            .map_or(method, String::as_str)
+    }
+
     pub fn output(&self, id: Id, result: Result<impl Serialize>) -> Result<()> {
         let output = match result {
             Ok(ok) => jsonrpc_core::Output::Success(jsonrpc_core::Success {
@@ -155,11 +339,38 @@ impl RpcClient {
     }
 }
 
+/// Whether the error means that the other end of the connection is gone, either because the
+/// server process exited or because a TCP connection was closed.
+fn is_disconnected(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::UnexpectedEof
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+    )
+}
+
+pub fn is_content_modified_error(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<LSError>() {
+        Some(err) if err == &LSError::ContentModified => true,
+        _ => false,
+    }
+}
+
+pub fn is_request_cancelled_error(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<LSError>() {
+        Some(err) if err == &LSError::RequestCancelled => true,
+        _ => false,
+    }
+}
+
 fn loop_read(
     reader: impl BufRead,
     reader_rx: Receiver<(Id, Sender<jsonrpc_core::Output>)>,
     sink: &Sender<Call>,
     language_id: &LanguageId,
+    trace: &Option<RpcTrace>,
 ) -> Result<()> {
     let mut pending_outputs = HashMap::new();
 
@@ -172,7 +383,14 @@ fn loop_read(
         let mut message = String::new();
         let mut line = String::new();
         if language_id.is_some() {
-            reader.read_line(&mut line)?;
+            // The server process exited or closed the connection.
+            if reader.read_line(&mut line)? == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Language server closed the connection",
+                )
+                .into());
+            }
             let line = line.trim();
             if line.is_empty() {
                 count_empty_lines += 1;
@@ -205,6 +423,9 @@ fn loop_read(
             continue;
         }
         debug!("<= {:?} {}", language_id, message);
+        if let Some(trace) = trace {
+            trace.log("<=", language_id, message);
+        }
         // FIXME: Remove extra `meta` property from javascript-typescript-langserver and
         // `requestMethod` sent by Sorbet.
         let s = RE_REMOVE_EXTRA_FIELDS.replace(message, "");
@@ -246,12 +467,16 @@ fn loop_write(
     writer: impl Write,
     rx: &Receiver<RawMessage>,
     language_id: &LanguageId,
+    trace: &Option<RpcTrace>,
 ) -> Result<()> {
     let mut writer = writer;
 
     for msg in rx.iter() {
         let s = serde_json::to_string(&msg)?;
         debug!("=> {:?} {}", language_id, s);
+        if let Some(trace) = trace {
+            trace.log("=>", language_id, &s);
+        }
         if language_id.is_none() {
             // Use different convention for two reasons,
             // 1. If using '\r\ncontent', nvim will receive output as `\r` + `content`, while vim
@@ -268,8 +493,35 @@ fn loop_write(
 
 #[cfg(test)]
 mod test {
-    use super::RE_REMOVE_EXTRA_FIELDS;
+    use super::{
+        cap_payload, trace_entry, RequestTimeouts, MAX_TRACED_PAYLOAD_LEN, RE_REMOVE_EXTRA_FIELDS,
+    };
     use crate::types::RawMessage;
+    use std::time::Duration;
+
+    #[test]
+    fn test_request_timeouts() {
+        let timeouts = RequestTimeouts {
+            default: Duration::from_secs(10),
+            methods: vec![(
+                "textDocument/completion".to_string(),
+                Duration::from_secs(1),
+            )]
+            .into_iter()
+            .collect(),
+        };
+
+        assert_eq!(timeouts.get("textDocument/hover"), Duration::from_secs(10));
+        assert_eq!(
+            timeouts.get("textDocument/completion"),
+            Duration::from_secs(1)
+        );
+        assert_eq!(timeouts.get("initialize"), Duration::from_secs(60));
+        assert_eq!(
+            timeouts.get("workspace/diagnostic"),
+            Duration::from_secs(600)
+        );
+    }
 
     #[test]
     // The library we're using for json-rpc doesn't accept extra fields in the structs used to
@@ -298,4 +550,29 @@ mod test {
         let result: Result<RawMessage, _> = serde_json::from_str(&message);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_trace_entry() {
+        let language_id = Some("rust".to_owned());
+        let message = r#"{"jsonrpc":"2.0","id":1,"method":"shutdown","params":null}"#;
+        assert_eq!(
+            trace_entry("=>", &language_id, message),
+            "=> Some(\"rust\") shutdown #1\nnull\n\n"
+        );
+
+        let message = r#"{"jsonrpc":"2.0","id":1,"result":{"a":1}}"#;
+        assert_eq!(
+            trace_entry("<=", &language_id, message),
+            "<= Some(\"rust\") response #1\n{\n  \"a\": 1\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn test_cap_payload() {
+        assert_eq!(cap_payload("short".to_owned()), "short");
+
+        let payload = "é".repeat(MAX_TRACED_PAYLOAD_LEN);
+        let capped = cap_payload(payload);
+        assert!(capped.ends_with(&format!("... ({} more bytes)", MAX_TRACED_PAYLOAD_LEN)));
+    }
 }
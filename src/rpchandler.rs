@@ -1,4 +1,5 @@
-use crate::extensions::clangd;
+use crate::extensions::{clangd, rust_analyzer};
+use crate::rpcclient::{is_content_modified_error, is_request_cancelled_error};
 use crate::{language_client::LanguageClient, language_server_protocol::Direction, types::*};
 use anyhow::{anyhow, Result};
 use log::*;
@@ -6,23 +7,22 @@ use lsp_types::notification::{self, Notification};
 use lsp_types::request::{self, Request};
 use serde_json::Value;
 
-fn is_content_modified_error(err: &anyhow::Error) -> bool {
-    match err.downcast_ref::<LSError>() {
-        Some(err) if err == &LSError::ContentModified => true,
-        _ => false,
-    }
-}
-
 impl LanguageClient {
     pub fn handle_call(&self, msg: Call) -> Result<()> {
         match msg {
             Call::MethodCall(lang_id, method_call) => {
-                let result = self.handle_method_call(lang_id.as_deref(), &method_call);
-                if let Err(ref err) = result {
-                    if is_content_modified_error(err) {
-                        return Ok(());
+                let result = match self.handle_method_call(lang_id.as_deref(), &method_call) {
+                    // The result was invalidated by changes to the document, or the request was
+                    // cancelled, so it's dropped without showing an error, but the caller still
+                    // gets a response.
+                    Err(ref err)
+                        if is_content_modified_error(err) || is_request_cancelled_error(err) =>
+                    {
+                        Ok(Value::Null)
                     }
-
+                    result => result,
+                };
+                if let Err(ref err) = result {
                     if err.downcast_ref::<LCError>().is_none() {
                         error!(
                             "Error handling message: {}\n\nMessage: {}\n\nError: {:?}",
@@ -38,7 +38,7 @@ impl LanguageClient {
             Call::Notification(lang_id, notification) => {
                 let result = self.handle_notification(lang_id.as_deref(), &notification);
                 if let Err(ref err) = result {
-                    if is_content_modified_error(err) {
+                    if is_content_modified_error(err) || is_request_cancelled_error(err) {
                         return Ok(());
                     }
 
@@ -82,23 +82,36 @@ impl LanguageClient {
             request::UnregisterCapability::METHOD => {
                 self.client_unregister_capability(language_id.unwrap_or_default(), &params)
             }
+            request::WorkspaceConfiguration::METHOD => {
+                self.workspace_configuration(language_id.unwrap_or_default(), &params)
+            }
             request::HoverRequest::METHOD => self.text_document_hover(&params),
             request::Rename::METHOD => self.text_document_rename(&params),
             request::DocumentSymbolRequest::METHOD => self.text_document_document_symbol(&params),
             request::ShowMessageRequest::METHOD => self.window_show_message_request(&params),
+            ShowDocument::METHOD => self.window_show_document(&params),
             request::WorkspaceSymbol::METHOD => self.workspace_symbol(&params),
             request::CodeActionRequest::METHOD => self.text_document_code_action(&params),
             request::Completion::METHOD => self.text_document_completion(&params),
             request::SignatureHelpRequest::METHOD => self.text_document_signature_help(&params),
             request::GotoDefinition::METHOD => self.text_document_definition(&params),
             request::References::METHOD => self.text_document_references(&params),
+            DocumentDiagnosticRequest::METHOD => self.text_document_diagnostic(&params),
+            WorkspaceDiagnosticRequest::METHOD => self.workspace_diagnostic(&params),
+            LinkedEditingRangeRequest::METHOD => self.text_document_linked_editing_range(&params),
+            request::GotoDeclaration::METHOD => self.text_document_declaration(&params),
+            request::GotoImplementation::METHOD => self.text_document_implementation(&params),
+            request::GotoTypeDefinition::METHOD => self.text_document_type_definition(&params),
             request::Formatting::METHOD => self.text_document_formatting(&params),
+            request::WillSaveWaitUntil::METHOD => self.text_document_will_save_wait_until(&params),
             request::RangeFormatting::METHOD => self.text_document_range_formatting(&params),
             request::CodeLensRequest::METHOD => self.text_document_code_lens(&params),
+            request::DocumentLinkRequest::METHOD => self.text_document_document_link(&params),
             request::ResolveCompletionItem::METHOD => self.completion_item_resolve(&params),
             request::ExecuteCommand::METHOD => self.workspace_execute_command(&params),
             request::ApplyWorkspaceEdit::METHOD => self.workspace_apply_edit(&params),
             request::Shutdown::METHOD => self.shutdown(&params),
+            request::SelectionRangeRequest::METHOD => self.text_document_selection_range(&params),
             request::DocumentHighlightRequest::METHOD => {
                 self.text_document_document_highlight(&params)
             }
@@ -121,10 +134,46 @@ impl LanguageClient {
             REQUEST_SEMANTIC_SCOPES => self.semantic_scopes(&params),
             REQUEST_SHOW_SEMANTIC_HL_SYMBOLS => self.semantic_highlight_symbols(&params),
             REQUEST_EXECUTE_CODE_ACTION => self.execute_code_action(&params),
+            REQUEST_GOTO_EXPRESSION_TYPE => self.goto_expression_type(&params),
+            REQUEST_SELECTION_RANGE_EXPAND => self.select_range(&params, Direction::Next),
+            REQUEST_SELECTION_RANGE_SHRINK => self.select_range(&params, Direction::Previous),
+            REQUEST_DOCUMENT_SYMBOL_SEARCH => self.document_symbol_search(&params),
+            REQUEST_CLANGD_GOTO_DEFINITION => self.clangd_goto_definition(&params),
+            REQUEST_GET_DIAGNOSTICS => self.get_diagnostics(&params),
+            REQUEST_CODE_LENS_LIST => self.code_lens_list(&params),
+            REQUEST_INCOMING_CALLS => self.incoming_calls(&params),
+            REQUEST_SHOW_TYPE => self.show_type(&params),
+            REQUEST_REFRESH_DIAGNOSTICS => self.refresh_diagnostics(&params),
+            REQUEST_RESTART_SERVER => self.restart_server(&params),
+            REQUEST_TOGGLE_SEMANTIC_HIGHLIGHT => self.toggle_semantic_highlight(&params),
+            REQUEST_TOGGLE_DIAGNOSTICS => self.toggle_diagnostics(&params),
+            REQUEST_FOLLOW_DOCUMENT_LINK => self.follow_document_link(&params),
+            REQUEST_TYPE_HIERARCHY => self.type_hierarchy(&params),
+            REQUEST_SET_TRACE => self.set_trace(&params),
+            REQUEST_REGISTRATIONS => self.registrations(&params),
+            REQUEST_COPY_DIAGNOSTIC_AT_POINT => self.copy_diagnostic_at_point(&params),
+            REQUEST_BENCHMARK => self.benchmark(&params),
+            REQUEST_HANDLE_BUF_WRITE_PRE => self.handle_buf_write_pre(&params),
 
             clangd::request::SwitchSourceHeader::METHOD => {
                 self.text_document_switch_source_header(&params)
             }
+            rust_analyzer::request::OpenCargoToml::METHOD => {
+                self.rust_analyzer_open_cargo_toml(&params)
+            }
+            rust_analyzer::request::ParentModule::METHOD => {
+                self.rust_analyzer_parent_module(&params)
+            }
+            rust_analyzer::request::JoinLines::METHOD => self.rust_analyzer_join_lines(&params),
+            rust_analyzer::request::Ssr::METHOD => self.rust_analyzer_ssr(&params),
+            rust_analyzer::request::ReloadWorkspace::METHOD => {
+                self.rust_analyzer_reload_workspace(&params)
+            }
+            rust_analyzer::request::AnalyzerStatus::METHOD => {
+                self.rust_analyzer_analyzer_status(&params)
+            }
+            rust_analyzer::request::SyntaxTree::METHOD => self.rust_analyzer_syntax_tree(&params),
+            rust_analyzer::request::ViewHir::METHOD => self.rust_analyzer_view_hir(&params),
 
             _ => {
                 let language_id_target = if language_id.is_some() {
@@ -182,8 +231,11 @@ impl LanguageClient {
             notification::SemanticHighlighting::METHOD => {
                 self.text_document_semantic_highlight(&params)?
             }
-            notification::Progress::METHOD => self.progress(&params)?,
+            notification::Progress::METHOD => {
+                self.progress(language_id.unwrap_or_default(), &params)?
+            }
             notification::LogMessage::METHOD => self.window_log_message(&params)?,
+            LogTrace::METHOD => self.log_trace(language_id.unwrap_or_default(), &params)?,
             notification::ShowMessage::METHOD => self.window_show_message(&params)?,
             notification::Exit::METHOD => self.exit(&params)?,
             // Extensions.
@@ -197,6 +249,7 @@ impl LanguageClient {
             NOTIFICATION_HANDLE_COMPLETE_DONE => self.handle_complete_done(&params)?,
             NOTIFICATION_FZF_SINK_LOCATION => self.fzf_sink_location(&params)?,
             NOTIFICATION_FZF_SINK_COMMAND => self.fzf_sink_command(&params)?,
+            NOTIFICATION_CODE_ACTION_SINK => self.code_action_sink(&params)?,
             NOTIFICATION_CLEAR_DOCUMENT_HL => self.clear_document_highlight(&params)?,
             NOTIFICATION_LANGUAGE_STATUS => self.language_status(&params)?,
             NOTIFICATION_WINDOW_PROGRESS => self.window_progress(&params)?,
@@ -208,6 +261,8 @@ impl LanguageClient {
             NOTIFICATION_DIAGNOSTICS_PREVIOUS => {
                 self.cycle_diagnostics(&params, Direction::Previous)?
             }
+            NOTIFICATION_PIN_HOVER => self.pin_hover(&params)?,
+            NOTIFICATION_CLOSE_PINNED_HOVERS => self.close_pinned_hovers(&params)?,
 
             _ => {
                 let language_id_target = if language_id.is_some() {
@@ -1,12 +1,32 @@
 use lsp_types::{Diagnostic, DiagnosticSeverity};
 use serde::{Deserialize, Serialize};
 
+/// Priority of diagnostics signs, same as the default priority of signs in vim.
+pub const DEFAULT_SIGN_PRIORITY: u64 = 10;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Sign {
     pub id: u64,
     /// line number. 0-based.
     pub line: u64,
     pub name: String,
+    pub priority: u64,
+}
+
+impl Sign {
+    /// Creates the sign of a diagnostic with the given priority. If `by_severity` is set, the
+    /// priority is raised by the severity of the diagnostic, so that an error sign is shown over
+    /// the signs of other plugins that a hint sign would be hidden by.
+    pub fn new(diagnostic: &Diagnostic, priority: u64, by_severity: bool) -> Self {
+        let mut sign = Sign::from(diagnostic);
+        if by_severity {
+            let severity = diagnostic.severity.unwrap_or(DiagnosticSeverity::Hint);
+            sign.priority = priority + DiagnosticSeverity::Hint as u64 - severity as u64;
+        } else {
+            sign.priority = priority;
+        }
+        sign
+    }
 }
 
 impl From<&Diagnostic> for Sign {
@@ -16,6 +36,11 @@ impl From<&Diagnostic> for Sign {
         let name = format!("LanguageClient{:?}", severity);
         let id = 75_000 + line * DiagnosticSeverity::Hint as u64 + severity as u64;
 
-        Sign { id, line, name }
+        Sign {
+            id,
+            line,
+            name,
+            priority: DEFAULT_SIGN_PRIORITY,
+        }
     }
 }
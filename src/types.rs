@@ -2,7 +2,7 @@ use crate::logger::Logger;
 use crate::rpcclient::RpcClient;
 use crate::{
     language_client::LanguageClient,
-    utils::{code_action_kind_as_str, ToUrl},
+    utils::{code_action_kind_as_str, completion_item_kind_abbr, markdown_code_regions, ToUrl},
     vim::Vim,
     watcher::FSWatch,
 };
@@ -10,20 +10,22 @@ use crate::{viewport::Viewport, vim::Highlight};
 use anyhow::{anyhow, Result};
 use jsonrpc_core::Params;
 use log::*;
-use lsp_types::Range;
 use lsp_types::{
     CodeAction, CodeLens, Command, CompletionItem, CompletionTextEdit, Diagnostic,
-    DiagnosticSeverity, DocumentHighlightKind, FileChangeType, FileEvent, Hover, HoverContents,
-    InitializeResult, InsertTextFormat, Location, MarkedString, MarkupContent, MarkupKind,
-    MessageType, NumberOrString, Registration, SemanticHighlightingInformation, SymbolInformation,
-    TextDocumentItem, TextDocumentPositionParams, Url, WorkspaceEdit,
+    DiagnosticSeverity, DocumentHighlightKind, DocumentLink, FileChangeType, FileEvent, Hover,
+    HoverContents, InitializeResult, InsertTextFormat, Location, MarkedString, MarkupContent,
+    MarkupKind, MessageType, NumberOrString, Registration, SemanticHighlightingInformation,
+    SymbolInformation, TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams,
+    TextDocumentSyncKind, TraceOption, Url, WorkspaceEdit,
 };
+use lsp_types::{Position, Range};
 use maplit::hashmap;
 use pathdiff::diff_paths;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
     io::{BufRead, BufReader, BufWriter, Write},
     net::TcpStream,
     path::{Path, PathBuf},
@@ -38,6 +40,8 @@ use thiserror::Error;
 pub enum LSError {
     #[error("Content Modified")]
     ContentModified,
+    #[error("Request Cancelled")]
+    RequestCancelled,
 }
 
 #[derive(Debug, Error)]
@@ -66,6 +70,26 @@ pub const REQUEST_SEMANTIC_SCOPES: &str = "languageClient/semanticScopes";
 pub const REQUEST_SHOW_SEMANTIC_HL_SYMBOLS: &str = "languageClient/showSemanticHighlightSymbols";
 pub const REQUEST_CLASS_FILE_CONTENTS: &str = "java/classFileContents";
 pub const REQUEST_EXECUTE_CODE_ACTION: &str = "languageClient/executeCodeAction";
+pub const REQUEST_GOTO_EXPRESSION_TYPE: &str = "languageClient/gotoExpressionType";
+pub const REQUEST_SELECTION_RANGE_EXPAND: &str = "languageClient/selectionRangeExpand";
+pub const REQUEST_SELECTION_RANGE_SHRINK: &str = "languageClient/selectionRangeShrink";
+pub const REQUEST_DOCUMENT_SYMBOL_SEARCH: &str = "languageClient/documentSymbolSearch";
+pub const REQUEST_CLANGD_GOTO_DEFINITION: &str = "languageClient/clangdGotoDefinition";
+pub const REQUEST_GET_DIAGNOSTICS: &str = "languageClient/getDiagnostics";
+pub const REQUEST_CODE_LENS_LIST: &str = "languageClient/codeLensList";
+pub const REQUEST_INCOMING_CALLS: &str = "languageClient/incomingCalls";
+pub const REQUEST_SHOW_TYPE: &str = "languageClient/showType";
+pub const REQUEST_REFRESH_DIAGNOSTICS: &str = "languageClient/refreshDiagnostics";
+pub const REQUEST_RESTART_SERVER: &str = "languageClient/restartServer";
+pub const REQUEST_TOGGLE_SEMANTIC_HIGHLIGHT: &str = "languageClient/toggleSemanticHighlight";
+pub const REQUEST_TOGGLE_DIAGNOSTICS: &str = "languageClient/toggleDiagnostics";
+pub const REQUEST_FOLLOW_DOCUMENT_LINK: &str = "languageClient/followDocumentLink";
+pub const REQUEST_TYPE_HIERARCHY: &str = "languageClient/typeHierarchy";
+pub const REQUEST_SET_TRACE: &str = "languageClient/setTrace";
+pub const REQUEST_REGISTRATIONS: &str = "languageClient/registrations";
+pub const REQUEST_COPY_DIAGNOSTIC_AT_POINT: &str = "languageClient/copyDiagnosticAtPoint";
+pub const REQUEST_BENCHMARK: &str = "languageClient/benchmark";
+pub const REQUEST_HANDLE_BUF_WRITE_PRE: &str = "languageClient/handleBufWritePre";
 
 pub const NOTIFICATION_HANDLE_BUF_NEW_FILE: &str = "languageClient/handleBufNewFile";
 pub const NOTIFICATION_HANDLE_BUF_ENTER: &str = "languageClient/handleBufEnter";
@@ -77,6 +101,7 @@ pub const NOTIFICATION_HANDLE_CURSOR_MOVED: &str = "languageClient/handleCursorM
 pub const NOTIFICATION_HANDLE_COMPLETE_DONE: &str = "languageClient/handleCompleteDone";
 pub const NOTIFICATION_FZF_SINK_LOCATION: &str = "LanguageClient_FZFSinkLocation";
 pub const NOTIFICATION_FZF_SINK_COMMAND: &str = "LanguageClient_FZFSinkCommand";
+pub const NOTIFICATION_CODE_ACTION_SINK: &str = "LanguageClient_codeActionSink";
 pub const NOTIFICATION_SERVER_EXITED: &str = "$languageClient/serverExited";
 pub const NOTIFICATION_CLEAR_DOCUMENT_HL: &str = "languageClient/clearDocumentHighlight";
 pub const NOTIFICATION_RUST_BEGIN_BUILD: &str = "rustDocument/beginBuild";
@@ -86,21 +111,39 @@ pub const NOTIFICATION_WINDOW_PROGRESS: &str = "window/progress";
 pub const NOTIFICATION_LANGUAGE_STATUS: &str = "language/status";
 pub const NOTIFICATION_DIAGNOSTICS_NEXT: &str = "languageClient/diagnosticsNext";
 pub const NOTIFICATION_DIAGNOSTICS_PREVIOUS: &str = "languageClient/diagnosticsPrevious";
+pub const NOTIFICATION_PIN_HOVER: &str = "languageClient/pinHover";
+pub const NOTIFICATION_CLOSE_PINNED_HOVERS: &str = "languageClient/closePinnedHovers";
+
+/// Prefix of the names given to unnamed buffers, followed by the buffer number.
+pub const UNTITLED_PREFIX: &str = "untitled:Untitled-";
 
 pub const VIM_SERVER_STATUS: &str = "g:LanguageClient_serverStatus";
 pub const VIM_SERVER_STATUS_MESSAGE: &str = "g:LanguageClient_serverStatusMessage";
 pub const VIM_IS_SERVER_RUNNING: &str = "LanguageClient_isServerRunning";
+pub const VIM_BUFFER_DISABLED: &str = "LanguageClient_disabled";
+pub const VIM_WORKSPACE_EDIT_FILES: &str = "g:LanguageClient_workspaceEditFiles";
 pub const VIM_STATUS_LINE_DIAGNOSTICS_COUNTS: &str = "LanguageClient_statusLineDiagnosticsCounts";
 
+/// Maximum number of resolved completion items kept for the current completion.
+pub const MAX_RESOLVED_COMPLETION_ITEMS: usize = 256;
+
 /// Thread safe read.
 pub trait SyncRead: BufRead + Sync + Send + std::fmt::Debug {}
 impl SyncRead for BufReader<ChildStdout> {}
 impl SyncRead for BufReader<TcpStream> {}
+#[cfg(unix)]
+impl SyncRead for BufReader<std::os::unix::net::UnixStream> {}
+#[cfg(windows)]
+impl SyncRead for BufReader<std::fs::File> {}
 
 /// Thread safe write.
 pub trait SyncWrite: Write + Sync + Send + std::fmt::Debug {}
 impl SyncWrite for BufWriter<ChildStdin> {}
 impl SyncWrite for BufWriter<TcpStream> {}
+#[cfg(unix)]
+impl SyncWrite for BufWriter<std::os::unix::net::UnixStream> {}
+#[cfg(windows)]
+impl SyncWrite for BufWriter<std::fs::File> {}
 
 /// Rpc message id.
 pub type Id = u64;
@@ -151,19 +194,39 @@ pub struct State {
     pub vim: Vim,
 
     pub capabilities: HashMap<String, InitializeResult>,
-    pub registrations: Vec<Registration>,
+    // language id => capabilities as sent by the server, including those lsp_types doesn't know.
+    pub server_capabilities: HashMap<String, Value>,
+    // language id => capabilities the server registered dynamically.
+    pub registrations: HashMap<String, Vec<Registration>>,
     pub roots: HashMap<String, String>,
     pub text_documents: HashMap<String, TextDocumentItem>,
     pub viewports: HashMap<String, Viewport>,
     pub text_documents_metadata: HashMap<String, TextDocumentItemMetadata>,
+    // filename => time the buffer of a document still open on the server was deleted.
+    #[serde(skip_serializing)]
+    pub pending_closes: HashMap<String, Instant>,
+    // filename => language id of the documents opened whose follow-up requests are deferred.
+    pub pending_opens: HashMap<String, String>,
+    // when the last document whose follow-up requests are deferred was opened.
+    #[serde(skip_serializing)]
+    pub last_open: Option<Instant>,
+    // filename => time of the last documentHighlight request.
+    #[serde(skip_serializing)]
+    pub document_highlight_requests: HashMap<String, Instant>,
     pub semantic_scopes: HashMap<String, Vec<Vec<String>>>,
     pub semantic_scope_to_hl_group_table: HashMap<String, Vec<Option<String>>>,
     // filename => semantic highlight state
     pub semantic_highlights: HashMap<String, TextDocumentSemanticHighlightState>,
+    // whether semantic highlights are shown, which can be toggled at runtime.
+    pub semantic_highlight_enabled: bool,
     // filename => diagnostics.
     pub diagnostics: HashMap<String, Vec<Diagnostic>>,
+    // filenames whose diagnostics are hidden, which can be toggled at runtime.
+    pub diagnostics_hidden: HashSet<String>,
     // filename => codeLens.
     pub code_lens: HashMap<String, Vec<CodeLens>>,
+    // filename => documentLink.
+    pub document_links: HashMap<String, Vec<DocumentLink>>,
     // filename => inlayHint.
     pub inlay_hints: HashMap<String, Vec<InlayHint>>,
     #[serde(skip_serializing)]
@@ -183,6 +246,49 @@ pub struct State {
     pub last_cursor_line: u64,
     pub last_line_diagnostic: String,
     pub stashed_code_action_actions: Vec<CodeAction>,
+    // ids of the windows of the hovers kept open with LanguageClient#pinHover().
+    pub pinned_hovers: Vec<u64>,
+    // (label, detail, data) of a completion item => resolved item, for the current completion.
+    pub resolved_completion_items: HashMap<String, Value>,
+    // filename => selection ranges around the last position requested.
+    pub selection_ranges: HashMap<String, SelectionRangeState>,
+    // id of the quickfix list last populated with diagnostics, 0 if none.
+    pub diagnostics_qflist_id: u64,
+    // window id => id of the location list of the window last populated with diagnostics.
+    pub diagnostics_loclist_ids: HashMap<u64, u64>,
+    // whether the quickfix list held diagnostics after the last update.
+    pub diagnostics_qflist_populated: bool,
+    // ids of the windows whose location list held diagnostics after the last update.
+    pub diagnostics_loclists_populated: HashSet<u64>,
+    // language id => trace level currently in effect for the server.
+    pub traces: HashMap<String, TraceOption>,
+    // language id => position encoding chosen by the server.
+    pub position_encodings: HashMap<String, PositionEncoding>,
+    // language id => how the server wants to be notified of changes to documents.
+    pub text_document_sync_kinds: HashMap<String, TextDocumentSyncKind>,
+    // language id => identifier of the diagnostics of the servers that prefer them to be pulled.
+    pub pull_diagnostics: HashMap<String, Option<String>>,
+    // filename => time of the last change whose diagnostics are waiting to be pulled.
+    #[serde(skip_serializing)]
+    pub pending_diagnostic_pulls: HashMap<String, Instant>,
+    // language ids of the servers that can report the diagnostics of the whole workspace.
+    pub workspace_diagnostics: HashSet<String>,
+    // language ids of the servers that provide call hierarchies.
+    pub call_hierarchy_providers: HashSet<String>,
+    // language ids of the servers that provide type hierarchies.
+    pub type_hierarchy_providers: HashSet<String>,
+    // language id => file operations the server wants to be told about.
+    pub file_operations: HashMap<String, Value>,
+    // locations of the last search cycled through, and the index of the one jumped to.
+    pub location_cycle: Option<(Vec<Location>, usize)>,
+    // method => token of the latest find locations request of that method.
+    pub find_locations_tokens: HashMap<String, u64>,
+    // language id => canonicalized filename => id of the last diagnostics report pulled.
+    pub diagnostics_result_ids: HashMap<String, HashMap<String, String>>,
+    // canonicalized filenames of the reports of the workspace diagnostics being pulled.
+    pub workspace_diagnostics_files: HashSet<String>,
+    // language id => progress token => state of the work done progress reported with that token.
+    pub progress: BTreeMap<String, BTreeMap<String, ProgressState>>,
 
     pub logger: Logger,
     /// Stores a JSON with the initialization options for all servers started with this client, each
@@ -215,17 +321,25 @@ impl State {
             clients: hashmap! { None => client },
             restarts: HashMap::new(),
             capabilities: HashMap::new(),
-            registrations: vec![],
+            server_capabilities: HashMap::new(),
+            registrations: HashMap::new(),
             roots: HashMap::new(),
             text_documents: HashMap::new(),
             viewports: HashMap::new(),
             text_documents_metadata: HashMap::new(),
+            pending_closes: HashMap::new(),
+            pending_opens: HashMap::new(),
+            last_open: None,
+            document_highlight_requests: HashMap::new(),
             semantic_scopes: HashMap::new(),
             semantic_scope_to_hl_group_table: HashMap::new(),
             semantic_highlights: HashMap::new(),
+            semantic_highlight_enabled: true,
             inlay_hints: HashMap::new(),
             code_lens: HashMap::new(),
+            document_links: HashMap::new(),
             diagnostics: HashMap::new(),
+            diagnostics_hidden: HashSet::new(),
             line_diagnostics: HashMap::new(),
             namespace_ids: HashMap::new(),
             highlight_source: None,
@@ -238,6 +352,27 @@ impl State {
             last_cursor_line: 0,
             last_line_diagnostic: " ".into(),
             stashed_code_action_actions: vec![],
+            pinned_hovers: vec![],
+            resolved_completion_items: HashMap::new(),
+            selection_ranges: HashMap::new(),
+            diagnostics_qflist_id: 0,
+            diagnostics_loclist_ids: HashMap::new(),
+            diagnostics_qflist_populated: false,
+            diagnostics_loclists_populated: HashSet::new(),
+            traces: HashMap::new(),
+            position_encodings: HashMap::new(),
+            text_document_sync_kinds: HashMap::new(),
+            pull_diagnostics: HashMap::new(),
+            pending_diagnostic_pulls: HashMap::new(),
+            workspace_diagnostics: HashSet::new(),
+            call_hierarchy_providers: HashSet::new(),
+            type_hierarchy_providers: HashSet::new(),
+            file_operations: HashMap::new(),
+            location_cycle: None,
+            find_locations_tokens: HashMap::new(),
+            diagnostics_result_ids: HashMap::new(),
+            workspace_diagnostics_files: HashSet::new(),
+            progress: BTreeMap::new(),
             initialization_options: Value::Null,
             logger,
         }
@@ -273,6 +408,282 @@ impl FromStr for SelectionUI {
     }
 }
 
+/// `textDocument/prepareCallHierarchy`. Call hierarchies are not yet part of lsp-types, so the
+/// items are kept as they were sent, to be passed back to the server as is.
+pub enum CallHierarchyPrepare {}
+
+impl lsp_types::request::Request for CallHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<Value>>;
+    const METHOD: &'static str = "textDocument/prepareCallHierarchy";
+}
+
+/// `callHierarchy/incomingCalls`, the callers of an item of a call hierarchy.
+pub enum CallHierarchyIncomingCalls {}
+
+impl lsp_types::request::Request for CallHierarchyIncomingCalls {
+    type Params = Value;
+    type Result = Option<Vec<CallHierarchyIncomingCall>>;
+    const METHOD: &'static str = "callHierarchy/incomingCalls";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    pub from: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallHierarchyItem {
+    pub uri: Url,
+}
+
+/// `textDocument/prepareTypeHierarchy`. Like call hierarchies, type hierarchies are not yet part
+/// of lsp-types, so the items are passed back to the server as they were sent.
+pub enum TypeHierarchyPrepare {}
+
+impl lsp_types::request::Request for TypeHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<Value>>;
+    const METHOD: &'static str = "textDocument/prepareTypeHierarchy";
+}
+
+/// `typeHierarchy/supertypes`, the parents of an item of a type hierarchy.
+pub enum TypeHierarchySupertypes {}
+
+impl lsp_types::request::Request for TypeHierarchySupertypes {
+    type Params = Value;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/supertypes";
+}
+
+/// `typeHierarchy/subtypes`, the children of an item of a type hierarchy.
+pub enum TypeHierarchySubtypes {}
+
+impl lsp_types::request::Request for TypeHierarchySubtypes {
+    type Params = Value;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/subtypes";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyItem {
+    pub name: String,
+    pub uri: Url,
+    pub selection_range: Range,
+}
+
+/// `textDocument/diagnostic`, used by servers that prefer diagnostics to be pulled rather than
+/// published. Not yet part of lsp-types.
+pub enum DocumentDiagnosticRequest {}
+
+impl lsp_types::request::Request for DocumentDiagnosticRequest {
+    type Params = DocumentDiagnosticParams;
+    type Result = DocumentDiagnosticReport;
+    const METHOD: &'static str = "textDocument/diagnostic";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiagnosticParams {
+    pub text_document: TextDocumentIdentifier,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_result_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DocumentDiagnosticReport {
+    #[serde(rename_all = "camelCase")]
+    Full {
+        result_id: Option<String>,
+        items: Vec<Diagnostic>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Unchanged { result_id: String },
+}
+
+/// `workspace/diagnostic`, pulls the diagnostics of every file of the workspace. Not yet part of
+/// lsp-types.
+pub enum WorkspaceDiagnosticRequest {}
+
+/// Tokens of the progress and the partial results of `workspace/diagnostic`.
+pub const WORKSPACE_DIAGNOSTIC_PROGRESS_TOKEN: &str = "workspace/diagnostic";
+pub const WORKSPACE_DIAGNOSTIC_PARTIAL_RESULT_TOKEN: &str = "workspace/diagnostic/partialResult";
+
+impl lsp_types::request::Request for WorkspaceDiagnosticRequest {
+    type Params = WorkspaceDiagnosticParams;
+    type Result = WorkspaceDiagnosticReport;
+    const METHOD: &'static str = "workspace/diagnostic";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDiagnosticParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    pub previous_result_ids: Vec<PreviousResultId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_done_token: Option<NumberOrString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_result_token: Option<NumberOrString>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviousResultId {
+    pub uri: Url,
+    pub value: String,
+}
+
+/// The result of `workspace/diagnostic`, also sent in parts as `$/progress` notifications when
+/// partial results were asked for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkspaceDiagnosticReport {
+    pub items: Vec<WorkspaceDocumentDiagnosticReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum WorkspaceDocumentDiagnosticReport {
+    #[serde(rename_all = "camelCase")]
+    Full {
+        uri: Url,
+        version: Option<i64>,
+        result_id: Option<String>,
+        items: Vec<Diagnostic>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Unchanged {
+        uri: Url,
+        version: Option<i64>,
+        result_id: String,
+    },
+}
+
+/// `textDocument/linkedEditingRange`, the ranges edited together with the one at a position,
+/// e.g. the opening and closing tags of an element. Not yet part of lsp-types.
+pub enum LinkedEditingRangeRequest {}
+
+impl lsp_types::request::Request for LinkedEditingRangeRequest {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<LinkedEditingRanges>;
+    const METHOD: &'static str = "textDocument/linkedEditingRange";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkedEditingRanges {
+    pub ranges: Vec<Range>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_pattern: Option<String>,
+}
+
+/// `window/showDocument`, sent by the server to have a document or URL shown. Not yet part of
+/// lsp-types.
+pub enum ShowDocument {}
+
+impl lsp_types::request::Request for ShowDocument {
+    type Params = ShowDocumentParams;
+    type Result = ShowDocumentResult;
+    const METHOD: &'static str = "window/showDocument";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShowDocumentParams {
+    pub uri: Url,
+    // whether to show the resource in an external program, e.g. a browser.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub take_focus: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Range>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShowDocumentResult {
+    pub success: bool,
+}
+
+/// `workspace/willCreateFiles`, sent before files are created, so that the server can make edits
+/// for them. File operations are not yet part of lsp-types.
+pub enum WillCreateFiles {}
+
+impl lsp_types::request::Request for WillCreateFiles {
+    type Params = CreateFilesParams;
+    type Result = Option<WorkspaceEdit>;
+    const METHOD: &'static str = "workspace/willCreateFiles";
+}
+
+/// `$/setTrace`, sent to change the trace level of the server. Not yet part of lsp-types.
+pub enum SetTrace {}
+
+impl lsp_types::notification::Notification for SetTrace {
+    type Params = SetTraceParams;
+    const METHOD: &'static str = "$/setTrace";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetTraceParams {
+    pub value: TraceOption,
+}
+
+/// `$/logTrace`, the traces of the execution of the server, as set with `$/setTrace`.
+pub enum LogTrace {}
+
+impl lsp_types::notification::Notification for LogTrace {
+    type Params = LogTraceParams;
+    const METHOD: &'static str = "$/logTrace";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogTraceParams {
+    pub message: String,
+    // only sent when the trace level is verbose.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<String>,
+}
+
+/// `workspace/didCreateFiles`, sent after files were created.
+pub enum DidCreateFiles {}
+
+impl lsp_types::notification::Notification for DidCreateFiles {
+    type Params = CreateFilesParams;
+    const METHOD: &'static str = "workspace/didCreateFiles";
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateFilesParams {
+    pub files: Vec<FileCreate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileCreate {
+    pub uri: Url,
+}
+
+/// Chain of selection ranges around a position, from the innermost to the outermost, along with
+/// the index of the range currently selected.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelectionRangeState {
+    pub version: i64,
+    pub ranges: Vec<Range>,
+    pub index: usize,
+}
+
+impl SelectionRangeState {
+    pub fn contains(&self, position: Position) -> bool {
+        self.ranges.get(self.index).map_or(false, |range| {
+            range.start <= position && position <= range.end
+        })
+    }
+}
+
 pub enum LCNamespace {
     VirtualText,
     SemanticHighlight,
@@ -316,7 +727,110 @@ impl FromStr for HoverPreviewOption {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Which range of an insert-replace completion edit to apply.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CompletionEditRange {
+    Insert,
+    Replace,
+    /// Replace when completing in front of the rest of a word, insert otherwise.
+    Auto,
+}
+
+impl Default for CompletionEditRange {
+    fn default() -> Self {
+        CompletionEditRange::Auto
+    }
+}
+
+impl FromStr for CompletionEditRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "INSERT" => Ok(CompletionEditRange::Insert),
+            "REPLACE" => Ok(CompletionEditRange::Replace),
+            "AUTO" => Ok(CompletionEditRange::Auto),
+            _ => Err(anyhow!(
+                "Invalid option for LanguageClient_completionEditRange: {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Where virtual texts are shown on their line, as the `virt_text_pos` of neovim's extmarks.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VirtualTextPosition {
+    Eol,
+    Overlay,
+    RightAlign,
+}
+
+impl Default for VirtualTextPosition {
+    fn default() -> Self {
+        VirtualTextPosition::Eol
+    }
+}
+
+impl FromStr for VirtualTextPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "EOL" => Ok(VirtualTextPosition::Eol),
+            "OVERLAY" => Ok(VirtualTextPosition::Overlay),
+            "RIGHT_ALIGN" => Ok(VirtualTextPosition::RightAlign),
+            _ => Err(anyhow!(
+                "Invalid option for LanguageClient_virtualTextPosition: {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Encoding of the character offsets of positions exchanged with a server.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PositionEncoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "utf-16")]
+    Utf16,
+    #[serde(rename = "utf-32")]
+    Utf32,
+}
+
+impl Default for PositionEncoding {
+    fn default() -> Self {
+        PositionEncoding::Utf16
+    }
+}
+
+/// Snippet plugin used to expand snippet completion items.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SnippetEngine {
+    UltiSnips,
+    Vsnip,
+    Neosnippet,
+}
+
+impl FromStr for SnippetEngine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "ULTISNIPS" => Ok(SnippetEngine::UltiSnips),
+            "VSNIP" => Ok(SnippetEngine::Vsnip),
+            "NEOSNIPPET" => Ok(SnippetEngine::Neosnippet),
+            _ => Err(anyhow!(
+                "Invalid option for LanguageClient_snippetEngine: {}",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DiagnosticsList {
     Quickfix,
     Location,
@@ -345,6 +859,15 @@ impl FromStr for DiagnosticsList {
     }
 }
 
+/// The kind of content a quickfix or location list is populated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ListKind {
+    Locations,
+    Symbols,
+    Diagnostics,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CodeLensDisplay {
@@ -472,6 +995,9 @@ pub struct QuickfixEntry {
     pub text: Option<String>,
     #[serde(rename = "type")]
     pub typ: Option<char>,
+    /// Entries marked as not valid (e.g. headers) can't be jumped to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -559,60 +1085,50 @@ pub struct VimCompleteItemUserData {
     pub snippet: Option<String>,
 }
 
+/// Details of the label of a completion item, which are not yet part of lsp-types.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct CompletionItemLabelDetails {
+    pub detail: Option<String>,
+    pub description: Option<String>,
+}
+
 impl VimCompleteItem {
-    pub fn from_lsp(lspitem: &CompletionItem, complete_position: Option<u64>) -> Result<Self> {
+    pub fn from_lsp(
+        lspitem: &CompletionItem,
+        label_details: Option<&CompletionItemLabelDetails>,
+        kind_labels: &HashMap<String, String>,
+        complete_position: Option<u64>,
+    ) -> Result<Self> {
         debug!(
             "LSP CompletionItem to VimCompleteItem: {:?}, {:?}",
             lspitem, complete_position
         );
         let abbr = lspitem.label.clone();
 
-        if let Some(CompletionTextEdit::InsertAndReplace(_)) = lspitem.text_edit {
-            error!("insert replace is not supported");
-        }
-
         let word = lspitem.insert_text.clone().unwrap_or_else(|| {
-            if lspitem.text_edit.iter().any(|te| match te {
-                CompletionTextEdit::Edit(_) => false,
-                CompletionTextEdit::InsertAndReplace(_) => true,
-            }) {
-                error!("insert replace is not supported");
-            }
+            // Vim only inserts text before the cursor, so for insert-replace edits the start of
+            // the insert range is used, the rest is handled in handle_complete_done.
+            let (new_text, start) = match lspitem.text_edit {
+                Some(CompletionTextEdit::Edit(ref edit)) => (&edit.new_text, edit.range.start),
+                Some(CompletionTextEdit::InsertAndReplace(ref edit)) => {
+                    (&edit.new_text, edit.insert.start)
+                }
+                None => return lspitem.label.clone(),
+            };
 
-            if lspitem.insert_text_format == Some(InsertTextFormat::Snippet)
-                || lspitem
-                    .text_edit
-                    .as_ref()
-                    .map(|text_edit| match text_edit {
-                        CompletionTextEdit::Edit(edit) => edit.new_text.is_empty(),
-                        // InsertAndReplace is not supported, so if we encounter a completion item
-                        // with a text edit of this variant, then we just default to using the label
-                        // as the format instead of the new text.
-                        CompletionTextEdit::InsertAndReplace(_) => true,
-                    })
-                    .unwrap_or(true)
+            if lspitem.insert_text_format == Some(InsertTextFormat::Snippet) || new_text.is_empty()
             {
                 return lspitem.label.clone();
             }
 
-            match (lspitem.text_edit.clone(), complete_position) {
-                // see comment above about InsertAndReplace
-                (Some(CompletionTextEdit::InsertAndReplace(_)), _) => lspitem.label.clone(),
-                (Some(CompletionTextEdit::Edit(ref text_edit)), Some(complete_position)) => {
-                    // TextEdit range start might be different from vim expected completion start.
-                    // From spec, TextEdit can only span one line, i.e., the current line.
-                    if text_edit.range.start.character != complete_position {
-                        text_edit
-                            .new_text
-                            .get((complete_position as usize)..)
-                            .and_then(|line| line.split_whitespace().next())
-                            .map_or_else(String::new, ToOwned::to_owned)
-                    } else {
-                        text_edit.new_text.clone()
-                    }
-                }
-                (Some(CompletionTextEdit::Edit(ref text_edit)), _) => text_edit.new_text.clone(),
-                (_, _) => lspitem.label.clone(),
+            match complete_position {
+                // TextEdit range start might be different from vim expected completion start.
+                // From spec, TextEdit can only span one line, i.e., the current line.
+                Some(complete_position) if start.character != complete_position => new_text
+                    .get((complete_position as usize)..)
+                    .and_then(|line| line.split_whitespace().next())
+                    .map_or_else(String::new, ToOwned::to_owned),
+                _ => new_text.clone(),
             }
         });
 
@@ -623,11 +1139,43 @@ impl VimCompleteItem {
             snippet = None;
         };
 
+        let detail = lspitem
+            .detail
+            .clone()
+            .unwrap_or_default()
+            .replace("\n", " ");
+        // The label details take the place of the detail in the menu, which then goes to the info.
+        let label_details = label_details
+            .map(|details| {
+                vec![&details.detail, &details.description]
+                    .into_iter()
+                    .filter_map(|s| s.as_deref())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .replace("\n", " ")
+            })
+            .filter(|details| !details.is_empty());
+
         let mut info = String::new();
+        if label_details.is_some() && !detail.is_empty() {
+            info += &detail;
+        }
         if let Some(ref doc) = lspitem.documentation {
+            if !info.is_empty() {
+                info += "\n\n";
+            }
             info += &doc.to_string();
         }
 
+        // Kinds are configured by their name in the spec, e.g. `Function`.
+        let kind = lspitem.kind.map_or_else(String::new, |kind| {
+            kind_labels
+                .get(&format!("{:?}", kind))
+                .cloned()
+                .unwrap_or_else(|| completion_item_kind_abbr(kind).to_string())
+        });
+
         let user_data = VimCompleteItemUserData {
             lspitem: Some(lspitem.clone()),
             snippet: snippet.clone(),
@@ -639,13 +1187,9 @@ impl VimCompleteItem {
             abbr,
             icase: Some(1),
             dup: Some(1),
-            menu: lspitem
-                .detail
-                .clone()
-                .unwrap_or_default()
-                .replace("\n", " "),
+            menu: label_details.unwrap_or(detail),
             info,
-            kind: lspitem.kind.map(|k| format!("{:?}", k)).unwrap_or_default(),
+            kind,
             is_snippet: Some(snippet.is_some()),
             snippet,
             user_data: Some(serde_json::to_string(&user_data)?),
@@ -760,11 +1304,26 @@ impl ToString for NumberOrString {
     }
 }
 
+/// Lines of a preview holding code of another filetype, like a fenced code block in markdown.
+/// Lines are 1-based and inclusive.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CodeRegion {
+    pub start: u64,
+    pub end: u64,
+    pub filetype: String,
+}
+
 pub trait ToDisplay {
     fn to_display(&self) -> Vec<String>;
     fn vim_filetype(&self) -> Option<String> {
         None
     }
+    fn code_regions(&self) -> Vec<CodeRegion> {
+        match self.vim_filetype().as_deref() {
+            Some("markdown") => markdown_code_regions(&self.to_display()),
+            _ => vec![],
+        }
+    }
 }
 
 impl ToDisplay for lsp_types::MarkedString {
@@ -801,22 +1360,29 @@ impl ToDisplay for Hover {
     fn to_display(&self) -> Vec<String> {
         match self.contents {
             HoverContents::Scalar(ref ms) => ms.to_display(),
-            HoverContents::Array(ref arr) => arr
-                .iter()
-                .flat_map(|ms| {
-                    if let MarkedString::LanguageString(ref ls) = ms {
-                        let mut buf = Vec::new();
-
-                        buf.push(format!("```{}", ls.language));
-                        buf.extend(ls.value.lines().map(String::from));
-                        buf.push("```".to_string());
-
-                        buf
-                    } else {
-                        ms.to_display()
+            HoverContents::Array(ref arr) => {
+                // The array is displayed as markdown, so code blocks are fenced with their own
+                // language and elements are separated by a horizontal rule.
+                let mut buf = Vec::new();
+                for ms in arr {
+                    let lines = match ms {
+                        MarkedString::LanguageString(ref ls) if !ls.value.trim().is_empty() => {
+                            let mut lines = vec![format!("```{}", ls.language)];
+                            lines.extend(ls.value.lines().map(String::from));
+                            lines.push("```".to_string());
+                            lines
+                        }
+                        MarkedString::String(ref s) if !s.trim().is_empty() => ms.to_display(),
+                        _ => continue,
+                    };
+
+                    if !buf.is_empty() {
+                        buf.push("---".to_string());
                     }
-                })
-                .collect(),
+                    buf.extend(lines);
+                }
+                buf
+            }
             HoverContents::Markup(ref mc) => mc.to_display(),
         }
     }
@@ -859,7 +1425,7 @@ impl LinesLen for Hover {
     fn lines_len(&self) -> usize {
         match self.contents {
             HoverContents::Scalar(ref c) => c.lines_len(),
-            HoverContents::Array(ref arr) => arr.iter().map(LinesLen::lines_len).sum(),
+            HoverContents::Array(_) => self.to_display().len(),
             HoverContents::Markup(ref c) => c.lines_len(),
         }
     }
@@ -978,6 +1544,13 @@ pub enum RootMarkers {
     Map(HashMap<String, Vec<String>>),
 }
 
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum SettingsPath {
+    Array(Vec<String>),
+    Map(HashMap<String, Vec<String>>),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WindowProgressParams {
     pub title: Option<String>,
@@ -986,6 +1559,36 @@ pub struct WindowProgressParams {
     pub done: Option<bool>,
 }
 
+const PROGRESS_SPINNER_FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// State of a work done progress, as last reported by the server.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressState {
+    pub title: String,
+    pub message: Option<String>,
+    pub percentage: Option<u32>,
+    // number of reports received, used to animate the spinner.
+    pub frame: usize,
+}
+
+impl ProgressState {
+    /// Formats the progress for the statusline, e.g. `⠙ indexing 42% (std)`.
+    pub fn status_message(&self) -> String {
+        let mut buf = format!(
+            "{} {}",
+            PROGRESS_SPINNER_FRAMES[self.frame % PROGRESS_SPINNER_FRAMES.len()],
+            self.title
+        );
+        if let Some(percentage) = self.percentage {
+            buf += &format!(" {}%", percentage);
+        }
+        if let Some(message) = &self.message {
+            buf += &format!(" ({})", message);
+        }
+        buf
+    }
+}
+
 pub trait Filepath {
     fn filepath(&self) -> Result<PathBuf>;
 }
@@ -1050,15 +1653,18 @@ impl ToLSP<Vec<FileEvent>> for notify::DebouncedEvent {
 }
 
 pub trait ListItem {
-    fn quickfix_item(&self, lc: &LanguageClient) -> Result<QuickfixEntry>;
-    fn string_item(&self, lc: &LanguageClient, cwd: &str) -> Result<String>;
+    fn quickfix_item(&self, lc: &LanguageClient, lines: &LineCache) -> Result<QuickfixEntry>;
+    fn string_item(&self, lc: &LanguageClient, lines: &LineCache, cwd: &str) -> Result<String>;
 }
 
 impl ListItem for Location {
-    fn quickfix_item(&self, lc: &LanguageClient) -> Result<QuickfixEntry> {
+    fn quickfix_item(&self, lc: &LanguageClient, lines: &LineCache) -> Result<QuickfixEntry> {
         let filename = self.uri.filepath()?.to_string_lossy().into_owned();
-        let start = self.range.start;
-        let text = lc.get_line(&filename, start.line).unwrap_or_default();
+        let start = lc.position_to_bytes(lines, Path::new(&filename), self.range.start)?;
+        let text = lc
+            .get_cached_line(lines, Path::new(&filename), start.line)
+            .map(|text| text.trim().to_owned())
+            .unwrap_or_default();
 
         Ok(QuickfixEntry {
             filename,
@@ -1067,13 +1673,17 @@ impl ListItem for Location {
             text: Some(text),
             nr: None,
             typ: None,
+            valid: None,
         })
     }
 
-    fn string_item(&self, lc: &LanguageClient, cwd: &str) -> Result<String> {
+    fn string_item(&self, lc: &LanguageClient, lines: &LineCache, cwd: &str) -> Result<String> {
         let filename = self.uri.filepath()?;
-        let start = self.range.start;
-        let text = lc.get_line(&filename, start.line).unwrap_or_default();
+        let start = lc.position_to_bytes(lines, &filename, self.range.start)?;
+        let text = lc
+            .get_cached_line(lines, &filename, start.line)
+            .map(|text| text.trim().to_owned())
+            .unwrap_or_default();
         let relpath = diff_paths(&filename, Path::new(&cwd)).unwrap_or(filename);
         Ok(format!(
             "{}:{}:{}:\t{}",
@@ -1085,8 +1695,101 @@ impl ListItem for Location {
     }
 }
 
+/// The lines of the files read while building a list, so that a file with many entries in the
+/// list is fetched only once. Local to the list being built, see
+/// `LanguageClient::with_line_cache`.
+#[derive(Debug, Default)]
+pub struct LineCache {
+    pub files: RefCell<HashMap<String, CachedLines>>,
+}
+
+/// The lines of a file cached while building a list: those of its buffer, and those on disk, read
+/// the first time a line is missing from the buffer.
+#[derive(Debug, Default)]
+pub struct CachedLines {
+    pub buffer: Vec<String>,
+    pub disk: Option<Vec<String>>,
+}
+
+/// A single change of a rename, as shown in the rename preview.
+#[derive(Debug, Clone)]
+pub struct RenamePreviewItem {
+    pub filename: String,
+    pub line: u64,
+    pub character: u64,
+    pub before: String,
+    pub after: String,
+}
+
+impl ListItem for RenamePreviewItem {
+    fn quickfix_item(&self, _: &LanguageClient, _: &LineCache) -> Result<QuickfixEntry> {
+        Ok(QuickfixEntry {
+            filename: self.filename.clone(),
+            lnum: self.line + 1,
+            col: Some(self.character + 1),
+            text: Some(format!("{} → {}", self.before, self.after)),
+            nr: None,
+            typ: None,
+            valid: None,
+        })
+    }
+
+    fn string_item(&self, _: &LanguageClient, _: &LineCache, cwd: &str) -> Result<String> {
+        let filename = PathBuf::from(&self.filename);
+        let relpath = diff_paths(&filename, Path::new(cwd)).unwrap_or(filename);
+        Ok(format!(
+            "{}:{}:{}:\t{} → {}",
+            relpath.to_string_lossy(),
+            self.line + 1,
+            self.character + 1,
+            self.before,
+            self.after,
+        ))
+    }
+}
+
+/// A code lens of a file, as shown in the list of code lenses.
+#[derive(Debug, Clone)]
+pub struct CodeLensItem {
+    pub filename: String,
+    pub code_lens: CodeLens,
+}
+
+impl ListItem for CodeLensItem {
+    fn quickfix_item(&self, lc: &LanguageClient, lines: &LineCache) -> Result<QuickfixEntry> {
+        let start =
+            lc.position_to_bytes(lines, Path::new(&self.filename), self.code_lens.range.start)?;
+        Ok(QuickfixEntry {
+            filename: self.filename.clone(),
+            lnum: start.line + 1,
+            col: Some(start.character + 1),
+            text: self.code_lens.command.as_ref().map(|cmd| cmd.title.clone()),
+            nr: None,
+            typ: None,
+            valid: None,
+        })
+    }
+
+    fn string_item(&self, lc: &LanguageClient, lines: &LineCache, cwd: &str) -> Result<String> {
+        let filename = PathBuf::from(&self.filename);
+        let start = lc.position_to_bytes(lines, &filename, self.code_lens.range.start)?;
+        let relpath = diff_paths(&filename, Path::new(cwd)).unwrap_or(filename);
+        Ok(format!(
+            "{}:{}:{}:\t{}",
+            relpath.to_string_lossy(),
+            start.line + 1,
+            start.character + 1,
+            self.code_lens
+                .command
+                .as_ref()
+                .map(|cmd| cmd.title.as_str())
+                .unwrap_or_default(),
+        ))
+    }
+}
+
 impl ListItem for CodeAction {
-    fn quickfix_item(&self, _: &LanguageClient) -> Result<QuickfixEntry> {
+    fn quickfix_item(&self, _: &LanguageClient, _: &LineCache) -> Result<QuickfixEntry> {
         let text = Some(format!(
             "{}: {}",
             code_action_kind_as_str(&self),
@@ -1100,10 +1803,11 @@ impl ListItem for CodeAction {
             text,
             nr: None,
             typ: None,
+            valid: None,
         })
     }
 
-    fn string_item(&self, _: &LanguageClient, _: &str) -> Result<String> {
+    fn string_item(&self, _: &LanguageClient, _: &LineCache, _: &str) -> Result<String> {
         Ok(format!(
             "{}: {}",
             code_action_kind_as_str(&self),
@@ -1113,7 +1817,7 @@ impl ListItem for CodeAction {
 }
 
 impl ListItem for Command {
-    fn quickfix_item(&self, _: &LanguageClient) -> Result<QuickfixEntry> {
+    fn quickfix_item(&self, _: &LanguageClient, _: &LineCache) -> Result<QuickfixEntry> {
         Ok(QuickfixEntry {
             filename: "".into(),
             lnum: 0,
@@ -1121,16 +1825,17 @@ impl ListItem for Command {
             text: Some(format!("{}: {}", self.command, self.title)),
             nr: None,
             typ: None,
+            valid: None,
         })
     }
 
-    fn string_item(&self, _: &LanguageClient, _: &str) -> Result<String> {
+    fn string_item(&self, _: &LanguageClient, _: &LineCache, _: &str) -> Result<String> {
         Ok(format!("{}: {}", self.command, self.title))
     }
 }
 
 impl ListItem for lsp_types::DocumentSymbol {
-    fn quickfix_item(&self, _: &LanguageClient) -> Result<QuickfixEntry> {
+    fn quickfix_item(&self, _: &LanguageClient, _: &LineCache) -> Result<QuickfixEntry> {
         let start = self.selection_range.start;
         let result = QuickfixEntry {
             filename: "".to_string(),
@@ -1139,11 +1844,12 @@ impl ListItem for lsp_types::DocumentSymbol {
             text: Some(self.name.clone()),
             nr: None,
             typ: None,
+            valid: None,
         };
         Ok(result)
     }
 
-    fn string_item(&self, _: &LanguageClient, _: &str) -> Result<String> {
+    fn string_item(&self, _: &LanguageClient, _: &LineCache, _: &str) -> Result<String> {
         let start = self.selection_range.start;
         let result = format!(
             "{}:{}:\t{}\t\t{:?}",
@@ -1157,23 +1863,25 @@ impl ListItem for lsp_types::DocumentSymbol {
 }
 
 impl ListItem for SymbolInformation {
-    fn quickfix_item(&self, _: &LanguageClient) -> Result<QuickfixEntry> {
-        let start = self.location.range.start;
+    fn quickfix_item(&self, lc: &LanguageClient, lines: &LineCache) -> Result<QuickfixEntry> {
+        let filename = self.location.uri.filepath()?;
+        let start = lc.position_to_bytes(lines, &filename, self.location.range.start)?;
 
         Ok(QuickfixEntry {
-            filename: self.location.uri.filepath()?.to_string_lossy().into_owned(),
+            filename: filename.to_string_lossy().into_owned(),
             lnum: start.line + 1,
             col: Some(start.character + 1),
             text: Some(self.name.clone()),
             nr: None,
             typ: None,
+            valid: None,
         })
     }
 
-    fn string_item(&self, _: &LanguageClient, cwd: &str) -> Result<String> {
+    fn string_item(&self, lc: &LanguageClient, lines: &LineCache, cwd: &str) -> Result<String> {
         let filename = self.location.uri.filepath()?;
+        let start = lc.position_to_bytes(lines, &filename, self.location.range.start)?;
         let relpath = diff_paths(&filename, Path::new(cwd)).unwrap_or(filename);
-        let start = self.location.range.start;
         Ok(format!(
             "{}:{}:{}:\t{}\t\t{:?}",
             relpath.to_string_lossy(),
@@ -1198,6 +1906,9 @@ pub struct VirtualText {
     pub line: u64,
     pub text: String,
     pub hl_group: String,
+    pub position: VirtualTextPosition,
+    // Number of spaces in front of the text.
+    pub spacing: usize,
 }
 
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -1,7 +1,9 @@
-use crate::types::{RootMarkers, ToUsize};
+use crate::types::{
+    CodeRegion, CompletionItemLabelDetails, PositionEncoding, RootMarkers, ToUsize,
+};
 use anyhow::{anyhow, Result};
 use log::*;
-use lsp_types::{CodeAction, Position, TextEdit, Url};
+use lsp_types::{CodeAction, CompletionItem, CompletionItemKind, Position, Range, TextEdit, Url};
 use serde_json::json;
 use serde_json::Value;
 use std::{
@@ -183,6 +185,72 @@ fn position_to_offset(lines: &[String], position: &Position) -> usize {
     chars_above + character
 }
 
+/// Converts a character offset in the given encoding into a byte offset into `line`.
+pub fn character_to_byte(line: &str, character: u64, encoding: PositionEncoding) -> u64 {
+    if encoding == PositionEncoding::Utf8 {
+        return character;
+    }
+
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= character {
+            return idx as u64;
+        }
+        units += char_units(c, encoding);
+    }
+    line.len() as u64
+}
+
+/// Converts a byte offset into `line` into a character offset in the given encoding.
+pub fn byte_to_character(line: &str, byte: u64, encoding: PositionEncoding) -> u64 {
+    if encoding == PositionEncoding::Utf8 {
+        return byte;
+    }
+
+    line.char_indices()
+        .take_while(|(idx, _)| (*idx as u64) < byte)
+        .map(|(_, c)| char_units(c, encoding))
+        .sum()
+}
+
+fn char_units(c: char, encoding: PositionEncoding) -> u64 {
+    match encoding {
+        PositionEncoding::Utf8 => c.len_utf8() as u64,
+        PositionEncoding::Utf16 => c.len_utf16() as u64,
+        PositionEncoding::Utf32 => 1,
+    }
+}
+
+/// Converts a range sent by a server into a range of byte offsets into `lines`.
+pub fn range_to_bytes(lines: &[String], range: &Range, encoding: PositionEncoding) -> Range {
+    let convert = |position: &Position| {
+        let line = lines
+            .get(position.line as usize)
+            .map(String::as_str)
+            .unwrap_or_default();
+        Position::new(
+            position.line,
+            character_to_byte(line, position.character, encoding),
+        )
+    };
+    Range::new(convert(&range.start), convert(&range.end))
+}
+
+/// Converts a range of byte offsets into `lines` into a range in the encoding of a server.
+pub fn range_from_bytes(lines: &[String], range: &Range, encoding: PositionEncoding) -> Range {
+    let convert = |position: &Position| {
+        let line = lines
+            .get(position.line as usize)
+            .map(String::as_str)
+            .unwrap_or_default();
+        Position::new(
+            position.line,
+            byte_to_character(line, position.character, encoding),
+        )
+    };
+    Range::new(convert(&range.start), convert(&range.end))
+}
+
 fn offset_to_position(lines: &[String], offset: usize) -> Position {
     if lines.is_empty() {
         return Position::new(0, 0);
@@ -481,6 +549,435 @@ pub fn code_action_kind_as_str(action: &CodeAction) -> &str {
     }
 }
 
+/// Short abbreviation of a completion item kind, shown in the completion menu when the kind has no
+/// label configured.
+pub fn completion_item_kind_abbr(kind: CompletionItemKind) -> &'static str {
+    match kind {
+        CompletionItemKind::Text => "text",
+        CompletionItemKind::Method => "meth",
+        CompletionItemKind::Function => "fn",
+        CompletionItemKind::Constructor => "ctor",
+        CompletionItemKind::Field => "field",
+        CompletionItemKind::Variable => "var",
+        CompletionItemKind::Class => "class",
+        CompletionItemKind::Interface => "iface",
+        CompletionItemKind::Module => "mod",
+        CompletionItemKind::Property => "prop",
+        CompletionItemKind::Unit => "unit",
+        CompletionItemKind::Value => "val",
+        CompletionItemKind::Enum => "enum",
+        CompletionItemKind::Keyword => "kw",
+        CompletionItemKind::Snippet => "snip",
+        CompletionItemKind::Color => "color",
+        CompletionItemKind::File => "file",
+        CompletionItemKind::Reference => "ref",
+        CompletionItemKind::Folder => "dir",
+        CompletionItemKind::EnumMember => "enum",
+        CompletionItemKind::Constant => "const",
+        CompletionItemKind::Struct => "struct",
+        CompletionItemKind::Event => "event",
+        CompletionItemKind::Operator => "op",
+        CompletionItemKind::TypeParameter => "type",
+    }
+}
+
+/// Order in which completion items are shown: preselected items first, then by sort text, which
+/// defaults to the label, keeping the order of the server for items that compare equal. Returns
+/// the indices of the items.
+pub fn completion_items_order(items: &[CompletionItem]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    order.sort_by_key(|&idx| {
+        let item = &items[idx];
+        (
+            !item.preselect.unwrap_or_default(),
+            item.sort_text.as_ref().unwrap_or(&item.label),
+            &item.label,
+        )
+    });
+    order
+}
+
+/// Applies the `itemDefaults` of a completion list to each of its items.
+///
+/// Fields already present in an item take precedence over the defaults. An `editRange` default
+/// is turned into the item's `textEdit`, either a plain `TextEdit` or an `InsertReplaceEdit`
+/// depending on the shape of the range, using `textEditText` or `label` as the new text.
+pub fn apply_completion_item_defaults(result: &mut Value) {
+    let defaults = match result.get("itemDefaults") {
+        Some(Value::Object(defaults)) => defaults.clone(),
+        _ => return,
+    };
+
+    let items = match result.get_mut("items") {
+        Some(Value::Array(items)) => items,
+        _ => return,
+    };
+
+    for item in items.iter_mut().filter_map(Value::as_object_mut) {
+        for key in &[
+            "commitCharacters",
+            "insertTextFormat",
+            "insertTextMode",
+            "data",
+        ] {
+            if let Some(value) = defaults.get(*key) {
+                item.entry(*key).or_insert_with(|| value.clone());
+            }
+        }
+
+        if item.contains_key("textEdit") {
+            continue;
+        }
+
+        let edit_range = match defaults.get("editRange") {
+            Some(Value::Object(edit_range)) => edit_range,
+            _ => continue,
+        };
+        let new_text = item
+            .get("textEditText")
+            .or_else(|| item.get("label"))
+            .cloned()
+            .unwrap_or_else(|| json!(""));
+
+        let text_edit = if edit_range.contains_key("insert") && edit_range.contains_key("replace") {
+            json!({
+                "newText": new_text,
+                "insert": edit_range["insert"],
+                "replace": edit_range["replace"],
+            })
+        } else {
+            json!({
+                "newText": new_text,
+                "range": edit_range,
+            })
+        };
+        item.insert("textEdit".to_owned(), text_edit);
+    }
+}
+
+/// Gets the label details of the items of a completion response, in the order of the items.
+pub fn completion_label_details(result: &Value) -> Vec<Option<CompletionItemLabelDetails>> {
+    let items = match result {
+        Value::Array(items) => items,
+        Value::Object(list) => match list.get("items") {
+            Some(Value::Array(items)) => items,
+            _ => return vec![],
+        },
+        _ => return vec![],
+    };
+
+    items
+        .iter()
+        .map(|item| {
+            item.get("labelDetails")
+                .and_then(|details| serde_json::from_value(details.clone()).ok())
+        })
+        .collect()
+}
+
+/// Substitutes the `{root}`, `{filename}` and `{languageId}` placeholders of an argument of a server
+/// command.
+pub fn substitute_command_placeholders(
+    arg: &str,
+    root: &str,
+    filename: &str,
+    language_id: &str,
+) -> String {
+    arg.replace("{root}", root)
+        .replace("{filename}", filename)
+        .replace("{languageId}", language_id)
+}
+
+/// Indents the lines after the first one of a completion with the given indentation, for items
+/// whose insert text mode is to adjust the indentation to the line they are inserted in.
+pub fn adjust_indentation(text: &str, indent: &str) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(idx, line)| {
+            if idx == 0 || line.trim().is_empty() {
+                line.to_owned()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Converts a snippet in the LSP snippet syntax into plain text, replacing tabstops and variables
+/// with nothing, placeholders with their default text and choices with their first option.
+pub fn strip_snippet_placeholders(snippet: &str) -> String {
+    let chars: Vec<char> = snippet.chars().collect();
+    let mut text = String::with_capacity(snippet.len());
+    let mut pos = 0;
+    strip_snippet_text(&chars, &mut pos, &mut text, false);
+    text
+}
+
+fn strip_snippet_text(chars: &[char], pos: &mut usize, text: &mut String, nested: bool) {
+    while *pos < chars.len() {
+        match chars[*pos] {
+            '\\' if *pos + 1 < chars.len() && matches!(chars[*pos + 1], '$' | '}' | '\\') => {
+                text.push(chars[*pos + 1]);
+                *pos += 2;
+            }
+            '}' if nested => {
+                *pos += 1;
+                return;
+            }
+            '$' => {
+                *pos += 1;
+                strip_snippet_element(chars, pos, text);
+            }
+            c => {
+                text.push(c);
+                *pos += 1;
+            }
+        }
+    }
+}
+
+fn strip_snippet_element(chars: &[char], pos: &mut usize, text: &mut String) {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    match chars.get(*pos) {
+        // Tabstop or variable, e.g. `$1` or `$TM_FILENAME`.
+        Some(c) if is_name(*c) => {
+            while *pos < chars.len() && is_name(chars[*pos]) {
+                *pos += 1;
+            }
+        }
+        Some('{') => {
+            *pos += 1;
+            while *pos < chars.len() && is_name(chars[*pos]) {
+                *pos += 1;
+            }
+
+            match chars.get(*pos) {
+                Some('}') => *pos += 1,
+                // Placeholder, e.g. `${1:default}`, which can be nested.
+                Some(':') => {
+                    *pos += 1;
+                    strip_snippet_text(chars, pos, text, true);
+                }
+                // Choice, e.g. `${1|one,two|}`, only the first option is kept.
+                Some('|') => {
+                    *pos += 1;
+                    let mut first = true;
+                    while *pos < chars.len() {
+                        match chars[*pos] {
+                            '\\' if *pos + 1 < chars.len() => {
+                                if first {
+                                    text.push(chars[*pos + 1]);
+                                }
+                                *pos += 2;
+                                continue;
+                            }
+                            ',' => first = false,
+                            '|' if chars.get(*pos + 1) == Some(&'}') => {
+                                *pos += 2;
+                                break;
+                            }
+                            c if first => text.push(c),
+                            _ => {}
+                        }
+                        *pos += 1;
+                    }
+                }
+                // Anything else, e.g. a variable transform, is dropped.
+                _ => {
+                    while *pos < chars.len() && chars[*pos] != '}' {
+                        *pos += 1;
+                    }
+                    *pos += 1;
+                }
+            }
+        }
+        _ => text.push('$'),
+    }
+}
+
+/// Finds the fenced code blocks of markdown lines that have a language, so that they can be
+/// highlighted with the syntax of that language. Regions cover the code, not the fences.
+pub fn markdown_code_regions(lines: &[String]) -> Vec<CodeRegion> {
+    let mut regions = vec![];
+    // The language and first line of the block being read.
+    let mut block: Option<(&str, usize)> = None;
+    for (idx, line) in lines.iter().enumerate() {
+        let line = line.trim_start();
+        if !line.starts_with("```") {
+            continue;
+        }
+
+        match block {
+            Some((filetype, start)) => {
+                if !filetype.is_empty() && idx > start {
+                    regions.push(CodeRegion {
+                        start: start as u64 + 1,
+                        end: idx as u64,
+                        filetype: filetype.to_owned(),
+                    });
+                }
+                block = None;
+            }
+            None => {
+                // Info strings like `rust,ignore` have the language first.
+                let info = line.trim_start_matches('`').trim();
+                let filetype = info.split(|c: char| c == ',' || c.is_whitespace()).next();
+                block = Some((filetype.unwrap_or_default(), idx + 1));
+            }
+        }
+    }
+    regions
+}
+
+/// Renders markdown lines as plain text, for when markdown can't be highlighted. Headings,
+/// emphasis and inline code lose their markers and links show their url after their text. Fenced
+/// code blocks are kept as is, and lines map one to one so that code regions still apply.
+pub fn markdown_to_text(lines: &[String]) -> Vec<String> {
+    let mut in_code_block = false;
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code_block = !in_code_block;
+                return line.clone();
+            }
+
+            if in_code_block {
+                line.clone()
+            } else {
+                markdown_line_to_text(line)
+            }
+        })
+        .collect()
+}
+
+fn markdown_line_to_text(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+    let line = if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+        trimmed[hashes..].trim_start()
+    } else {
+        line
+    };
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut text = String::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        match c {
+            '\\' if chars.get(idx + 1).map_or(false, char::is_ascii_punctuation) => {
+                text.push(chars[idx + 1]);
+                idx += 2;
+            }
+            '`' => match chars[idx + 1..].iter().position(|c| *c == '`') {
+                Some(len) => {
+                    text.extend(&chars[idx + 1..idx + 1 + len]);
+                    idx += len + 2;
+                }
+                None => {
+                    text.push(c);
+                    idx += 1;
+                }
+            },
+            '[' | '!' => match markdown_link(&chars[idx..]) {
+                Some((label, url, len)) => {
+                    text += &format!("{} ({})", markdown_line_to_text(&label), url);
+                    idx += len;
+                }
+                None => {
+                    text.push(c);
+                    idx += 1;
+                }
+            },
+            '*' | '_' => {
+                let run = chars[idx..].iter().take_while(|x| **x == c).count();
+                let prev = if idx > 0 { chars.get(idx - 1) } else { None };
+                let next = chars.get(idx + run);
+                // Emphasis markers touch the text they emphasize, unlike bullets or operators,
+                // and underscores within words are part of them, e.g. in snake_case.
+                let flanking = prev.map_or(false, |c| !c.is_whitespace())
+                    || next.map_or(false, |c| !c.is_whitespace());
+                let intraword = prev.map_or(false, |c| c.is_alphanumeric())
+                    && next.map_or(false, |c| c.is_alphanumeric());
+                if !flanking || (c == '_' && intraword) {
+                    text.extend(&chars[idx..idx + run]);
+                }
+                idx += run;
+            }
+            _ => {
+                text.push(c);
+                idx += 1;
+            }
+        }
+    }
+    text
+}
+
+/// Parses the link or image at the start of `chars`, e.g. `[text](url)`, into its text, its url
+/// and its length.
+fn markdown_link(chars: &[char]) -> Option<(String, String, usize)> {
+    let start = if chars.first() == Some(&'!') { 1 } else { 0 };
+    if chars.get(start) != Some(&'[') {
+        return None;
+    }
+
+    let close = start + chars[start..].iter().position(|c| *c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 1 + chars[close + 1..].iter().position(|c| *c == ')')?;
+
+    let label = chars[start + 1..close].iter().collect();
+    let url = chars[close + 2..end].iter().collect();
+    Some((label, url, end + 1))
+}
+
+/// The number of screen cells a character takes: two for wide characters, such as CJK ideographs
+/// and emoji, and one for the others.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F680..=0x1F6FF
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Truncates a message to at most `width` screen cells, the way Vim's `strdisplaywidth()` counts
+/// them, ending it with `...` if it was truncated. The message is cut between characters, so
+/// multibyte characters are never split.
+pub fn ellipsis(message: &str, width: usize) -> String {
+    if message.chars().map(char_width).sum::<usize>() <= width {
+        return message.to_owned();
+    }
+
+    let available = width.saturating_sub(3);
+    let mut used = 0;
+    let end = message
+        .char_indices()
+        .find(|(_, c)| {
+            used += char_width(*c);
+            used > available
+        })
+        .map_or(message.len(), |(idx, _)| idx);
+    format!("{}...", &message[..end])
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -769,4 +1266,292 @@ mod test {
         assert_eq!(convert_to_vim_str("xyz'''ffff"), "'xyz''''''ffff'");
         assert_eq!(convert_to_vim_str("'''"), "''''''''");
     }
+
+    #[test]
+    fn test_substitute_command_placeholders() {
+        assert_eq!(
+            substitute_command_placeholders("--root={root}", "/src/foo", "/src/foo/a.rs", "rust"),
+            "--root=/src/foo"
+        );
+        assert_eq!(
+            substitute_command_placeholders(
+                "{languageId}:{filename}",
+                "/src/foo",
+                "/src/foo/a.rs",
+                "rust"
+            ),
+            "rust:/src/foo/a.rs"
+        );
+        assert_eq!(
+            substitute_command_placeholders("{other}", "/src/foo", "/src/foo/a.rs", "rust"),
+            "{other}"
+        );
+    }
+
+    #[test]
+    fn test_strip_snippet_placeholders() {
+        assert_eq!(strip_snippet_placeholders("foo"), "foo");
+        assert_eq!(strip_snippet_placeholders("foo($1)$0"), "foo()");
+        assert_eq!(
+            strip_snippet_placeholders("foo(${1:bar}, ${2:baz})"),
+            "foo(bar, baz)"
+        );
+        assert_eq!(strip_snippet_placeholders("${1:a ${2:b}} c"), "a b c");
+        assert_eq!(strip_snippet_placeholders("${1|one,two|}"), "one");
+        assert_eq!(strip_snippet_placeholders("${TM_FILENAME:x}$TM_LINE"), "x");
+        assert_eq!(strip_snippet_placeholders("${1}${2:}"), "");
+        assert_eq!(strip_snippet_placeholders("\\$1 \\} $ x"), "$1 } $ x");
+        assert_eq!(strip_snippet_placeholders("${1:{\\}}"), "{}");
+    }
+
+    #[test]
+    fn test_apply_completion_item_defaults() {
+        let range = json!({
+            "start": { "line": 0, "character": 4 },
+            "end": { "line": 0, "character": 6 },
+        });
+        let mut result = json!({
+            "isIncomplete": false,
+            "itemDefaults": {
+                "editRange": range,
+                "insertTextFormat": 2,
+            },
+            "items": [
+                { "label": "foo" },
+                { "label": "bar", "textEditText": "bar()", "insertTextFormat": 1 },
+                {
+                    "label": "baz",
+                    "textEdit": { "newText": "baz", "range": range },
+                },
+            ],
+        });
+
+        apply_completion_item_defaults(&mut result);
+        assert_eq!(
+            result["items"],
+            json!([
+                {
+                    "label": "foo",
+                    "insertTextFormat": 2,
+                    "textEdit": { "newText": "foo", "range": range },
+                },
+                {
+                    "label": "bar",
+                    "textEditText": "bar()",
+                    "insertTextFormat": 1,
+                    "textEdit": { "newText": "bar()", "range": range },
+                },
+                {
+                    "label": "baz",
+                    "insertTextFormat": 2,
+                    "textEdit": { "newText": "baz", "range": range },
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply_completion_item_defaults_insert_replace() {
+        let insert = json!({
+            "start": { "line": 0, "character": 4 },
+            "end": { "line": 0, "character": 6 },
+        });
+        let replace = json!({
+            "start": { "line": 0, "character": 4 },
+            "end": { "line": 0, "character": 9 },
+        });
+        let mut result = json!({
+            "isIncomplete": false,
+            "itemDefaults": {
+                "editRange": { "insert": insert, "replace": replace },
+            },
+            "items": [{ "label": "foo" }],
+        });
+
+        apply_completion_item_defaults(&mut result);
+        assert_eq!(
+            result["items"],
+            json!([{
+                "label": "foo",
+                "textEdit": { "newText": "foo", "insert": insert, "replace": replace },
+            }])
+        );
+    }
+
+    #[test]
+    fn test_position_encoding_conversions() {
+        // "é" is 2 bytes and 1 UTF-16 unit, "😀" is 4 bytes and 2 UTF-16 units.
+        let line = "aé😀b";
+        assert_eq!(character_to_byte(line, 2, PositionEncoding::Utf16), 3);
+        assert_eq!(character_to_byte(line, 4, PositionEncoding::Utf16), 7);
+        assert_eq!(character_to_byte(line, 3, PositionEncoding::Utf32), 7);
+        assert_eq!(character_to_byte(line, 3, PositionEncoding::Utf8), 3);
+        assert_eq!(character_to_byte(line, 10, PositionEncoding::Utf16), 8);
+
+        assert_eq!(byte_to_character(line, 3, PositionEncoding::Utf16), 2);
+        assert_eq!(byte_to_character(line, 7, PositionEncoding::Utf16), 4);
+        assert_eq!(byte_to_character(line, 7, PositionEncoding::Utf32), 3);
+        assert_eq!(byte_to_character(line, 7, PositionEncoding::Utf8), 7);
+
+        let lines = vec!["x".to_owned(), line.to_owned()];
+        let range = Range::new(Position::new(1, 2), Position::new(1, 4));
+        let bytes = range_to_bytes(&lines, &range, PositionEncoding::Utf16);
+        assert_eq!(bytes, Range::new(Position::new(1, 3), Position::new(1, 7)));
+        assert_eq!(
+            range_from_bytes(&lines, &bytes, PositionEncoding::Utf16),
+            range
+        );
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        assert_eq!(ellipsis("short", 10), "short");
+        assert_eq!(ellipsis("exactly10!", 10), "exactly10!");
+        assert_eq!(ellipsis("a bit too long", 10), "a bit t...");
+        assert_eq!(ellipsis("abc", 0), "...");
+    }
+
+    #[test]
+    fn test_ellipsis_multibyte() {
+        assert_eq!(ellipsis("héllo wörld", 11), "héllo wörld");
+        assert_eq!(ellipsis("héllo wörld", 8), "héllo...");
+        assert_eq!(ellipsis("变量未使用的警告", 16), "变量未使用的警告");
+        assert_eq!(ellipsis("变量未使用的警告", 7), "变量...");
+        assert_eq!(ellipsis("变量未使用的警告", 6), "变...");
+        assert_eq!(ellipsis("😀😀😀😀😀", 5), "😀...");
+        assert_eq!(ellipsis("😀😀😀😀😀", 4), "...");
+    }
+
+    #[test]
+    fn test_markdown_code_regions() {
+        let lines: Vec<String> = vec![
+            "```rust",
+            "fn foo() -> u32",
+            "```",
+            "---",
+            "Does foo.",
+            "```",
+            "plain",
+            "```",
+            "  ```python",
+            "x = 1",
+            "y = 2",
+            "  ```",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        assert_eq!(
+            markdown_code_regions(&lines),
+            vec![
+                CodeRegion {
+                    start: 2,
+                    end: 2,
+                    filetype: "rust".into(),
+                },
+                CodeRegion {
+                    start: 10,
+                    end: 11,
+                    filetype: "python".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adjust_indentation() {
+        assert_eq!(adjust_indentation("foo()", "    "), "foo()");
+        assert_eq!(
+            adjust_indentation("if x {\n    y\n\n}", "\t"),
+            "if x {\n\t    y\n\n\t}"
+        );
+        assert_eq!(adjust_indentation("a\nb", ""), "a\nb");
+    }
+
+    #[test]
+    fn test_completion_label_details() {
+        let details = CompletionItemLabelDetails {
+            detail: Some("(x: u32)".into()),
+            description: Some("std::foo".into()),
+        };
+        let items = json!([
+            { "label": "foo", "labelDetails": { "detail": "(x: u32)", "description": "std::foo" } },
+            { "label": "bar" },
+        ]);
+        assert_eq!(
+            completion_label_details(&items),
+            vec![Some(details.clone()), None]
+        );
+        assert_eq!(
+            completion_label_details(&json!({ "isIncomplete": false, "items": items })),
+            vec![Some(details), None]
+        );
+        assert!(completion_label_details(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn test_completion_items_order() {
+        let item = |label: &str, sort_text: Option<&str>, preselect: Option<bool>| CompletionItem {
+            label: label.into(),
+            sort_text: sort_text.map(Into::into),
+            preselect,
+            ..CompletionItem::default()
+        };
+        let items = vec![
+            item("c", None, None),
+            item("b", Some("2"), None),
+            item("a", Some("3"), None),
+            item("d", None, Some(true)),
+            item("a", Some("1"), Some(false)),
+        ];
+
+        assert_eq!(completion_items_order(&items), vec![3, 4, 1, 2, 0]);
+        assert!(completion_items_order(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_completion_item_kind_abbr() {
+        assert_eq!(
+            completion_item_kind_abbr(CompletionItemKind::Function),
+            "fn"
+        );
+        assert_eq!(
+            completion_item_kind_abbr(CompletionItemKind::EnumMember),
+            "enum"
+        );
+        assert_eq!(
+            completion_item_kind_abbr(CompletionItemKind::TypeParameter),
+            "type"
+        );
+    }
+
+    #[test]
+    fn test_markdown_to_text() {
+        let lines: Vec<String> = vec![
+            "# Heading",
+            "Some **bold**, *emphasized* and `inline_code` text.",
+            "See [the docs](https://example.com/docs) for snake_case and 2 * 3.",
+            "* a bullet with an escaped \\*",
+            "```rust",
+            "let **x** = [a](b);",
+            "```",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        assert_eq!(
+            markdown_to_text(&lines),
+            vec![
+                "Heading",
+                "Some bold, emphasized and inline_code text.",
+                "See the docs (https://example.com/docs) for snake_case and 2 * 3.",
+                "* a bullet with an escaped *",
+                "```rust",
+                "let **x** = [a](b);",
+                "```",
+            ]
+        );
+    }
 }
@@ -1,18 +1,34 @@
 use crate::{
     rpcclient::RpcClient,
     sign::Sign,
-    types::{Bufnr, QuickfixEntry, VimExp, VirtualText},
-    utils::Canonicalize,
+    types::{Bufnr, QuickfixEntry, VimExp, VirtualText, UNTITLED_PREFIX, VIM_BUFFER_DISABLED},
+    utils::{ellipsis, Canonicalize},
     viewport::Viewport,
 };
 use anyhow::Result;
 use jsonrpc_core::Value;
 use log::*;
-use lsp_types::Position;
+use lsp_types::{Position, Range};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 use std::{path::Path, sync::Arc};
 
+/// Buffer argument for the vim functions taking one: the buffer number for the names given to
+/// unnamed buffers, which vim can't look buffers up by, and the name itself otherwise.
+pub fn bufexpr(bufname: &str) -> Value {
+    match untitled_bufnr(bufname) {
+        Some(bufnr) => json!(bufnr),
+        None => json!(bufname),
+    }
+}
+
+/// Number of the unnamed buffer given the name, e.g. `untitled:Untitled-3`.
+fn untitled_bufnr(bufname: &str) -> Option<Bufnr> {
+    bufname
+        .strip_prefix(UNTITLED_PREFIX)
+        .and_then(|bufnr| bufnr.parse().ok())
+}
+
 /// Try get value of an variable from RPC params.
 pub fn try_get<'a, R: Deserialize<'a>>(key: &str, params: &'a Value) -> Result<Option<R>> {
     let value = &params[key];
@@ -29,7 +45,7 @@ pub struct HighlightSource {
     pub source: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Highlight {
     pub line: u64,
     pub character_start: u64,
@@ -107,7 +123,17 @@ impl Vim {
     /// Function wrappers.
 
     pub fn getbufvar<R: DeserializeOwned>(&self, bufname: &str, var: &str) -> Result<R> {
-        self.rpcclient.call("getbufvar", json!([bufname, var]))
+        self.rpcclient
+            .call("getbufvar", json!([bufexpr(bufname), var]))
+    }
+
+    /// Whether the plugin was disabled for the given buffer with LanguageClient#disableBuffer().
+    pub fn is_buffer_disabled(&self, bufname: &str) -> Result<bool> {
+        let disabled: u8 = self.rpcclient.call(
+            "getbufvar",
+            json!([bufexpr(bufname), VIM_BUFFER_DISABLED, 0]),
+        )?;
+        Ok(disabled == 1)
     }
 
     pub fn get_filename(&self, params: &Value) -> Result<String> {
@@ -128,6 +154,10 @@ impl Vim {
     pub fn get_bufnr(&self, filename: &str, params: &Value) -> Result<Bufnr> {
         let key = "bufnr";
 
+        if let Some(bufnr) = untitled_bufnr(filename) {
+            return Ok(bufnr);
+        }
+
         try_get(key, params)?.map_or_else(|| self.eval(format!("bufnr('{}')", filename)), Ok)
     }
 
@@ -170,7 +200,7 @@ impl Vim {
     }
 
     pub fn get_text(&self, bufname: &str) -> Result<Vec<String>> {
-        self.rpcclient.call("LSP#text", json!([bufname]))
+        self.rpcclient.call("LSP#text", json!([bufexpr(bufname)]))
     }
 
     pub fn get_handle(&self, params: &Value) -> Result<bool> {
@@ -183,13 +213,24 @@ impl Vim {
         self.rpcclient.notify("s:Echo", message.as_ref())
     }
 
-    pub fn echo_ellipsis(&self, message: impl AsRef<str>) -> Result<()> {
+    /// Gets the number of columns a message can take without triggering |hit-enter|, leaving
+    /// `reserve` columns free.
+    pub fn get_echo_width(&self, reserve: usize) -> Result<usize> {
+        let width: i64 =
+            self.eval("&columns * &cmdheight - (&showcmd ? 11 : 0) - (&ruler ? 18 : 0)")?;
+        Ok((width.max(0) as usize).saturating_sub(reserve))
+    }
+
+    pub fn echo_ellipsis(&self, message: impl AsRef<str>, reserve: usize) -> Result<()> {
         let message = message.as_ref().lines().collect::<Vec<_>>().join(" ");
-        self.rpcclient.notify("s:EchoEllipsis", message)
+        let message = ellipsis(&message, self.get_echo_width(reserve)?);
+        self.rpcclient.notify("s:Echo", message)
     }
 
-    pub fn echomsg_ellipsis(&self, message: impl AsRef<str>) -> Result<()> {
+    pub fn echomsg_ellipsis(&self, message: impl AsRef<str>, reserve: usize) -> Result<()> {
         let message = message.as_ref().lines().collect::<Vec<_>>().join(" ");
+        // Leave room for the `[LC] ` prefix.
+        let message = ellipsis(&message, self.get_echo_width(reserve + 5)?);
         self.rpcclient.notify("s:EchomsgEllipsis", message)
     }
 
@@ -238,6 +279,19 @@ impl Vim {
         Ok(())
     }
 
+    /// Replaces the items of the quickfix (or location) list with the given id, creating a new list
+    /// if it no longer exists. Returns the id of the list that was populated.
+    pub fn set_list_by_id(
+        &self,
+        loclist: bool,
+        list: &[QuickfixEntry],
+        title: &str,
+        id: u64,
+    ) -> Result<u64> {
+        self.rpcclient
+            .call("s:SetListById", json!([loclist, list, title, id]))
+    }
+
     /// clears all highlights in the current buffer.
     pub fn clear_highlights(&self, namespace: &str) -> Result<()> {
         self.rpcclient
@@ -272,7 +326,12 @@ impl Vim {
         )
     }
 
+    pub fn select_range(&self, range: &Range) -> Result<()> {
+        self.rpcclient.notify("s:SelectRange", json!([range]))
+    }
+
     pub fn set_signs(&self, filename: &str, signs: &[Sign]) -> Result<i8> {
-        self.rpcclient.call("s:set_signs", json!([filename, signs]))
+        self.rpcclient
+            .call("s:set_signs", json!([bufexpr(filename), signs]))
     }
 }